@@ -0,0 +1,245 @@
+use std::fmt;
+
+use camino::Utf8Path;
+use colored::Colorize;
+use indexmap::IndexMap;
+use ltk_meta::value::{EmbeddedValue, UnorderedContainerValue};
+use ltk_meta::{BinProperty, BinTree, PropertyValueEnum};
+use ltk_ritobin::{HashMapProvider, HashProvider};
+use miette::Result;
+
+use crate::utils::bin_io::{self, FallbackProvider};
+use crate::utils::config::load_or_create_config;
+use crate::utils::hashtable::LazyHashProvider;
+use crate::utils::ritobin_text::names_from_text;
+use crate::utils::schema::Schema;
+use crate::utils::tree_diff::ValueFormatter;
+use crate::utils::{format_hash, hyperlink_path};
+
+/// File extensions that can be analyzed
+pub(crate) const SUPPORTED_EXTENSIONS: &[&str] = &["bin", "py", "ritobin"];
+
+/// Lists longer than this are reported unless `--max-items` says otherwise
+pub const DEFAULT_MAX_ITEMS: usize = 10_000;
+
+/// How likely a finding is to be an actual problem
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    /// Often intended, worth a look
+    Info,
+    /// Parses fine but is unlikely to work in game
+    Warning,
+    /// Breaks the schema the file is checked against
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Pad before coloring, escape codes would count towards the width
+        let label = match self {
+            Severity::Info => format!("{:<7}", "info").cyan(),
+            Severity::Warning => format!("{:<7}", "warning").yellow(),
+            Severity::Error => format!("{:<7}", "error").red(),
+        };
+        write!(f, "{}", label)
+    }
+}
+
+/// One problem found in a tree
+struct Finding {
+    severity: Severity,
+    /// Property path in `set` syntax, e.g. `Characters/Ahri.skinScale`
+    path: String,
+    message: String,
+}
+
+/// Run heuristic lint rules over a .bin or ritobin text file and report what they find:
+/// empty lists and maps, null links and links to entries the file doesn't have, embeds
+/// without a type or fields, lists with more than `max_items` items, and with a `schema`,
+/// values that don't match it. Fails if any finding is an error.
+pub fn analyze(input: String, schema: Option<&Schema>, max_items: usize) -> Result<()> {
+    let input_path = Utf8Path::new(&input);
+    let extension = input_path.extension().unwrap_or("");
+    if !SUPPORTED_EXTENSIONS.contains(&extension) {
+        return Err(miette::miette!(
+            "Unsupported file extension: {}. Supported extensions: .bin, .py, .ritobin",
+            input_path
+        ));
+    }
+
+    let (tree, text_names) = if extension == "bin" {
+        (
+            bin_io::read_bin_file(input_path)?,
+            HashMapProvider::default(),
+        )
+    } else {
+        let text = bin_io::read_text_file(input_path)?;
+        (bin_io::parse_text(&text)?, names_from_text(&text))
+    };
+
+    let (config, _) = load_or_create_config()?;
+    let hashes = LazyHashProvider::new(config.hashtable_dir, config.combined_hashtable);
+    let names = FallbackProvider {
+        primary: hashes.get()?,
+        fallback: text_names,
+    };
+
+    let mut analyzer = Analyzer {
+        tree: &tree,
+        names: &names,
+        max_items,
+        findings: Vec::new(),
+    };
+    for object in tree.objects.values() {
+        let path = ValueFormatter::new(&names).entry_path(object.path_hash);
+        analyzer.check_fields(&path, &object.properties);
+    }
+    let mut findings = analyzer.findings;
+    if let Some(schema) = schema {
+        findings.extend(
+            schema
+                .check(&tree, &names)
+                .into_iter()
+                .map(|violation| Finding {
+                    severity: Severity::Error,
+                    path: violation.path,
+                    message: violation.message,
+                }),
+        );
+    }
+
+    if findings.is_empty() {
+        tracing::info!("No problems found in {}", hyperlink_path(input_path));
+        return Ok(());
+    }
+
+    println!();
+    println!("{}", hyperlink_path(input_path).bold());
+    for finding in &findings {
+        println!(
+            "  {} {}: {}",
+            finding.severity, finding.path, finding.message
+        );
+    }
+    println!();
+
+    let count = |severity| {
+        findings
+            .iter()
+            .filter(|finding| finding.severity == severity)
+            .count()
+    };
+    let errors = count(Severity::Error);
+    tracing::info!(
+        "Analysis complete: {} error(s), {} warning(s), {} info",
+        errors,
+        count(Severity::Warning),
+        count(Severity::Info)
+    );
+    if errors > 0 {
+        return Err(miette::miette!("{} error(s) in {}", errors, input_path));
+    }
+    Ok(())
+}
+
+struct Analyzer<'a, H> {
+    tree: &'a BinTree,
+    names: &'a H,
+    max_items: usize,
+    findings: Vec<Finding>,
+}
+
+impl<H: HashProvider> Analyzer<'_, H> {
+    fn report(&mut self, severity: Severity, path: &str, message: String) {
+        self.findings.push(Finding {
+            severity,
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    fn type_name(&self, hash: u32) -> String {
+        self.names
+            .lookup_type(hash)
+            .map(str::to_string)
+            .unwrap_or_else(|| format_hash(hash))
+    }
+
+    fn check_fields(&mut self, path: &str, properties: &IndexMap<u32, BinProperty>) {
+        for property in properties.values() {
+            let field = self
+                .names
+                .lookup_field(property.name_hash)
+                .map(str::to_string)
+                .unwrap_or_else(|| format_hash(property.name_hash));
+            self.check_value(&format!("{}.{}", path, field), &property.value);
+        }
+    }
+
+    fn check_value(&mut self, path: &str, value: &PropertyValueEnum) {
+        match value {
+            PropertyValueEnum::ObjectLink(link) if link.0 == 0 => {
+                self.report(Severity::Warning, path, "link is null".to_string());
+            }
+            PropertyValueEnum::ObjectLink(link) if !self.tree.objects.contains_key(&link.0) => {
+                // The entry may well be in one of the bins this one depends on
+                let severity = if self.tree.dependencies.is_empty() {
+                    Severity::Warning
+                } else {
+                    Severity::Info
+                };
+                let target = self
+                    .names
+                    .lookup_entry(link.0)
+                    .map(|name| format!("{:?}", name))
+                    .unwrap_or_else(|| format_hash(link.0));
+                self.report(
+                    severity,
+                    path,
+                    format!("links to {}, which is not an entry of this file", target),
+                );
+            }
+            PropertyValueEnum::Embedded(EmbeddedValue(value)) => {
+                if value.class_hash == 0 {
+                    self.report(Severity::Warning, path, "embed has no type".to_string());
+                } else if value.properties.is_empty() {
+                    let message = format!(
+                        "embedded {} has no fields",
+                        self.type_name(value.class_hash)
+                    );
+                    self.report(Severity::Info, path, message);
+                }
+                self.check_fields(path, &value.properties);
+            }
+            PropertyValueEnum::Struct(value) => self.check_fields(path, &value.properties),
+            PropertyValueEnum::Container(container)
+            | PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)) => {
+                let len = container.items.len();
+                if len == 0 {
+                    self.report(Severity::Info, path, "list is empty".to_string());
+                } else if len > self.max_items {
+                    let message = format!("list has {} items (more than {})", len, self.max_items);
+                    self.report(Severity::Warning, path, message);
+                }
+                for (index, item) in container.items.iter().enumerate() {
+                    self.check_value(&format!("{}[{}]", path, index), item);
+                }
+            }
+            PropertyValueEnum::Map(map) => {
+                if map.entries.is_empty() {
+                    self.report(Severity::Info, path, "map is empty".to_string());
+                }
+                for (key, value) in &map.entries {
+                    let value_path = ValueFormatter::new(self.names).map_value_path(path, &key.0);
+                    self.check_value(&value_path, value);
+                }
+            }
+            PropertyValueEnum::Optional(optional) => {
+                if let Some(value) = optional.value.as_deref() {
+                    self.check_value(path, value);
+                }
+            }
+            _ => {}
+        }
+    }
+}
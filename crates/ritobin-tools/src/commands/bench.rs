@@ -0,0 +1,173 @@
+use std::fs;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use ltk_meta::BinTree;
+use ltk_ritobin::HashMapProvider;
+use miette::{IntoDiagnostic, Result, WrapErr};
+
+use crate::commands::convert::{directory_paths, glob_paths, is_glob_pattern};
+use crate::utils::bin_io;
+use crate::utils::config::load_or_create_config;
+use crate::utils::hashtable::LazyHashProvider;
+
+/// File extensions that can be benchmarked
+pub(crate) const SUPPORTED_EXTENSIONS: &[&str] = &["bin", "py", "ritobin"];
+
+/// Timed passes over the inputs unless `--iterations` says otherwise
+pub const DEFAULT_ITERATIONS: u32 = 10;
+
+/// An input read into memory, so disk speed doesn't end up in the timings
+enum Source {
+    Bin(Vec<u8>),
+    Text(String),
+}
+
+impl Source {
+    fn parse(&self) -> Result<BinTree> {
+        match self {
+            Source::Bin(bytes) => bin_io::read_bin_bytes(bytes),
+            Source::Text(text) => bin_io::parse_text(text),
+        }
+    }
+
+    /// Convert to the other format, returning the output size
+    fn serialize(&self, tree: &BinTree, hashes: Option<&HashMapProvider>) -> Result<usize> {
+        match self {
+            Source::Bin(_) => Ok(bin_io::tree_to_text(tree, hashes)?.len()),
+            Source::Text(_) => Ok(bin_io::tree_to_bytes(tree)?.len()),
+        }
+    }
+}
+
+/// Time conversions of a file, or of the files of a directory (searched recursively with
+/// `recursive`) or glob pattern, without writing anything. Every input is read into memory
+/// and converted once untimed to warm up, then `iterations` more times: .bin to ritobin
+/// text and text to .bin, like `convert` would. Logs the mean, median and 95th percentile
+/// parse and serialize time per file and the files converted per second.
+pub fn bench(input: String, recursive: bool, iterations: u32) -> Result<()> {
+    let paths = bench_paths(&input, recursive)?;
+    if paths.is_empty() {
+        return Err(miette::miette!(
+            "No .bin, .py or .ritobin files found in {}",
+            input
+        ));
+    }
+
+    let sources = paths
+        .iter()
+        .map(|path| {
+            let source = if path.extension() == Some("bin") {
+                Source::Bin(
+                    fs::read(path)
+                        .into_diagnostic()
+                        .wrap_err_with(|| format!("Failed to read file: {}", path))?,
+                )
+            } else {
+                Source::Text(bin_io::read_text_file(path)?)
+            };
+            Ok((path, source))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let (config, _) = load_or_create_config()?;
+    let provider = LazyHashProvider::new(config.hashtable_dir, config.combined_hashtable);
+    // Loaded up front, only the conversions are timed
+    let hashes = if sources
+        .iter()
+        .any(|(_, source)| matches!(source, Source::Bin(_)))
+    {
+        provider.get()?
+    } else {
+        None
+    };
+
+    tracing::info!(
+        "Benchmarking {} file(s), 1 warmup and {} timed iteration(s)",
+        sources.len(),
+        iterations
+    );
+    for (path, source) in &sources {
+        let tree = source
+            .parse()
+            .wrap_err_with(|| format!("Failed to parse {}", path))?;
+        source
+            .serialize(&tree, hashes)
+            .wrap_err_with(|| format!("Failed to convert {}", path))?;
+    }
+
+    let mut parse_times = Vec::with_capacity(sources.len() * iterations as usize);
+    let mut serialize_times = Vec::with_capacity(parse_times.capacity());
+    for _ in 0..iterations {
+        for (_, source) in &sources {
+            let start = Instant::now();
+            let tree = black_box(source.parse()?);
+            let parsed = Instant::now();
+            black_box(source.serialize(&tree, hashes)?);
+            parse_times.push(parsed - start);
+            serialize_times.push(parsed.elapsed());
+        }
+    }
+
+    let total: Duration = parse_times.iter().chain(&serialize_times).sum();
+    log_stats("Parse", &mut parse_times);
+    log_stats("Serialize", &mut serialize_times);
+    tracing::info!(
+        "Throughput: {:.1} files/s ({} conversions in {})",
+        parse_times.len() as f64 / total.as_secs_f64(),
+        parse_times.len(),
+        format_duration(total)
+    );
+    Ok(())
+}
+
+/// The supported files `input` names, in a stable order so runs compare
+fn bench_paths(input: &str, recursive: bool) -> Result<Vec<Utf8PathBuf>> {
+    let input_path = Utf8Path::new(input);
+    let mut paths = if input_path.is_dir() {
+        directory_paths(input_path, (!recursive).then_some(1), false, false)
+    } else if !input_path.exists() && is_glob_pattern(input) {
+        glob_paths(input, false)?
+    } else if is_supported(input_path) {
+        return Ok(vec![input_path.to_path_buf()]);
+    } else {
+        return Err(miette::miette!(
+            "Unsupported file extension: {}. Supported extensions: .bin, .py, .ritobin",
+            input_path
+        ));
+    };
+    paths.retain(|path| path.is_file() && is_supported(path));
+    paths.sort();
+    Ok(paths)
+}
+
+fn is_supported(path: &Utf8Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| SUPPORTED_EXTENSIONS.contains(&ext))
+}
+
+/// Log the mean, median and 95th percentile of `times`
+fn log_stats(label: &str, times: &mut [Duration]) {
+    times.sort();
+    let mean = times.iter().sum::<Duration>() / times.len() as u32;
+    let middle = times.len() / 2;
+    let median = if times.len().is_multiple_of(2) {
+        (times[middle - 1] + times[middle]) / 2
+    } else {
+        times[middle]
+    };
+    // Nearest rank: the smallest time at least 95% of the times are below or equal to
+    let p95 = times[(times.len() * 95).div_ceil(100) - 1];
+    tracing::info!(
+        "{:<9} mean {}, median {}, p95 {}",
+        label,
+        format_duration(mean),
+        format_duration(median),
+        format_duration(p95)
+    );
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,28 @@
+use miette::Result;
+
+use crate::utils::config::load_or_create_config;
+use crate::utils::hashtable;
+use crate::utils::hyperlink_path;
+
+/// Build a binary cache from the text hashtables in the configured hashtable directory.
+///
+/// Conversions prefer the cache over the text files as long as it is newer than all of them,
+/// so re-running this after `download-hashes` keeps it in sync.
+pub fn build_hash_cache() -> Result<()> {
+    let (config, _) = load_or_create_config()?;
+
+    let hashtable_dir = config
+        .hashtable_dir
+        .ok_or_else(|| miette::miette!("No hashtable directory configured"))?;
+
+    let (cache_path, count) = hashtable::build_hash_cache(&hashtable_dir)?;
+    if count == 0 {
+        tracing::warn!(
+            "No hashes found in {}, run `download-hashes` first",
+            hyperlink_path(&hashtable_dir)
+        );
+    }
+
+    tracing::info!("Cached {} hashes to {}", count, hyperlink_path(&cache_path));
+    Ok(())
+}
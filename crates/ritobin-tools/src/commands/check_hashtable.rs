@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fs;
+
+use camino::Utf8Path;
+use colored::Colorize;
+use ltk_hash::fnv1a::hash_lower;
+use miette::{IntoDiagnostic, Result, WrapErr};
+
+use crate::utils::config::load_or_create_config;
+use crate::utils::hashtable::HASHTABLE_FILES;
+use crate::utils::hyperlink_path;
+
+/// Problems listed per file before the rest are summarized
+const MAX_PROBLEMS_SHOWN: usize = 20;
+
+/// A malformed or suspicious hashtable line
+#[derive(Debug)]
+struct Problem {
+    line: usize,
+    message: String,
+}
+
+/// Validate the text hashtables in the configured hashtable directory.
+///
+/// Every `hashes.*.txt` line must be `<hex hash> <name>`. Lines that don't parse are
+/// skipped by the loader, so they're reported here along with hashes mapped to several
+/// names and names that don't hash to their listed value.
+pub fn check_hashtable() -> Result<()> {
+    let (config, _) = load_or_create_config()?;
+
+    let hashtable_dir = config
+        .hashtable_dir
+        .ok_or_else(|| miette::miette!("No hashtable directory configured"))?;
+
+    println!();
+    println!(
+        "  {} {}",
+        "hashtable_dir:".bright_white(),
+        hyperlink_path(&hashtable_dir)
+    );
+    println!();
+
+    let mut problem_count = 0;
+    for file in HASHTABLE_FILES {
+        let path = hashtable_dir.join(file);
+        if !path.exists() {
+            println!("  {} {}", file.bright_white(), "missing".bright_yellow());
+            continue;
+        }
+
+        let (line_count, problems) = check_file(&path)?;
+        problem_count += problems.len();
+
+        if problems.is_empty() {
+            println!(
+                "  {} {} lines {}",
+                file.bright_white(),
+                line_count,
+                "✓".bright_green()
+            );
+            continue;
+        }
+
+        println!(
+            "  {} {} lines, {} {}",
+            file.bright_white(),
+            line_count,
+            format!("{} problem(s)", problems.len()).bright_red(),
+            "✗".bright_red()
+        );
+        for problem in problems.iter().take(MAX_PROBLEMS_SHOWN) {
+            println!(
+                "    {} {}",
+                format!("line {}:", problem.line).bright_black(),
+                problem.message
+            );
+        }
+        if problems.len() > MAX_PROBLEMS_SHOWN {
+            println!(
+                "    {}",
+                format!("... and {} more", problems.len() - MAX_PROBLEMS_SHOWN).bright_black()
+            );
+        }
+    }
+    println!();
+
+    if problem_count > 0 {
+        Err(miette::miette!(
+            "Found {} problem(s) in the hashtables, re-run `download-hashes` to replace corrupted files",
+            problem_count
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check every line of a hashtable file, returning the line count and problems found
+fn check_file(path: &Utf8Path) -> Result<(usize, Vec<Problem>)> {
+    let bytes = fs::read(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read hashtable: {}", path))?;
+    Ok(check_lines(&bytes))
+}
+
+/// Check every line of a hashtable's contents, returning the line count and problems found
+fn check_lines(bytes: &[u8]) -> (usize, Vec<Problem>) {
+    let mut problems = Vec::new();
+    // hash -> (name, line it was first defined on)
+    let mut seen: HashMap<u32, (String, usize)> = HashMap::new();
+    let mut line_count = 0;
+
+    for (index, raw_line) in bytes.split(|&b| b == b'\n').enumerate() {
+        let line_number = index + 1;
+        line_count = line_number;
+
+        let Ok(line) = std::str::from_utf8(raw_line) else {
+            problems.push(Problem {
+                line: line_number,
+                message: "invalid UTF-8".to_string(),
+            });
+            continue;
+        };
+
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((hash_str, name)) = line.split_once(' ') else {
+            problems.push(Problem {
+                line: line_number,
+                message: format!("expected '<hash> <name>', got '{}'", line),
+            });
+            continue;
+        };
+
+        let hash = match u32::from_str_radix(hash_str, 16) {
+            Ok(hash) if !hash_str.starts_with('+') => hash,
+            _ => {
+                problems.push(Problem {
+                    line: line_number,
+                    message: format!("invalid hash '{}', expected up to 8 hex digits", hash_str),
+                });
+                continue;
+            }
+        };
+
+        if name.is_empty() {
+            problems.push(Problem {
+                line: line_number,
+                message: format!("hash {} has no name", hash_str),
+            });
+            continue;
+        }
+
+        if hash_lower(name) != hash {
+            problems.push(Problem {
+                line: line_number,
+                message: format!("'{}' does not hash to {}", name, hash_str),
+            });
+        }
+
+        match seen.get(&hash) {
+            Some((first_name, first_line)) if first_name != name => {
+                problems.push(Problem {
+                    line: line_number,
+                    message: format!(
+                        "hash {} maps to '{}', but to '{}' on line {}",
+                        hash_str, name, first_name, first_line
+                    ),
+                });
+            }
+            Some(_) => {}
+            None => {
+                seen.insert(hash, (name.to_string(), line_number));
+            }
+        }
+    }
+
+    // A trailing newline produces one empty final segment that isn't a line
+    if bytes.ends_with(b"\n") {
+        line_count -= 1;
+    }
+
+    (line_count, problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(name: &str) -> String {
+        format!("{:08x} {}\n", hash_lower(name), name)
+    }
+
+    #[test]
+    fn clean_file_has_no_problems() {
+        let text = format!("{}{}", line("championSkinName"), line("skinScale"));
+        let (line_count, problems) = check_lines(text.as_bytes());
+        assert_eq!(line_count, 2);
+        assert!(problems.is_empty(), "{:?}", problems);
+    }
+
+    #[test]
+    fn malformed_line_is_reported() {
+        let text = format!("{}not-a-hashtable-line\n", line("skinScale"));
+        let (_, problems) = check_lines(text.as_bytes());
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].line, 2);
+        assert!(problems[0].message.starts_with("expected '<hash> <name>'"));
+    }
+
+    #[test]
+    fn duplicate_hash_is_reported() {
+        let hash = hash_lower("skinScale");
+        let text = format!("{}{:08x} otherName\n", line("skinScale"), hash);
+        let (_, problems) = check_lines(text.as_bytes());
+        assert!(
+            problems.iter().any(|problem| problem.line == 2
+                && problem.message.contains("but to 'skinScale' on line 1")),
+            "{:?}",
+            problems
+        );
+    }
+}
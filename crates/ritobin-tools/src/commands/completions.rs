@@ -0,0 +1,37 @@
+use std::io::{self, Write};
+
+use clap::CommandFactory;
+use clap_complete::Shell;
+use miette::Result;
+
+use crate::Args;
+
+/// Print a completion script for `shell` to stdout
+pub fn completions(shell: Shell) -> Result<()> {
+    write_completions(shell, &mut io::stdout());
+
+    Ok(())
+}
+
+/// Write a completion script for `shell` to `out`
+fn write_completions(shell: Shell, out: &mut impl Write) {
+    let mut command = Args::command();
+    let bin_name = command.get_name().to_string();
+    clap_complete::generate(shell, &mut command, bin_name, out);
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::ValueEnum;
+
+    use super::*;
+
+    #[test]
+    fn completions_generate_for_every_shell() {
+        for shell in Shell::value_variants() {
+            let mut out = Vec::new();
+            write_completions(*shell, &mut out);
+            assert!(!out.is_empty(), "no completions for {}", shell);
+        }
+    }
+}
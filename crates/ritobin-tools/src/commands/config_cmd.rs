@@ -1,73 +1,152 @@
+use crate::commands::download_hashes::{self, DEFAULT_RETRIES};
 use crate::utils::config::{self, AppConfig};
+use crate::utils::hashtable::HASHTABLE_FILES;
+use crate::utils::{colors_enabled, confirm, hyperlink_path, prompt_theme};
 use camino::Utf8PathBuf;
 use colored::Colorize;
-use miette::Result;
+use dialoguer::{Confirm, Input};
+use miette::{IntoDiagnostic, Result};
+use similar::{ChangeTag, TextDiff};
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, IsTerminal};
 
-/// Format a path as a clickable hyperlink using OSC 8 escape sequence.
-/// Falls back to underlined text if terminal doesn't support hyperlinks.
+/// Format a path as an underlined hyperlink, see [`hyperlink_path`]
 fn clickable_path(path: &Utf8PathBuf) -> String {
-    let file_url = format!("file:///{}", path.as_str().replace('\\', "/"));
-    let display = path.as_str().underline();
-    // OSC 8 hyperlink: \x1b]8;;URL\x1b\\TEXT\x1b]8;;\x1b\\
-    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", file_url, display)
+    let link = hyperlink_path(path);
+    if !colors_enabled() {
+        return link;
+    }
+    link.underline().to_string()
 }
 
-/// Print a config path entry with status indicator
-fn print_path_config(
-    name: &str,
-    path: Option<&Utf8PathBuf>,
-    validator: impl Fn(&Utf8PathBuf) -> bool,
-) {
-    match path {
-        Some(p) => {
-            let status = if validator(p) {
-                "✓".bright_green()
-            } else {
-                "✗".bright_red()
-            };
-            println!(
-                "  {} {} {}",
-                format!("{}:", name).bright_white(),
-                clickable_path(p),
-                status
-            );
-        }
-        None => {
-            println!(
-                "  {} {}",
-                format!("{}:", name).bright_white(),
-                "(not set)".bright_yellow()
-            );
+/// Config values laid out as two columns, keys padded to the longest one so the values
+/// line up however many keys there are
+#[derive(Default)]
+struct ConfigTable {
+    rows: Vec<(&'static str, String)>,
+}
+
+impl ConfigTable {
+    /// Add a path entry with a status indicator
+    fn path(
+        &mut self,
+        name: &'static str,
+        path: Option<&Utf8PathBuf>,
+        validator: impl Fn(&Utf8PathBuf) -> bool,
+    ) {
+        let value = match path {
+            Some(p) => {
+                let status = if validator(p) {
+                    "✓".bright_green()
+                } else {
+                    "✗".bright_red()
+                };
+                format!("{} {}", clickable_path(p), status)
+            }
+            None => "(not set)".bright_yellow().to_string(),
+        };
+        self.rows.push((name, value));
+    }
+
+    /// Add a plain value entry
+    fn value(&mut self, name: &'static str, value: Option<impl Display>) {
+        let value = match value {
+            Some(v) => v.to_string(),
+            None => "(not set)".bright_yellow().to_string(),
+        };
+        self.rows.push((name, value));
+    }
+
+    fn print(&self) {
+        let width = self
+            .rows
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        for (name, value) in &self.rows {
+            // Pad before coloring, escape codes would count towards the width
+            let key = format!("{:<width$}", name, width = width);
+            println!("  {}  {}", key.bright_white(), value);
         }
     }
 }
 
 pub fn show_config() -> Result<()> {
     let (cfg, _) = config::load_or_create_config()?;
-    let config_path = config::default_config_path();
 
-    println!();
-    match &config_path {
-        Some(p) => println!("  {} {}", "config_file:".bright_white(), clickable_path(p)),
-        None => println!(
-            "  {} {}",
-            "config_file:".bright_white(),
-            "Unknown".bright_yellow()
-        ),
+    let mut table = ConfigTable::default();
+    match config::default_config_path() {
+        Some(p) => table.rows.push(("config_file", clickable_path(&p))),
+        None => table
+            .rows
+            .push(("config_file", "Unknown".bright_yellow().to_string())),
     }
-
-    print_path_config("hashtable_dir", cfg.hashtable_dir.as_ref(), |p| p.exists());
+    table.path("hashtable_dir", cfg.hashtable_dir.as_ref(), |p| p.exists());
+    table.value("download_timeout_secs", cfg.download_timeout_secs);
+    table.value("link_style", cfg.link_style);
+    table.value("io_buffer_size", cfg.io_buffer_size);
+    table.path("combined_hashtable", cfg.combined_hashtable.as_ref(), |p| {
+        p.is_file()
+    });
+    table.value("hashtable_max_age_days", cfg.hashtable_max_age_days);
+    table.value("hashtable_auto_update", cfg.hashtable_auto_update);
+    table.value("hash_resolver_cmd", cfg.hash_resolver_cmd.as_ref());
+    table.value(
+        "extra_hashtables",
+        cfg.extra_hashtables.as_ref().map(|extra| {
+            extra
+                .iter()
+                .map(|extra| extra.file.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }),
+    );
+    table.value(
+        "binary_extensions",
+        cfg.binary_extensions.as_ref().map(|list| list.join(", ")),
+    );
+    table.value(
+        "text_extensions",
+        cfg.text_extensions.as_ref().map(|list| list.join(", ")),
+    );
+    table.value("text_extension", cfg.text_extension);
 
+    println!();
+    table.print();
     println!();
     Ok(())
 }
 
+/// Reset the config file to the defaults, after showing what changes and asking
+/// (see [`confirm`])
 pub fn reset_config() -> Result<()> {
     let config_path = config::default_config_path()
         .map(|p| p.to_string())
         .unwrap_or_else(|| "Unknown".to_string());
 
     let default_cfg = AppConfig::default();
+    if let Ok(current) = fs::read_to_string(&config_path) {
+        let reset = toml::to_string_pretty(&default_cfg)
+            .map_err(|e| miette::miette!("Failed to serialize config: {}", e))?;
+        if current == reset {
+            println!(
+                "{}",
+                "Configuration is already at the defaults".bright_white()
+            );
+            return Ok(());
+        }
+
+        println!();
+        print_config_diff(&current, &reset);
+        println!();
+        if !confirm("Reset the configuration to the defaults?")? {
+            println!("{}", "Configuration left unchanged".bright_yellow());
+            return Ok(());
+        }
+    }
+
     config::save_config(&default_cfg)
         .map_err(|e| miette::miette!("Failed to reset config: {}", e))?;
 
@@ -82,9 +161,90 @@ pub fn reset_config() -> Result<()> {
     Ok(())
 }
 
+/// Print the lines of the config file that a change removes and adds
+fn print_config_diff(old: &str, new: &str) {
+    for change in TextDiff::from_lines(old, new).iter_all_changes() {
+        let line = change.value().trim_end_matches(['\r', '\n']);
+        match change.tag() {
+            ChangeTag::Delete => println!("  {}", format!("- {}", line).bright_red()),
+            ChangeTag::Insert => println!("  {}", format!("+ {}", line).bright_green()),
+            ChangeTag::Equal => {}
+        }
+    }
+}
+
+/// First-time setup: asks for the hashtable directory, saves the config and offers to
+/// download the hashtables. Without a terminal on stdin nothing is asked and the current
+/// values (or the defaults) are saved as they are.
+pub fn init_config() -> Result<()> {
+    // Only what's saved, values from --set aren't written to the file, and relative paths
+    // stay relative
+    let (mut cfg, config_path) = config::load_saved_config()?;
+
+    if !io::stdin().is_terminal() {
+        tracing::info!("stdin is not a terminal, saving the configuration without prompting");
+        config::save_config(&cfg).map_err(|e| miette::miette!("Failed to save config: {}", e))?;
+        print_initialized(&config_path, &cfg);
+        return Ok(());
+    }
+
+    let theme = prompt_theme();
+
+    let mut prompt = Input::<String>::with_theme(theme.as_ref()).with_prompt("Hashtable directory");
+    if let Some(dir) = &cfg.hashtable_dir {
+        prompt = prompt.default(dir.to_string());
+    }
+    let hashtable_dir = Utf8PathBuf::from(prompt.interact_text().into_diagnostic()?.trim());
+    cfg.hashtable_dir = Some(hashtable_dir.clone());
+
+    config::save_config(&cfg).map_err(|e| miette::miette!("Failed to save config: {}", e))?;
+    print_initialized(&config_path, &cfg);
+
+    // Only suggest downloading by default when some hashtable is still missing
+    let hashtable_dir = config::resolve_relative_path(&hashtable_dir, &config_path);
+    let missing = HASHTABLE_FILES
+        .iter()
+        .any(|file| !hashtable_dir.join(file).is_file());
+    let download = Confirm::with_theme(theme.as_ref())
+        .with_prompt("Download the hashtables from CommunityDragon now?")
+        .default(missing)
+        .interact()
+        .into_diagnostic()?;
+    if download {
+        download_hashes::download_hashes(None, DEFAULT_RETRIES, None, false)?;
+    }
+
+    Ok(())
+}
+
+fn print_initialized(config_path: &Utf8PathBuf, cfg: &AppConfig) {
+    println!();
+    println!("{}", "✓ Configuration saved".bright_green().bold());
+    println!();
+    println!(
+        "  {} {}",
+        "Config file:".bright_white().bold(),
+        clickable_path(config_path)
+    );
+    let mut table = ConfigTable::default();
+    table.path("hashtable_dir", cfg.hashtable_dir.as_ref(), |p| {
+        config::resolve_relative_path(p, config_path).exists()
+    });
+    table.print();
+    println!();
+}
+
 pub fn set_config_value(key: &str, value: &str) -> Result<()> {
+    if !config::CONFIG_KEYS.contains(&key) {
+        tracing::warn!(
+            "Unknown config key '{}', it will be ignored (known keys: {})",
+            key,
+            config::CONFIG_KEYS.join(", ")
+        );
+    }
+
     let mut table = config::load_config_as_table()?;
-    let toml_value = parse_toml_value(value);
+    let toml_value = parse_toml_value(key, value);
 
     table.insert(key.to_string(), toml_value);
 
@@ -106,8 +266,28 @@ pub fn set_config_value(key: &str, value: &str) -> Result<()> {
     Ok(())
 }
 
-/// Parse a string value into an appropriate TOML value type
-fn parse_toml_value(value: &str) -> toml::Value {
+/// Parse a string value into an appropriate TOML value type.
+/// Path keys are always strings with forward slashes, list keys split on commas, and
+/// quoting a value (`"123"` or `'true'`) forces it to be a string.
+pub fn parse_toml_value(key: &str, value: &str) -> toml::Value {
+    let unquoted = strip_quotes(value);
+    if config::PATH_CONFIG_KEYS.contains(&key) {
+        let path = Utf8PathBuf::from(unquoted.unwrap_or(value));
+        return toml::Value::String(config::normalize_path(&path).into_string());
+    }
+    if config::LIST_CONFIG_KEYS.contains(&key) {
+        let items = unquoted.unwrap_or(value).split(',').map(str::trim);
+        return toml::Value::Array(
+            items
+                .filter(|item| !item.is_empty())
+                .map(|item| toml::Value::String(item.to_string()))
+                .collect(),
+        );
+    }
+    if let Some(s) = unquoted {
+        return toml::Value::String(s.to_string());
+    }
+
     if let Ok(b) = value.parse::<bool>() {
         return toml::Value::Boolean(b);
     }
@@ -122,10 +302,101 @@ fn parse_toml_value(value: &str) -> toml::Value {
     toml::Value::String(value.to_string())
 }
 
-/// Ensures config.toml exists.
-pub fn ensure_config_exists() -> Result<()> {
-    let (_cfg, _path) = config::load_or_create_config()
+/// The inside of a value wrapped in matching double or single quotes
+fn strip_quotes(value: &str) -> Option<&str> {
+    ['"', '\'']
+        .into_iter()
+        .find_map(|quote| value.strip_prefix(quote)?.strip_suffix(quote))
+}
+
+/// Ensures config.toml exists, returning the loaded configuration.
+pub fn ensure_config_exists() -> Result<AppConfig> {
+    let (cfg, _path) = config::load_or_create_config()
         .map_err(|e| miette::miette!("Failed to initialize config: {}", e))?;
 
-    Ok(())
+    Ok(cfg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bool_values() {
+        assert_eq!(
+            parse_toml_value("hashtable_auto_update", "true"),
+            toml::Value::Boolean(true)
+        );
+        assert_eq!(
+            parse_toml_value("hashtable_auto_update", "false"),
+            toml::Value::Boolean(false)
+        );
+    }
+
+    #[test]
+    fn integer_values() {
+        assert_eq!(
+            parse_toml_value("download_timeout_secs", "45"),
+            toml::Value::Integer(45)
+        );
+    }
+
+    #[test]
+    fn float_values() {
+        assert_eq!(
+            parse_toml_value("download_timeout_secs", "1.5"),
+            toml::Value::Float(1.5)
+        );
+    }
+
+    #[test]
+    fn path_values() {
+        assert_eq!(
+            parse_toml_value("hashtable_dir", r"C:\Games\x"),
+            toml::Value::String("C:/Games/x".to_string())
+        );
+        assert_eq!(
+            parse_toml_value("hashtable_dir", r#""C:\Program Files\x""#),
+            toml::Value::String("C:/Program Files/x".to_string())
+        );
+        assert_eq!(
+            parse_toml_value("combined_hashtable", "2024"),
+            toml::Value::String("2024".to_string())
+        );
+        assert_eq!(
+            parse_toml_value("hashtable_dir", "1.5"),
+            toml::Value::String("1.5".to_string())
+        );
+    }
+
+    #[test]
+    fn list_values() {
+        assert_eq!(
+            parse_toml_value("binary_extensions", "bin, troybin,"),
+            toml::Value::Array(vec![
+                toml::Value::String("bin".to_string()),
+                toml::Value::String("troybin".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn quoted_values_stay_strings() {
+        assert_eq!(
+            parse_toml_value("download_timeout_secs", "\"45\""),
+            toml::Value::String("45".to_string())
+        );
+        assert_eq!(
+            parse_toml_value("hashtable_auto_update", "'true'"),
+            toml::Value::String("true".to_string())
+        );
+    }
+
+    #[test]
+    fn bare_strings() {
+        assert_eq!(
+            parse_toml_value("link_style", "osc8"),
+            toml::Value::String("osc8".to_string())
+        );
+    }
 }
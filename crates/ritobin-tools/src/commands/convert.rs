@@ -1,209 +1,2808 @@
-use std::fs::File;
-use std::io::{BufReader, BufWriter, Cursor, Read, Write};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::ops::AddAssign;
+use std::process::Command;
+use std::time::{Duration, Instant, SystemTime};
 
-use camino::{Utf8Path, Utf8PathBuf};
-use ltk_meta::BinTree;
-use ltk_ritobin::{HashMapProvider, HexHashProvider, WriterConfig};
+use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
+use clap::ValueEnum;
+use flate2::write::GzEncoder;
+use indicatif::HumanBytes;
+use ltk_meta::value::{EmbeddedValue, UnorderedContainerValue};
+use ltk_meta::{BinTree, PropertyValueEnum};
+use ltk_ritobin::{HashMapProvider, HashProvider};
+use memmap2::Mmap;
 use miette::{IntoDiagnostic, Result, WrapErr};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
 use walkdir::WalkDir;
 
-use crate::utils::config::load_or_create_config;
-use crate::utils::hyperlink_path;
+use crate::utils::bin_io::{self, FallbackProvider, RecordingProvider};
+use crate::utils::bin_json;
+use crate::utils::config::{
+    AppConfig, DEFAULT_BINARY_EXTENSIONS, DEFAULT_TEXT_EXTENSIONS, load_or_create_config,
+};
+use crate::utils::exit_code::Failure;
+use crate::utils::hashtable::LazyHashProvider;
+use crate::utils::linked_bins::linked_bins_summary;
+use crate::utils::permission::{
+    IntoAccessDiagnostic, OUTPUT_DIR_HINT, OUTPUT_FILE_HINT, is_permission_denied,
+};
+use crate::utils::ritobin_text::{
+    comment_lines, find_duplicate_keys, names_from_text, written_names,
+};
+use crate::utils::schema::Schema;
+use crate::utils::{
+    DEFAULT_IO_BUFFER_SIZE, TextExtension, confirm, format_hash, hyperlink_path,
+    install_interrupt_handler, io_buffer_size, log_file_progress, read_file_list, relative_path,
+    stop_requested,
+};
+
+/// `--output` value that writes the converted file to stdout
+pub const STDOUT_OUTPUT: &str = "-";
 
 /// Supported file extensions for conversion
-const SUPPORTED_EXTENSIONS: &[&str] = &["bin", "py", "ritobin"];
+pub(crate) const SUPPORTED_EXTENSIONS: &[&str] = &["bin", "py", "ritobin"];
+
+/// How an input file is read, decided by its extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum InputKind {
+    Bin,
+    Text,
+}
+
+impl InputKind {
+    /// The extension `--only` knows this kind by besides the file's own
+    fn only_name(self) -> &'static str {
+        match self {
+            InputKind::Bin => "bin",
+            InputKind::Text => "py",
+        }
+    }
+}
+
+/// The input extensions read as .bin and as ritobin text, from the `binary_extensions`
+/// and `text_extensions` config lists. Extensions match case-insensitively.
+#[derive(Debug, Clone)]
+pub(crate) struct InputExtensions {
+    binary: Vec<String>,
+    text: Vec<String>,
+}
+
+impl Default for InputExtensions {
+    fn default() -> Self {
+        Self {
+            binary: to_strings(DEFAULT_BINARY_EXTENSIONS),
+            text: to_strings(DEFAULT_TEXT_EXTENSIONS),
+        }
+    }
+}
+
+impl InputExtensions {
+    fn from_config(config: &AppConfig) -> Self {
+        let defaults = Self::default();
+        Self {
+            binary: config.binary_extensions.clone().unwrap_or(defaults.binary),
+            text: config.text_extensions.clone().unwrap_or(defaults.text),
+        }
+    }
+
+    /// How `path` is read, `None` when its extension is in neither list
+    pub(crate) fn kind(&self, path: &Utf8Path) -> Option<InputKind> {
+        let extension = path.extension()?;
+        let matches = |list: &[String]| list.iter().any(|e| e.eq_ignore_ascii_case(extension));
+        if matches(&self.binary) {
+            Some(InputKind::Bin)
+        } else if matches(&self.text) {
+            Some(InputKind::Text)
+        } else {
+            None
+        }
+    }
+
+    /// The extensions for error messages, e.g. `.bin, .py, .ritobin`
+    fn describe(&self) -> String {
+        self.binary
+            .iter()
+            .chain(&self.text)
+            .map(|ext| format!(".{}", ext))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn to_strings(list: &[&str]) -> Vec<String> {
+    list.iter().map(|s| s.to_string()).collect()
+}
+
+/// Format written by a conversion
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum OutputFormat {
+    Bin,
+    Ritobin,
+    Json,
+}
+
+impl OutputFormat {
+    pub(crate) const ALL: [OutputFormat; 3] =
+        [OutputFormat::Bin, OutputFormat::Ritobin, OutputFormat::Json];
+
+    /// Output extensions selecting this format
+    pub(crate) fn extensions(self) -> &'static [&'static str] {
+        match self {
+            OutputFormat::Bin => &["bin"],
+            OutputFormat::Ritobin => &["py", "ritobin"],
+            OutputFormat::Json => &["json"],
+        }
+    }
+
+    pub(crate) fn description(self) -> &'static str {
+        match self {
+            OutputFormat::Bin => "Binary property bin, as stored in the game files",
+            OutputFormat::Ritobin => "Ritobin text, editable and diffable",
+            OutputFormat::Json => "JSON for other tools (output only)",
+        }
+    }
+
+    /// Input extensions that can be converted to this format
+    pub(crate) fn sources(self) -> &'static [&'static str] {
+        match self {
+            OutputFormat::Bin => &["py", "ritobin"],
+            OutputFormat::Ritobin => &["bin"],
+            OutputFormat::Json => SUPPORTED_EXTENSIONS,
+        }
+    }
+}
+
+/// The format an explicit output path asks for, `None` if its extension doesn't name one
+fn target_from_output_ext(output_path: &Utf8Path) -> Option<OutputFormat> {
+    // A compressed output is named after what it holds, e.g. `skin0.json.gz`
+    let extension = match Compression::from_path(output_path) {
+        Some(_) => Utf8Path::new(output_path.file_stem()?).extension()?,
+        None => output_path.extension()?,
+    };
+    OutputFormat::ALL
+        .into_iter()
+        .find(|format| format.extensions().contains(&extension))
+}
+
+/// Newline style of written ritobin text
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LineEnding {
+    /// `\n`, the same on every platform
+    #[default]
+    Lf,
+    /// `\r\n`
+    Crlf,
+    /// `\r\n` on Windows, `\n` elsewhere
+    Native,
+}
+
+impl LineEnding {
+    /// Rewrite the newlines of `text`, which the ritobin writer always ends with `\n`
+    fn apply(self, text: String) -> String {
+        let crlf = match self {
+            LineEnding::Lf => false,
+            LineEnding::Crlf => true,
+            LineEnding::Native => cfg!(windows),
+        };
+        if crlf {
+            text.replace('\n', "\r\n")
+        } else {
+            text
+        }
+    }
+}
+
+/// How `--flat` handles two inputs that would write the same output name
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnCollision {
+    /// Fail before converting anything
+    #[default]
+    Error,
+    /// Prefix the colliding outputs with their parent directories, e.g. `a_b_character.py`
+    Rename,
+}
+
+/// Compression applied to text and JSON output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Compression {
+    /// gzip, written as `<output>.gz`
+    Gz,
+    /// Zstandard, written as `<output>.zst`
+    Zst,
+}
+
+impl Compression {
+    fn extension(self) -> &'static str {
+        match self {
+            Compression::Gz => "gz",
+            Compression::Zst => "zst",
+        }
+    }
+
+    /// The compression a path's extension names, e.g. gzip for `skin0.py.gz`
+    fn from_path(path: &Utf8Path) -> Option<Self> {
+        let extension = path.extension()?;
+        Self::value_variants()
+            .iter()
+            .copied()
+            .find(|compression| compression.extension() == extension)
+    }
+
+    fn compress(self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Compression::Gz => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            }
+            Compression::Zst => zstd::encode_all(bytes, zstd::DEFAULT_COMPRESSION_LEVEL),
+        }
+    }
+}
+
+/// Number of files listed in the `--timings` summary
+const SLOWEST_FILES: usize = 10;
+
+/// Line separating documents for `--multi` and `--bundle` unless `--separator` is given.
+/// Every ritobin document starts with this header, so plain concatenation already splits on it.
+pub const DEFAULT_DOCUMENT_SEPARATOR: &str = "#PROP_text";
+
+/// Options applied to every file converted in a run
+#[derive(Debug, Clone, Default)]
+pub struct ConvertOptions {
+    /// Write a `<output>.sha256` checksum file next to every output
+    pub sha256: bool,
+    /// Write outputs identical to an earlier output of the run as a symlink to it
+    pub dedup: bool,
+    /// Template for output paths when no explicit output is given
+    pub name_template: Option<NameTemplate>,
+    /// Reject ritobin input that defines the same key twice in one block
+    pub strict: bool,
+    /// Memory-map .bin input instead of reading it through a buffered file handle
+    pub mmap: bool,
+    /// Extensions to convert in directory and glob runs, empty means all supported ones
+    pub only: Vec<String>,
+    /// Split ritobin input into several documents and write one numbered .bin per document
+    pub multi: bool,
+    /// Convert all .bin inputs into a single ritobin text file
+    pub bundle: bool,
+    /// Line separating documents for `multi` and `bundle`, [`DEFAULT_DOCUMENT_SEPARATOR`] if unset
+    pub separator: Option<String>,
+    /// Log paths relative to the input directory (or the working directory)
+    pub relative_paths: bool,
+    /// Fail .bin to text conversions that leave any hash without a name
+    pub require_names: bool,
+    /// Fail text to .bin conversions using names the hashtables don't know
+    pub known_names_only: bool,
+    /// Skip files in directory and glob runs last modified before this time
+    pub since: Option<SystemTime>,
+    /// How deep directory runs descend, overriding `recursive` when set
+    pub max_depth: Option<usize>,
+    /// Stop directory, glob and listed runs after this many files
+    pub limit: Option<usize>,
+    /// Directory the bins linked from .bin inputs are looked up in, to list them and their
+    /// entries at the end of the text output
+    pub resolve_links: Option<Utf8PathBuf>,
+    /// Start text output with a comment naming the source file, tool version, date and
+    /// hashtables, see [`provenance_comment`]
+    pub header: bool,
+    /// Follow symlinks when walking directories
+    pub follow_symlinks: bool,
+    /// Walk into hidden files and directories (names starting with `.`)
+    pub include_hidden: bool,
+    /// Command run after every successfully converted file
+    pub exec: Option<ExecCommand>,
+    /// Log how long each file took to parse, serialize and write, and summarize at the end
+    pub timings: bool,
+    /// CSV file receiving one row of timings per converted file
+    pub timings_out: Option<Utf8PathBuf>,
+    /// Start ritobin text output with a byte order mark if the file it replaces had one
+    pub preserve_bom: bool,
+    /// Only report how many files would be converted and their total size
+    pub count: bool,
+    /// Print every written output path to stdout, one per line
+    pub print_output_path: bool,
+    /// Schema every parsed tree must match, see [`Schema`]
+    pub schema: Option<Schema>,
+    /// Newline style of ritobin text output
+    pub line_ending: LineEnding,
+    /// Extension of text outputs without an explicit path, resolved by [`convert`] from
+    /// `text_extension` in the config when unset
+    pub text_extension: Option<TextExtension>,
+    /// File recording converted inputs, so a rerun of a directory or glob skips them
+    pub checkpoint: Option<Utf8PathBuf>,
+    /// Start `checkpoint` over instead of skipping the files it lists
+    pub no_resume: bool,
+    /// Compress text and JSON output, adding the compression's extension to the output path
+    pub compress: Option<Compression>,
+    /// Only write this many levels of values below each entry in text and JSON output,
+    /// 0 keeping just the entry names and types
+    pub entries_depth: Option<usize>,
+    /// Only convert the entries whose name starts with one of these, ignoring case
+    pub entry_prefixes: Vec<String>,
+    /// Write inputs without any entry matching `entry_prefixes` with no entries instead of
+    /// skipping them
+    pub keep_empty: bool,
+    /// Write every output of a directory or glob straight into `output_dir`
+    pub flat: bool,
+    /// Leave out ritobin entries that don't parse instead of failing the file
+    pub best_effort: bool,
+    /// What `flat` does when two inputs would write the same output name
+    pub on_collision: OnCollision,
+    /// File listing the inputs to convert one per line, `-` for stdin
+    pub files_from: Option<String>,
+    /// File listing the entries of every output of a directory or glob run, JSON for a
+    /// `.json` path and TSV otherwise
+    pub index: Option<Utf8PathBuf>,
+    /// Ask once per run before replacing an existing output, see [`confirm`]
+    pub confirm_overwrite: bool,
+    /// Base for relative paths, resolved by [`convert`] from the input
+    pub(crate) path_base: Option<Utf8PathBuf>,
+    /// Directory the outputs of a directory or glob run are written into, keeping their
+    /// path below the input directory unless `flat`
+    pub output_dir: Option<Utf8PathBuf>,
+    /// What outputs keep their path relative to in `output_dir`, resolved by [`convert`]
+    /// from the input
+    pub(crate) input_root: Option<Utf8PathBuf>,
+    /// Parent directory prefixes for inputs whose `flat` output names collide
+    pub(crate) flat_prefixes: HashMap<Utf8PathBuf, String>,
+    /// Outputs written since [`EntryIndex`] last collected them, filled while `index` is set
+    pub(crate) indexed: RefCell<Vec<IndexedOutput>>,
+    /// Answer to the `confirm_overwrite` question, once it was asked
+    pub(crate) overwrite_answer: Cell<Option<bool>>,
+    /// Warnings logged for the file being converted, handed out in its [`ConversionResult`]
+    pub(crate) warnings: RefCell<Vec<String>>,
+    /// SHA-256 of every output written so far and where it went, filled while `dedup` is set
+    pub(crate) written_digests: RefCell<HashMap<[u8; 32], Utf8PathBuf>>,
+    /// Which inputs are read as .bin and which as text, resolved by [`convert`] from the
+    /// config
+    pub(crate) input_extensions: InputExtensions,
+}
+
+impl ConvertOptions {
+    /// Render a path for logs, relative to the input root with `--relative-paths`
+    fn display_path(&self, path: &Utf8Path) -> String {
+        if path == STDOUT_OUTPUT {
+            return "stdout".to_string();
+        }
+        match &self.path_base {
+            Some(base) => hyperlink_path(relative_path(base, path)),
+            None => hyperlink_path(path),
+        }
+    }
+
+    /// Log a warning about the file being converted and keep it for its result
+    fn warn(&self, message: String) {
+        tracing::warn!("{}", message);
+        self.warnings.borrow_mut().push(message);
+    }
+
+    /// Warn that the comments on `lines` of a text input can't survive in its .bin output,
+    /// which has no place for them
+    fn warn_lost_comments(&self, input_path: &Utf8Path, lines: &[usize]) {
+        if let Some(first) = lines.first() {
+            self.warn(format!(
+                "{}: {} comment(s) will be lost in the .bin output (first on line {})",
+                self.display_path(input_path),
+                lines.len(),
+                first
+            ));
+        }
+    }
+
+    /// Write a tree as ritobin text, enforcing `require_names`. Also returns how many
+    /// hashes were written as hex, which `require_names` never allows.
+    fn tree_to_text(&self, tree: &BinTree, hashes: &LazyHashProvider) -> Result<(String, usize)> {
+        if self.require_names {
+            Ok((bin_io::tree_to_named_text(tree, hashes.get()?)?, 0))
+        } else {
+            bin_io::tree_to_text_unresolved(tree, hashes.get()?)
+        }
+    }
+
+    /// Parse ritobin text, with `best_effort` leaving out the entries that don't parse.
+    /// `line_offset` is added to reported lines, for documents that don't start the file.
+    fn parse_text(&self, input_path: &Utf8Path, text: &str, line_offset: usize) -> Result<BinTree> {
+        if !self.best_effort {
+            return bin_io::parse_text(text);
+        }
+
+        let (tree, skipped) = bin_io::parse_text_best_effort(text)?;
+        for entry in &skipped {
+            self.warn(format!(
+                "{}: skipped the entry on lines {}-{}, line {}: {}",
+                self.display_path(input_path),
+                entry.lines.start() + line_offset,
+                entry.lines.end() + line_offset,
+                entry.error_line + line_offset,
+                entry.error
+            ));
+        }
+        if !skipped.is_empty() {
+            self.warn(format!(
+                "{}: converted {} entries, skipped {} that don't parse",
+                self.display_path(input_path),
+                tree.objects.len(),
+                skipped.len()
+            ));
+        }
+        Ok(tree)
+    }
+
+    /// With `known_names_only`, fail if `text` names a hash of `tree` that the hashtables
+    /// and the `hash_resolver_cmd` don't know, warning about each such name with its line.
+    /// Hex literals are left alone, writing one is never a typo.
+    fn check_known_names(
+        &self,
+        input_path: &Utf8Path,
+        tree: &BinTree,
+        text: &str,
+        line_offset: usize,
+        hashes: &LazyHashProvider,
+    ) -> Result<()> {
+        if !self.known_names_only {
+            return Ok(());
+        }
+        let hashes = hashes.get()?.ok_or_else(|| {
+            miette::miette!(
+                "No hashtables loaded, --known-names-only can't tell which names are known"
+            )
+        })?;
+
+        let unresolved: HashMap<u32, &str> = bin_io::unresolved_hashes(tree, hashes)?
+            .into_iter()
+            .map(|(category, hash)| (hash, category))
+            .collect();
+        if unresolved.is_empty() {
+            return Ok(());
+        }
+
+        let input_label = self.display_path(input_path);
+        let mut unknown = 0;
+        for written in written_names(text) {
+            if let Some(category) = unresolved.get(&written.hash) {
+                tracing::warn!(
+                    "{}: line {}: {} is not a known {} name",
+                    input_label,
+                    written.line + line_offset,
+                    written.name,
+                    category
+                );
+                unknown += 1;
+            }
+        }
+        if unknown == 0 {
+            return Ok(());
+        }
+        Err(miette::miette!(
+            "{} unknown name(s) in {}, fix the typos or write the hashes as hex",
+            unknown,
+            input_label
+        ))
+    }
+
+    /// Fail if `tree` doesn't match the schema, warning about each violation
+    fn check_schema(
+        &self,
+        input_path: &Utf8Path,
+        tree: &BinTree,
+        names: &impl HashProvider,
+    ) -> Result<()> {
+        let Some(schema) = &self.schema else {
+            return Ok(());
+        };
+
+        let violations = schema.check(tree, names);
+        if violations.is_empty() {
+            return Ok(());
+        }
+
+        let input_label = self.display_path(input_path);
+        for violation in &violations {
+            tracing::warn!("{}: {}: {}", input_label, violation.path, violation.message);
+        }
+        Err(miette::miette!(
+            "{} schema violation(s) in {}",
+            violations.len(),
+            input_label
+        ))
+    }
+
+    /// Where text output for `output_path` goes: with `compress`, the path plus the
+    /// compression's extension unless it already ends with it
+    fn text_output_path(&self, output_path: Utf8PathBuf) -> Utf8PathBuf {
+        match self.compress {
+            Some(compression)
+                if output_path != STDOUT_OUTPUT
+                    && output_path.extension() != Some(compression.extension()) =>
+            {
+                Utf8PathBuf::from(format!("{}.{}", output_path, compression.extension()))
+            }
+            _ => output_path,
+        }
+    }
+
+    /// Write text or JSON output, compressed with `compress` or when the path ends in
+    /// the extension of a compression
+    fn write_text_output(&self, output_path: &Utf8Path, text: &str) -> Result<()> {
+        let Some(compression) = self
+            .compress
+            .or_else(|| Compression::from_path(output_path))
+        else {
+            return write_output(output_path, text.as_bytes(), self);
+        };
+
+        let bytes = compression
+            .compress(text.as_bytes())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to compress output for {}", output_path))?;
+        write_output(output_path, &bytes, self)
+    }
+
+    /// Keep only the entries of `tree` named with one of `entry_prefixes`, if any are given.
+    /// Entries without a name never match. Returns whether there is anything to write, which
+    /// is always the case with `keep_empty`. `label` names the input in the log.
+    ///
+    /// Fails if none of the entries has a name, as without hashtables every .bin would be
+    /// skipped and the run would quietly do nothing.
+    fn filter_entries(
+        &self,
+        label: &str,
+        tree: &mut BinTree,
+        names: &impl HashProvider,
+    ) -> Result<bool> {
+        if self.entry_prefixes.is_empty() {
+            return Ok(true);
+        }
+        if !tree.objects.is_empty()
+            && tree
+                .objects
+                .keys()
+                .all(|&hash| names.lookup_entry(hash).is_none())
+        {
+            return Err(Failure::Usage(format!(
+                "--entry-prefix can't match the entries of {}, none of their names are known; \
+                 run `ritobin-tools download-hashes` or check hashtable_dir",
+                label
+            ))
+            .into());
+        }
+
+        let prefixes: Vec<String> = self
+            .entry_prefixes
+            .iter()
+            .map(|prefix| prefix.to_lowercase())
+            .collect();
+        tree.objects.retain(|&hash, _| {
+            names.lookup_entry(hash).is_some_and(|name| {
+                let name = name.to_lowercase();
+                prefixes.iter().any(|prefix| name.starts_with(prefix))
+            })
+        });
+
+        if tree.objects.is_empty() && !self.keep_empty {
+            log_file_progress(format_args!(
+                "Skipping {} (no entries match --entry-prefix)",
+                label
+            ));
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Cut `tree` down to `entries_depth` levels of values, if set
+    fn prune(&self, tree: &mut BinTree) {
+        if let Some(depth) = self.entries_depth {
+            prune_tree(tree, depth);
+        }
+    }
+
+    /// Walk depth for directory inputs: `max_depth` if given, otherwise unlimited
+    /// with `recursive` and only the directory's own files without
+    fn walk_depth(&self, recursive: bool) -> Option<usize> {
+        self.max_depth.or((!recursive).then_some(1))
+    }
+
+    /// Paths under a directory input, walked according to these options
+    fn directory_paths(&self, dir_path: &Utf8Path, recursive: bool) -> Vec<Utf8PathBuf> {
+        directory_paths(
+            dir_path,
+            self.walk_depth(recursive),
+            self.follow_symlinks,
+            self.include_hidden,
+        )
+    }
+
+    /// With `index`, remember the entries written to `output_path`, named where `names`
+    /// knows them
+    fn index_entries(&self, output_path: &Utf8Path, tree: &BinTree, names: &impl HashProvider) {
+        if self.index.is_none() {
+            return;
+        }
+        let entries = tree
+            .objects
+            .keys()
+            .map(|&hash| (hash, names.lookup_entry(hash).map(str::to_string)))
+            .collect();
+        self.indexed.borrow_mut().push(IndexedOutput {
+            output: output_path.to_path_buf(),
+            entries,
+        });
+    }
+
+    /// Hint for outputs that can't be written, fitting how their path was given
+    fn output_hint(&self) -> &'static str {
+        if self.output_dir.is_some() {
+            OUTPUT_DIR_HINT
+        } else {
+            OUTPUT_FILE_HINT
+        }
+    }
+
+    /// Create the directory an output in `output_dir` goes into, then see [`check_overwrite`]
+    ///
+    /// [`check_overwrite`]: Self::check_overwrite
+    fn prepare_output(&self, output_path: &Utf8Path) -> Result<()> {
+        if self.output_dir.is_some()
+            && let Some(parent) = output_path.parent()
+            && !parent.as_str().is_empty()
+        {
+            fs::create_dir_all(parent)
+                .write_access(OUTPUT_DIR_HINT)
+                .wrap_err_with(|| format!("Failed to create output directory: {}", parent))?;
+        }
+        self.check_overwrite(output_path)?;
+        // A link left by an earlier `dedup` run would have the output written through it,
+        // into the file it points to
+        if self.dedup && output_path.is_symlink() {
+            fs::remove_file(output_path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to remove old link: {}", output_path))?;
+        }
+        Ok(())
+    }
+
+    /// With `dedup`, link `output_path` to the earlier output with the same `digest`, if
+    /// there is one. Returns whether it did, otherwise the output has to be written, see
+    /// [`record_written`](Self::record_written).
+    fn link_duplicate(&self, output_path: &Utf8Path, digest: [u8; 32]) -> bool {
+        let written = self.written_digests.borrow();
+        let Some(original) = written.get(&digest) else {
+            return false;
+        };
+        if original == output_path {
+            return false;
+        }
+
+        let target = link_target(output_path, original);
+        // The output replaces an existing file like any other write would, which
+        // `prepare_output` already confirmed
+        let linked = match fs::remove_file(output_path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => symlink_file(&target, output_path),
+        };
+        match linked {
+            Ok(()) => {
+                log_file_progress(format_args!(
+                    "{} is identical to {}, linked",
+                    self.display_path(output_path),
+                    self.display_path(original)
+                ));
+                true
+            }
+            Err(e) => {
+                self.warn(format!(
+                    "Failed to link {} to {}, writing a copy: {}",
+                    self.display_path(output_path),
+                    self.display_path(original),
+                    e
+                ));
+                false
+            }
+        }
+    }
+
+    /// Remember an output that was written in full, so later outputs with the same `digest`
+    /// are linked to it by [`link_duplicate`](Self::link_duplicate).
+    fn record_written(&self, output_path: &Utf8Path, digest: [u8; 32]) {
+        self.written_digests
+            .borrow_mut()
+            .entry(digest)
+            .or_insert_with(|| output_path.to_path_buf());
+    }
+
+    /// With `confirm_overwrite`, ask before the first existing output of the run is
+    /// replaced. Once declined, this and every later overwrite fails.
+    fn check_overwrite(&self, output_path: &Utf8Path) -> Result<()> {
+        if !self.confirm_overwrite || output_path == STDOUT_OUTPUT || !output_path.exists() {
+            return Ok(());
+        }
+        let approved = match self.overwrite_answer.get() {
+            Some(approved) => approved,
+            None => {
+                let approved = confirm(&format!(
+                    "{} already exists. Overwrite it and every other existing output?",
+                    output_path
+                ))?;
+                self.overwrite_answer.set(Some(approved));
+                approved
+            }
+        };
+        if !approved {
+            return Err(miette::miette!("overwriting existing outputs was declined"))
+                .wrap_err_with(|| format!("Not overwriting {}", output_path));
+        }
+        Ok(())
+    }
+
+    /// Log a finished conversion and run the `--exec` command for it, if any
+    fn converted(&self, input_path: &Utf8Path, output_path: &Utf8Path) -> Result<()> {
+        log_file_progress(format_args!(
+            "Converted {} -> {}",
+            self.display_path(input_path),
+            self.display_path(output_path)
+        ));
+        self.print_output(output_path);
+        match &self.exec {
+            Some(exec) => exec.run(input_path, output_path),
+            None => Ok(()),
+        }
+    }
+
+    /// With `print_output_path`, print the path as given, without hyperlinks or
+    /// `--relative-paths`, so scripts can use it directly
+    fn print_output(&self, output_path: &Utf8Path) {
+        if self.print_output_path {
+            println!("{}", output_path);
+        }
+    }
+
+    fn separator(&self) -> &str {
+        self.separator
+            .as_deref()
+            .unwrap_or(DEFAULT_DOCUMENT_SEPARATOR)
+    }
+}
+
+/// Time spent in each step of converting one file.
+/// Binary output streamed straight to the file counts its serialization as write time.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct FileTimings {
+    /// Reading the input and parsing it into a tree
+    parse: Duration,
+    /// Turning the tree into the output format
+    serialize: Duration,
+    /// Writing the output, including the checksum with `--sha256`
+    write: Duration,
+}
+
+impl FileTimings {
+    fn total(&self) -> Duration {
+        self.parse + self.serialize + self.write
+    }
+}
+
+impl AddAssign for FileTimings {
+    fn add_assign(&mut self, other: Self) {
+        self.parse += other.parse;
+        self.serialize += other.serialize;
+        self.write += other.write;
+    }
+}
+
+/// What converting one file did, returned by [`convert_file`]. Added up, it summarizes a
+/// whole batch.
+#[derive(Debug, Clone, Default)]
+pub struct ConversionResult {
+    /// Files written, several for `--multi`. Stdout is listed as `-`.
+    pub output_paths: Vec<Utf8PathBuf>,
+    /// Size of the input file
+    pub bytes_in: u64,
+    /// Size of the written files, not counting stdout
+    pub bytes_out: u64,
+    /// Hashes without a name, written as hex in text output
+    pub unresolved_hashes: usize,
+    /// Warnings logged while converting, e.g. entries `--best-effort` left out
+    pub warnings: Vec<String>,
+    /// Outputs written as a link to an identical earlier output with `--dedup`
+    pub deduplicated: usize,
+    /// Size of the files those links point to, which wasn't written again
+    pub bytes_saved: u64,
+    pub(crate) timings: FileTimings,
+}
+
+impl ConversionResult {
+    fn new(output_path: Utf8PathBuf, unresolved_hashes: usize, timings: FileTimings) -> Self {
+        Self {
+            output_paths: vec![output_path],
+            unresolved_hashes,
+            timings,
+            ..Default::default()
+        }
+    }
+}
+
+impl AddAssign for ConversionResult {
+    fn add_assign(&mut self, other: Self) {
+        self.output_paths.extend(other.output_paths);
+        self.bytes_in += other.bytes_in;
+        self.bytes_out += other.bytes_out;
+        self.unresolved_hashes += other.unresolved_hashes;
+        self.warnings.extend(other.warnings);
+        self.deduplicated += other.deduplicated;
+        self.bytes_saved += other.bytes_saved;
+        self.timings += other.timings;
+    }
+}
+
+/// Input paths already converted by earlier runs with the same `--checkpoint`, and the
+/// file that every newly converted path is appended to
+struct Checkpoint {
+    path: Utf8PathBuf,
+    done: HashSet<Utf8PathBuf>,
+    file: File,
+}
+
+impl Checkpoint {
+    /// The checkpoint if one was given, loading the paths it already holds.
+    /// With `no_resume` the file is emptied so every file is converted again.
+    fn open(options: &ConvertOptions) -> Result<Option<Self>> {
+        let Some(path) = &options.checkpoint else {
+            return Ok(None);
+        };
+
+        let mut done = HashSet::new();
+        let mut torn = false;
+        if !options.no_resume && path.is_file() {
+            let content = fs::read_to_string(path)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to read checkpoint: {}", path))?;
+            // A line without its newline was cut off mid-write, that file is redone
+            torn = !content.is_empty() && !content.ends_with('\n');
+            done.extend(
+                content
+                    .split_inclusive('\n')
+                    .filter_map(|line| line.strip_suffix('\n'))
+                    .filter(|line| !line.is_empty())
+                    .map(Utf8PathBuf::from),
+            );
+        }
+
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(!options.no_resume)
+            .write(true)
+            .truncate(options.no_resume)
+            .open(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to open checkpoint: {}", path))?;
+        let mut checkpoint = Self {
+            path: path.clone(),
+            done,
+            file,
+        };
+        // Terminate the cut off line so the next path starts on its own
+        if torn {
+            checkpoint.write_line("")?;
+        }
+
+        if !checkpoint.done.is_empty() {
+            tracing::info!(
+                "Resuming from {}: skipping {} already converted file(s)",
+                hyperlink_path(path),
+                checkpoint.done.len()
+            );
+        }
+        Ok(Some(checkpoint))
+    }
+
+    fn is_done(&self, path: &Utf8Path) -> bool {
+        self.done.contains(path)
+    }
+
+    /// Append a converted path. Each path is a single write of a whole line,
+    /// so an interrupted run never leaves half a path behind a newline.
+    fn record(&mut self, path: &Utf8Path) -> Result<()> {
+        self.write_line(path.as_str())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        self.file
+            .write_all(format!("{}\n", line).as_bytes())
+            .and_then(|_| self.file.flush())
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write checkpoint: {}", self.path))
+    }
+}
+
+/// Collects per-file timings for `--timings` and `--timings-out`
+struct TimingsReport {
+    files: Vec<(Utf8PathBuf, FileTimings)>,
+    csv: Option<(Utf8PathBuf, BufWriter<File>)>,
+}
+
+impl TimingsReport {
+    /// A report if timings were requested, creating the CSV file right away
+    /// so a bad path fails before any conversion work
+    fn new(options: &ConvertOptions) -> Result<Option<Self>> {
+        if !options.timings && options.timings_out.is_none() {
+            return Ok(None);
+        }
+
+        let csv = match &options.timings_out {
+            Some(path) => {
+                let file = File::create(path)
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to create timings file: {}", path))?;
+                let mut writer = BufWriter::new(file);
+                writeln!(writer, "path,parse_ms,serialize_ms,write_ms,total_ms")
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to write timings file: {}", path))?;
+                Some((path.clone(), writer))
+            }
+            None => None,
+        };
+
+        Ok(Some(Self {
+            files: Vec::new(),
+            csv,
+        }))
+    }
+
+    fn record(&mut self, path: &Utf8Path, timings: FileTimings) -> Result<()> {
+        tracing::debug!(
+            "Timings for {}: parse {}, serialize {}, write {}",
+            path,
+            format_duration(timings.parse),
+            format_duration(timings.serialize),
+            format_duration(timings.write)
+        );
+
+        if let Some((csv_path, writer)) = &mut self.csv {
+            writeln!(
+                writer,
+                "{},{:.3},{:.3},{:.3},{:.3}",
+                csv_field(path.as_str()),
+                timings.parse.as_secs_f64() * 1000.0,
+                timings.serialize.as_secs_f64() * 1000.0,
+                timings.write.as_secs_f64() * 1000.0,
+                timings.total().as_secs_f64() * 1000.0
+            )
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write timings file: {}", csv_path))?;
+        }
+
+        self.files.push((path.to_path_buf(), timings));
+        Ok(())
+    }
+
+    /// Log the totals and the slowest files, and flush the CSV file
+    fn finish(mut self, options: &ConvertOptions) -> Result<()> {
+        if let Some((csv_path, mut writer)) = self.csv.take() {
+            writer
+                .flush()
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to write timings file: {}", csv_path))?;
+            tracing::info!("Wrote timings to {}", hyperlink_path(&csv_path));
+        }
+
+        if !options.timings || self.files.is_empty() {
+            return Ok(());
+        }
+
+        let mut totals = FileTimings::default();
+        for (_, timings) in &self.files {
+            totals += *timings;
+        }
+        tracing::info!(
+            "Timings for {} file(s): parse {}, serialize {}, write {}, total {}",
+            self.files.len(),
+            format_duration(totals.parse),
+            format_duration(totals.serialize),
+            format_duration(totals.write),
+            format_duration(totals.total())
+        );
+
+        // A single file's timings are already in the totals
+        if self.files.len() == 1 {
+            return Ok(());
+        }
+        self.files
+            .sort_by_key(|(_, timings)| std::cmp::Reverse(timings.total()));
+        tracing::info!("Slowest files:");
+        for (path, timings) in self.files.iter().take(SLOWEST_FILES) {
+            tracing::info!(
+                "{:>10}  {} (parse {}, serialize {}, write {})",
+                format_duration(timings.total()),
+                options.display_path(path),
+                format_duration(timings.parse),
+                format_duration(timings.serialize),
+                format_duration(timings.write)
+            );
+        }
+        Ok(())
+    }
+}
+
+/// The entries of one output written with `--index`
+#[derive(Debug, Clone)]
+pub(crate) struct IndexedOutput {
+    output: Utf8PathBuf,
+    /// Path hash of every entry, with its name if known
+    entries: Vec<(u32, Option<String>)>,
+}
+
+/// One entry of a JSON `--index`
+#[derive(Debug, Serialize)]
+struct IndexEntry<'a> {
+    hash: String,
+    name: Option<&'a str>,
+}
+
+/// Collects the entries of every output for `--index`
+struct EntryIndex {
+    path: Utf8PathBuf,
+    writer: BufWriter<File>,
+    outputs: Vec<IndexedOutput>,
+}
+
+impl EntryIndex {
+    /// An index if one was requested, creating the file right away so a bad path fails
+    /// before any conversion work
+    fn new(options: &ConvertOptions) -> Result<Option<Self>> {
+        let Some(path) = &options.index else {
+            return Ok(None);
+        };
+        let file = File::create(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to create index file: {}", path))?;
+        Ok(Some(Self {
+            path: path.clone(),
+            writer: BufWriter::new(file),
+            outputs: Vec::new(),
+        }))
+    }
+
+    /// Take the outputs the last conversion wrote
+    fn record(&mut self, options: &ConvertOptions) {
+        self.outputs.append(&mut options.indexed.borrow_mut());
+    }
+
+    /// Name the entries the inputs didn't, sorted by output path, and write the index
+    fn finish(mut self, hashes: &LazyHashProvider) -> Result<()> {
+        if self
+            .outputs
+            .iter()
+            .any(|output| output.entries.iter().any(|(_, name)| name.is_none()))
+            && let Some(provider) = hashes.get()?
+        {
+            for (hash, name) in self
+                .outputs
+                .iter_mut()
+                .flat_map(|output| &mut output.entries)
+            {
+                if name.is_none() {
+                    *name = provider.lookup_entry(*hash).map(str::to_string);
+                }
+            }
+        }
+        self.outputs.sort_by(|a, b| a.output.cmp(&b.output));
+
+        let entry_count: usize = self.outputs.iter().map(|output| output.entries.len()).sum();
+        if self.path.extension() == Some("json") {
+            let index: BTreeMap<&str, Vec<IndexEntry>> = self
+                .outputs
+                .iter()
+                .map(|output| {
+                    let entries = output
+                        .entries
+                        .iter()
+                        .map(|(hash, name)| IndexEntry {
+                            hash: format_hash(*hash),
+                            name: name.as_deref(),
+                        })
+                        .collect();
+                    (output.output.as_str(), entries)
+                })
+                .collect();
+            serde_json::to_writer_pretty(&mut self.writer, &index)
+                .into_diagnostic()
+                .and_then(|()| writeln!(self.writer).into_diagnostic())
+                .wrap_err_with(|| format!("Failed to write index file: {}", self.path))?;
+        } else {
+            writeln!(self.writer, "output\thash\tname")
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to write index file: {}", self.path))?;
+            for output in &self.outputs {
+                for (hash, name) in &output.entries {
+                    writeln!(
+                        self.writer,
+                        "{}\t{}\t{}",
+                        output.output,
+                        format_hash(*hash),
+                        name.as_deref().unwrap_or("")
+                    )
+                    .into_diagnostic()
+                    .wrap_err_with(|| format!("Failed to write index file: {}", self.path))?;
+                }
+            }
+        }
+        self.writer
+            .flush()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write index file: {}", self.path))?;
+
+        tracing::info!(
+            "Wrote index of {} entries in {} file(s) to {}",
+            entry_count,
+            self.outputs.len(),
+            hyperlink_path(&self.path)
+        );
+        Ok(())
+    }
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+/// Quote a CSV field if it contains a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Parses a `--since` cutoff, either relative to now (`90s`, `30m`, `2h`, `3d`, `1w`)
+/// or an RFC 3339 timestamp like `2024-05-01T12:00:00Z`
+pub fn parse_since(value: &str) -> Result<SystemTime, String> {
+    if let Ok(timestamp) = OffsetDateTime::parse(value, &Rfc3339) {
+        return Ok(timestamp.into());
+    }
+
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+    let seconds_per_unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => {
+            return Err(format!(
+                "invalid time '{}', expected a duration like 2h or 3d, or an RFC 3339 timestamp",
+                value
+            ));
+        }
+    };
+    let amount: u64 = digits
+        .parse()
+        .map_err(|_| format!("invalid duration '{}'", value))?;
+
+    amount
+        .checked_mul(seconds_per_unit)
+        .and_then(|seconds| SystemTime::now().checked_sub(Duration::from_secs(seconds)))
+        .ok_or_else(|| format!("duration '{}' is too large", value))
+}
+
+/// A command run for every converted file, e.g. `wadpack add out.wad {output}`.
+///
+/// The command is split into arguments like a POSIX shell would (quotes group words),
+/// then `{input}` and `{output}` are replaced in every argument. It is run directly
+/// rather than through a shell, so paths with spaces stay single arguments.
+#[derive(Debug, Clone)]
+pub struct ExecCommand {
+    program: String,
+    args: Vec<String>,
+}
+
+impl ExecCommand {
+    /// Parses a command line, rejecting empty commands and unclosed quotes
+    pub fn parse(command: &str) -> Result<Self, String> {
+        let mut words = shlex::split(command)
+            .ok_or_else(|| format!("invalid command '{}': unclosed quote", command))?
+            .into_iter();
+        let program = words
+            .next()
+            .ok_or_else(|| "command must not be empty".to_string())?;
+
+        Ok(Self {
+            program,
+            args: words.collect(),
+        })
+    }
+
+    /// Run the command for one conversion, failing if it can't be started or exits non-zero
+    fn run(&self, input_path: &Utf8Path, output_path: &Utf8Path) -> Result<()> {
+        let substitute = |word: &str| {
+            word.replace("{input}", input_path.as_str())
+                .replace("{output}", output_path.as_str())
+        };
+        let program = substitute(&self.program);
+        let args: Vec<String> = self.args.iter().map(|arg| substitute(arg)).collect();
+
+        tracing::debug!("Running {} {}", program, args.join(" "));
+        let status = Command::new(&program)
+            .args(&args)
+            .status()
+            .map_err(|e| miette::miette!("Failed to run --exec command '{}': {}", program, e))?;
+
+        if !status.success() {
+            return Err(miette::miette!(
+                "--exec command '{}' failed for {}: {}",
+                program,
+                output_path,
+                status
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// A piece of a parsed [`NameTemplate`]
+#[derive(Debug, Clone)]
+enum TemplateSegment {
+    Literal(String),
+    Stem,
+    Ext,
+    Dir,
+    Hash8,
+}
+
+/// Output naming template, e.g. `{stem}.generated.{ext}` or `{dir}/{stem}_{hash8}.bin`.
+///
+/// Placeholders:
+/// - `{stem}`: input file name without extension
+/// - `{ext}`: extension of the output format (`py` or `bin`)
+/// - `{dir}`: directory of the input file
+/// - `{hash8}`: first 8 hex digits of the SHA-256 of the output
+///
+/// Templates without `{dir}` are resolved relative to the input file's directory.
+#[derive(Debug, Clone)]
+pub struct NameTemplate {
+    segments: Vec<TemplateSegment>,
+}
+
+impl NameTemplate {
+    /// Parses and validates a template, rejecting unknown or unclosed placeholders
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut rest = template;
+
+        while let Some(start) = rest.find('{') {
+            if start > 0 {
+                segments.push(TemplateSegment::Literal(rest[..start].to_string()));
+            }
+            let Some(len) = rest[start..].find('}') else {
+                return Err(format!("unclosed '{{' in name template '{}'", template));
+            };
+            segments.push(match &rest[start + 1..start + len] {
+                "stem" => TemplateSegment::Stem,
+                "ext" => TemplateSegment::Ext,
+                "dir" => TemplateSegment::Dir,
+                "hash8" => TemplateSegment::Hash8,
+                other => {
+                    return Err(format!(
+                        "unknown placeholder '{{{}}}', expected {{stem}}, {{ext}}, {{dir}} or {{hash8}}",
+                        other
+                    ));
+                }
+            });
+            rest = &rest[start + len + 1..];
+        }
+        if !rest.is_empty() {
+            segments.push(TemplateSegment::Literal(rest.to_string()));
+        }
+
+        // Without a per-file component every output in a directory would collide
+        let is_unique = segments
+            .iter()
+            .any(|s| matches!(s, TemplateSegment::Stem | TemplateSegment::Hash8));
+        if !is_unique {
+            return Err("name template must contain {stem} or {hash8}".to_string());
+        }
+
+        Ok(Self { segments })
+    }
+
+    /// Whether rendering needs the output bytes, i.e. the template uses `{hash8}`
+    fn needs_content(&self) -> bool {
+        self.segments
+            .iter()
+            .any(|s| matches!(s, TemplateSegment::Hash8))
+    }
+
+    /// Renders the output path for `input_path` converted to `bytes` with extension `ext`
+    fn render(&self, input_path: &Utf8Path, ext: &str, bytes: &[u8]) -> Utf8PathBuf {
+        let stem = input_path.file_stem().unwrap_or("output");
+        let parent = input_path
+            .parent()
+            .filter(|p| !p.as_str().is_empty())
+            .unwrap_or(Utf8Path::new("."));
+
+        let mut rendered = String::new();
+        let mut has_dir = false;
+        for segment in &self.segments {
+            match segment {
+                TemplateSegment::Literal(text) => rendered.push_str(text),
+                TemplateSegment::Stem => rendered.push_str(stem),
+                TemplateSegment::Ext => rendered.push_str(ext),
+                TemplateSegment::Dir => {
+                    has_dir = true;
+                    rendered.push_str(parent.as_str());
+                }
+                TemplateSegment::Hash8 => {
+                    let digest = format!("{:x}", Sha256::digest(bytes));
+                    rendered.push_str(&digest[..8]);
+                }
+            }
+        }
+
+        if has_dir {
+            Utf8PathBuf::from(rendered)
+        } else {
+            parent.join(rendered)
+        }
+    }
+}
+
+/// Convert between .bin (binary) and .py/.ritobin (text) formats.
+///
+/// - .bin -> .py: Converts binary bin file to ritobin text format
+/// - .py/.ritobin -> .bin: Parses ritobin text and converts to binary format
+///
+/// If input is a directory:
+/// - With recursive=true: converts all matching files in subdirectories
+/// - With recursive=false: converts only files in the immediate directory
+///
+/// If input is not an existing path but contains glob metacharacters (e.g. `data/**/*.bin`),
+/// it is expanded and every matching file is converted.
+///
+/// With `bundle`, every .bin input is converted into one text file at `output` instead.
+pub fn convert(
+    input: Option<String>,
+    output: Option<String>,
+    recursive: bool,
+    mut options: ConvertOptions,
+) -> Result<()> {
+    if let Some(root) = &options.resolve_links
+        && !root.is_dir()
+    {
+        return Err(Failure::Usage(format!(
+            "--resolve-links needs a directory to look up linked bins in, {} is not one",
+            root
+        ))
+        .into());
+    }
+
+    let (config, _) = load_or_create_config()?;
+    options.input_extensions = InputExtensions::from_config(&config);
+    options.text_extension = options.text_extension.or(config.text_extension);
+    let hashes = LazyHashProvider::new(config.hashtable_dir, config.combined_hashtable);
+
+    if let Some(files_from) = options.files_from.clone() {
+        return convert_listed(&files_from, input.as_deref(), recursive, options, &hashes);
+    }
+    let input = input
+        .ok_or_else(|| Failure::Usage("An input is required without --files-from".to_string()))?;
+    let input_path = Utf8Path::new(&input);
+
+    if options.relative_paths {
+        options.path_base = Some(if input_path.is_dir() {
+            input_path.to_path_buf()
+        } else {
+            Utf8PathBuf::from(".")
+        });
+    }
+
+    if let Some(max_depth) = options.max_depth
+        && recursive
+    {
+        tracing::warn!("--max-depth {} overrides --recursive", max_depth);
+    }
+
+    if output.as_deref() == Some(STDOUT_OUTPUT) {
+        if options.sha256 || options.multi || options.print_output_path {
+            return Err(Failure::Usage(
+                "--output - cannot be combined with --sha256, --multi or --print-output-path"
+                    .to_string(),
+            )
+            .into());
+        }
+        if input_path.is_dir() && !options.bundle {
+            return Err(Failure::Usage(
+                "--output - needs a single input file, or --bundle".to_string(),
+            )
+            .into());
+        }
+    }
+
+    if options.count {
+        return if input_path.is_dir() {
+            count_batch(options.directory_paths(input_path, recursive), &options)
+        } else if !input_path.exists() && is_glob_pattern(&input) {
+            count_batch(glob_paths(&input, options.include_hidden)?, &options)
+        } else {
+            // A single file is converted regardless of the batch filters
+            count_files([input_path.to_path_buf()])
+        };
+    }
+
+    if options.bundle {
+        let output = output
+            .map(Utf8PathBuf::from)
+            .ok_or_else(|| miette::miette!("--bundle requires --output"))?;
+        let paths = if input_path.is_dir() {
+            options.directory_paths(input_path, recursive)
+        } else if !input_path.exists() && is_glob_pattern(&input) {
+            glob_paths(&input, options.include_hidden)?
+        } else {
+            vec![input_path.to_path_buf()]
+        };
+        return bundle_bins(paths, &output, &options, &hashes);
+    }
+
+    let is_glob = !input_path.exists() && is_glob_pattern(&input);
+    if input_path.is_dir() || is_glob {
+        if output.is_some() {
+            return Err(Failure::Usage(
+                "--output names a single output file, use --output-dir to convert a directory or glob pattern into a directory".to_string(),
+            )
+            .into());
+        }
+        options.input_root = Some(if is_glob {
+            glob_root(&input)
+        } else {
+            input_path.to_path_buf()
+        });
+    } else if options.output_dir.is_some() {
+        return Err(Failure::Usage(
+            "--output-dir needs a directory or glob pattern input, use --output for a single file"
+                .to_string(),
+        )
+        .into());
+    }
+
+    if options.flat {
+        let output_dir = options
+            .output_dir
+            .clone()
+            .ok_or_else(|| miette::miette!("--flat requires --output-dir"))?;
+        let (paths, root) = if input_path.is_dir() {
+            (options.directory_paths(input_path, recursive), input_path)
+        } else if !input_path.exists() && is_glob_pattern(&input) {
+            (
+                glob_paths(&input, options.include_hidden)?,
+                Utf8Path::new(""),
+            )
+        } else {
+            return Err(Failure::Usage(
+                "--flat needs a directory or glob pattern input".to_string(),
+            )
+            .into());
+        };
+        let paths: Vec<Utf8PathBuf> = paths
+            .into_iter()
+            .filter(|path| is_batch_candidate(path, &options))
+            .collect();
+
+        options.flat_prefixes = flat_prefixes(&paths, root, &options)?;
+        fs::create_dir_all(&output_dir)
+            .write_access(OUTPUT_DIR_HINT)
+            .wrap_err_with(|| format!("Failed to create output directory: {}", output_dir))?;
+        return convert_batch(paths, &options, &hashes);
+    }
+
+    if input_path.is_dir() {
+        convert_directory(input_path, recursive, &options, &hashes)
+    } else if is_glob {
+        convert_glob(&input, &options, &hashes)
+    } else if options.index.is_some() {
+        Err(Failure::Usage("--index needs a directory or glob pattern input".to_string()).into())
+    } else {
+        let report = TimingsReport::new(&options)?;
+        let result = convert_file(input_path, output.map(Utf8PathBuf::from), &options, &hashes)?;
+        if let Some(mut report) = report {
+            report.record(input_path, result.timings)?;
+            report.finish(&options)?;
+        }
+        Ok(())
+    }
+}
+
+/// Convert the files listed in `files_from` (see [`read_file_list`]), plus the files of
+/// `input` when one is given. Each file is converted once, in the order it was listed.
+fn convert_listed(
+    files_from: &str,
+    input: Option<&str>,
+    recursive: bool,
+    mut options: ConvertOptions,
+    hashes: &LazyHashProvider,
+) -> Result<()> {
+    if options.relative_paths {
+        options.path_base = Some(Utf8PathBuf::from("."));
+    }
+
+    let mut paths = read_file_list(files_from)?;
+    if let Some(input) = input {
+        let input_path = Utf8Path::new(input);
+        if input_path.is_dir() {
+            paths.extend(options.directory_paths(input_path, recursive));
+        } else if !input_path.exists() && is_glob_pattern(input) {
+            paths.extend(glob_paths(input, options.include_hidden)?);
+        } else {
+            paths.push(input_path.to_path_buf());
+        }
+    }
+    let mut seen = HashSet::new();
+    paths.retain(|path| seen.insert(path.clone()));
+
+    if options.count {
+        return count_batch(paths, &options);
+    }
+    convert_batch(paths, &options, hashes)
+}
+
+/// Output file name of a batch input before any name template, .bin becoming text and
+/// text becoming .bin
+fn flat_name(path: &Utf8Path, options: &ConvertOptions) -> String {
+    let ext = if options.input_extensions.kind(path) == Some(InputKind::Bin) {
+        options.text_extension.unwrap_or_default().as_str()
+    } else {
+        "bin"
+    };
+    format!("{}.{}", path.file_stem().unwrap_or("output"), ext)
+}
+
+/// Prefixes that keep the `--flat` outputs of `paths` from overwriting each other.
+/// Inputs whose output names collide are prefixed with their parent directories below
+/// `root`, joined with `_`, or rejected with [`OnCollision::Error`]. A prefixed name can
+/// collide with another input's name in turn, which then gets prefixed too, so the
+/// result doesn't depend on the order of the inputs.
+fn flat_prefixes(
+    paths: &[Utf8PathBuf],
+    root: &Utf8Path,
+    options: &ConvertOptions,
+) -> Result<HashMap<Utf8PathBuf, String>> {
+    // Every input with its output name and, once it collided, its prefix
+    let mut outputs: Vec<(&Utf8PathBuf, String, Option<String>)> = paths
+        .iter()
+        .map(|path| (path, flat_name(path, options), None))
+        .collect();
+    let output_name = |(_, name, prefix): &(&Utf8PathBuf, String, Option<String>)| match prefix {
+        Some(prefix) if !prefix.is_empty() => format!("{}_{}", prefix, name),
+        _ => name.clone(),
+    };
+
+    loop {
+        let mut by_name: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+        for (index, output) in outputs.iter().enumerate() {
+            by_name.entry(output_name(output)).or_default().push(index);
+        }
+        let collisions: Vec<(String, Vec<usize>)> = by_name
+            .into_iter()
+            .filter(|(_, group)| group.len() > 1)
+            .collect();
+        let Some((name, group)) = collisions.first() else {
+            break;
+        };
+        if options.on_collision == OnCollision::Error {
+            return Err(miette::miette!(
+                "{} and {} would both be written as {}, use --on-collision rename to prefix them with their directories",
+                outputs[group[0]].0,
+                outputs[group[1]].0,
+                name
+            ));
+        }
+
+        for (name, group) in &collisions {
+            let unprefixed: Vec<usize> = group
+                .iter()
+                .copied()
+                .filter(|&index| outputs[index].2.is_none())
+                .collect();
+            if unprefixed.is_empty() {
+                return Err(miette::miette!(
+                    "{} and {} would both be written as {} even with their directories as prefix",
+                    outputs[group[0]].0,
+                    outputs[group[1]].0,
+                    name
+                ));
+            }
+            for index in unprefixed {
+                let path = outputs[index].0;
+                outputs[index].2 = Some(directory_prefix(path, root));
+            }
+        }
+    }
+
+    Ok(outputs
+        .into_iter()
+        .filter_map(|(path, _, prefix)| Some((path.clone(), prefix?)))
+        .filter(|(_, prefix)| !prefix.is_empty())
+        .collect())
+}
+
+/// The parent directories of `path` below `root`, joined with `_`
+fn directory_prefix(path: &Utf8Path, root: &Utf8Path) -> String {
+    let parent = path.parent().unwrap_or(Utf8Path::new(""));
+    parent
+        .strip_prefix(root)
+        .unwrap_or(parent)
+        .components()
+        .filter_map(|component| match component {
+            Utf8Component::Normal(name) => Some(name),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// Whether the input contains glob metacharacters
+pub(crate) fn is_glob_pattern(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+/// Convert all matching files in a directory
+fn convert_directory(
+    dir_path: &Utf8Path,
+    recursive: bool,
+    options: &ConvertOptions,
+    hashes: &LazyHashProvider,
+) -> Result<()> {
+    convert_batch(
+        options.directory_paths(dir_path, recursive),
+        options,
+        hashes,
+    )
+}
+
+/// All UTF-8 paths under a directory, including the directory itself.
+/// `max_depth` is passed to [`WalkDir::max_depth`], `None` walks the whole tree.
+/// With `follow_symlinks`, symlinked directories are walked too and symlinks that
+/// lead back into one of their own parents are skipped with a warning.
+/// Hidden entries (names starting with `.`, like `.git`) are skipped along with everything
+/// below them unless `include_hidden` is set. `dir_path` itself is always walked.
+/// Entries are sorted by file name within each directory, so batches process files and
+/// log them in the same order on every run and platform.
+pub(crate) fn directory_paths(
+    dir_path: &Utf8Path,
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    include_hidden: bool,
+) -> Vec<Utf8PathBuf> {
+    let mut walker = WalkDir::new(dir_path)
+        .follow_links(follow_symlinks)
+        .sort_by_file_name();
+    if let Some(max_depth) = max_depth {
+        walker = walker.max_depth(max_depth);
+    }
+
+    walker
+        .into_iter()
+        .filter_entry(|entry| include_hidden || entry.depth() == 0 || !is_hidden(entry.file_name()))
+        .filter_map(|entry| {
+            entry
+                .inspect_err(|e| {
+                    if let (Some(path), Some(ancestor)) = (e.path(), e.loop_ancestor()) {
+                        tracing::warn!(
+                            "Skipping symlink cycle: {} points back to {}",
+                            path.display(),
+                            ancestor.display()
+                        );
+                    }
+                })
+                .ok()
+        })
+        .filter_map(|entry| {
+            // Convert to Utf8Path, skip non-UTF8 paths
+            let path = Utf8Path::from_path(entry.path());
+            if path.is_none() {
+                tracing::warn!("Skipping non-UTF8 path: {}", entry.path().display());
+            }
+            path.map(Utf8Path::to_path_buf)
+        })
+        .collect()
+}
+
+/// Whether a file or directory name marks it as hidden
+pub(crate) fn is_hidden(file_name: &OsStr) -> bool {
+    file_name.as_encoded_bytes().starts_with(b".")
+}
+
+/// Convert all files matching a glob pattern
+fn convert_glob(pattern: &str, options: &ConvertOptions, hashes: &LazyHashProvider) -> Result<()> {
+    convert_batch(
+        glob_paths(pattern, options.include_hidden)?,
+        options,
+        hashes,
+    )
+}
+
+/// The directory a glob pattern matches in: its leading components without wildcards
+fn glob_root(pattern: &str) -> Utf8PathBuf {
+    Utf8Path::new(pattern)
+        .components()
+        .take_while(|component| !is_glob_pattern(component.as_str()))
+        .collect()
+}
 
-/// Convert between .bin (binary) and .py/.ritobin (text) formats.
-///
-/// - .bin -> .py: Converts binary bin file to ritobin text format
-/// - .py/.ritobin -> .bin: Parses ritobin text and converts to binary format
-///
-/// If input is a directory:
-/// - With recursive=true: converts all matching files in subdirectories
-/// - With recursive=false: converts only files in the immediate directory
-pub fn convert(input: String, output: Option<String>, recursive: bool) -> Result<()> {
-    let input_path = Utf8Path::new(&input);
+/// Expand a glob pattern, failing if nothing matches.
+/// Unless `include_hidden` is set, wildcards don't match names starting with `.`,
+/// hidden paths are only matched where the pattern spells out the dot.
+pub(crate) fn glob_paths(pattern: &str, include_hidden: bool) -> Result<Vec<Utf8PathBuf>> {
+    let match_options = glob::MatchOptions {
+        require_literal_leading_dot: !include_hidden,
+        ..Default::default()
+    };
+    let entries = glob::glob_with(pattern, match_options)
+        .map_err(|e| miette::miette!("Invalid glob pattern '{}': {}", pattern, e))?;
 
-    if input_path.is_dir() {
-        convert_directory(input_path, recursive)
-    } else {
-        convert_file(input_path, output.map(Utf8PathBuf::from))
+    let paths: Vec<Utf8PathBuf> = entries
+        .filter_map(|entry| match entry {
+            Ok(path) => Utf8PathBuf::from_path_buf(path)
+                .inspect_err(|p| tracing::warn!("Skipping non-UTF8 path: {}", p.display()))
+                .ok(),
+            Err(e) => {
+                tracing::warn!("Skipping unreadable path: {}", e);
+                None
+            }
+        })
+        .collect();
+
+    if paths.is_empty() {
+        return Err(miette::miette!("No files match '{}'", pattern));
+    }
+
+    Ok(paths)
+}
+
+/// Whether `path` was modified at or after `since`. Files without a readable
+/// modification time are kept, so they are never silently left out.
+fn modified_since(path: &Utf8Path, since: SystemTime) -> bool {
+    match path.metadata().and_then(|metadata| metadata.modified()) {
+        Ok(modified) => modified >= since,
+        Err(_) => true,
     }
 }
 
-/// Convert all matching files in a directory
-fn convert_directory(dir_path: &Utf8Path, recursive: bool) -> Result<()> {
-    let walker = if recursive {
-        WalkDir::new(dir_path)
-    } else {
-        WalkDir::new(dir_path).max_depth(1)
-    };
+/// Convert every .bin in `paths` to ritobin text and write them to one file,
+/// separated by the document separator line
+fn bundle_bins(
+    paths: Vec<Utf8PathBuf>,
+    output_path: &Utf8Path,
+    options: &ConvertOptions,
+    hashes: &LazyHashProvider,
+) -> Result<()> {
+    let mut bin_paths: Vec<_> = paths
+        .into_iter()
+        .filter(|p| p.is_file() && options.input_extensions.kind(p) == Some(InputKind::Bin))
+        .collect();
+    bin_paths.sort();
+
+    if bin_paths.is_empty() {
+        return Err(miette::miette!("No .bin files to bundle"));
+    }
+
+    let separator = options.separator();
+    let mut bundle = String::new();
+    for path in &bin_paths {
+        let mut tree = bin_io::read_bin_file(path)?;
+        let names = FallbackProvider {
+            primary: hashes.get()?,
+            fallback: HashMapProvider::default(),
+        };
+        if !options.filter_entries(&options.display_path(path), &mut tree, &names)? {
+            continue;
+        }
+        options.check_schema(path, &tree, &names)?;
+        options.prune(&mut tree);
+        let (text, _) = options
+            .tree_to_text(&tree, hashes)
+            .wrap_err_with(|| format!("Failed to convert {}", path))?;
+
+        // Documents already start with the default separator (the ritobin header)
+        if separator != DEFAULT_DOCUMENT_SEPARATOR && !bundle.is_empty() {
+            bundle.push_str(separator);
+            bundle.push('\n');
+        }
+        bundle.push_str(&text);
+        if !bundle.ends_with('\n') {
+            bundle.push('\n');
+        }
+    }
 
+    let bundle = options.line_ending.apply(bundle);
+    let output_path = &options.text_output_path(output_path.to_path_buf());
+    options.write_text_output(output_path, &bundle)?;
+
+    tracing::info!(
+        "Bundled {} files -> {}",
+        bin_paths.len(),
+        options.display_path(output_path)
+    );
+    options.print_output(output_path);
+
+    Ok(())
+}
+
+/// Convert every file with a supported extension, continuing past individual failures.
+/// Ctrl-C stops the batch after the file being converted.
+fn convert_batch(
+    paths: impl IntoIterator<Item = Utf8PathBuf>,
+    options: &ConvertOptions,
+    hashes: &LazyHashProvider,
+) -> Result<()> {
     let mut converted_count = 0;
+    let mut skipped_count = 0;
     let mut error_count = 0;
+    let mut permission_count = 0;
+    let mut totals = ConversionResult::default();
+    let mut error_counts: HashMap<String, usize> = HashMap::new();
+    let mut interrupted = false;
+    let mut limited = false;
+    let mut report = TimingsReport::new(options)?;
+    let mut checkpoint = Checkpoint::open(options)?;
+    let mut index = EntryIndex::new(options)?;
 
-    for entry in walker.into_iter().filter_map(|e| e.ok()) {
-        // Convert to Utf8Path, skip non-UTF8 paths
-        let Some(path) = Utf8Path::from_path(entry.path()) else {
-            tracing::warn!("Skipping non-UTF8 path: {}", entry.path().display());
-            continue;
-        };
+    install_interrupt_handler();
 
-        // Skip directories
-        if path.is_dir() {
-            continue;
+    for path in paths {
+        if stop_requested() {
+            interrupted = true;
+            break;
         }
 
-        // Check if file has a supported extension
-        let extension = path.extension().unwrap_or("");
+        if !is_batch_candidate(&path, options) {
+            continue;
+        }
 
-        if !SUPPORTED_EXTENSIONS.contains(&extension) {
+        if checkpoint.as_ref().is_some_and(|c| c.is_done(&path)) {
+            tracing::debug!("Skipping {} (in checkpoint)", path);
             continue;
         }
 
+        if options
+            .limit
+            .is_some_and(|limit| converted_count + error_count >= limit)
+        {
+            limited = true;
+            break;
+        }
+
         // Convert the file
-        match convert_file(path, None) {
-            Ok(()) => converted_count += 1,
+        let result = convert_file(&path, None, options, hashes);
+        // Even a failed conversion may have written outputs, e.g. when `--exec` fails
+        if let Some(index) = &mut index {
+            index.record(options);
+        }
+        match result {
+            Ok(result) if result.output_paths.is_empty() => {
+                skipped_count += 1;
+                if let Some(checkpoint) = &mut checkpoint {
+                    checkpoint.record(&path)?;
+                }
+            }
+            Ok(result) => {
+                converted_count += 1;
+                if let Some(report) = &mut report {
+                    report.record(&path, result.timings)?;
+                }
+                if let Some(checkpoint) = &mut checkpoint {
+                    checkpoint.record(&path)?;
+                }
+                totals += result;
+            }
             Err(e) => {
-                tracing::error!("Failed to convert {}: {}", path, e);
+                // Only the first file per distinct cause is reported loudly,
+                // large batches tend to fail the same way thousands of times
+                let cause = root_cause(&e);
+                let count = error_counts.entry(cause.clone()).or_insert(0);
+                if *count == 0 {
+                    tracing::error!(
+                        "Failed to convert {}: {}",
+                        options.display_path(&path),
+                        cause
+                    );
+                } else {
+                    tracing::debug!(
+                        "Failed to convert {}: {}",
+                        options.display_path(&path),
+                        cause
+                    );
+                }
+                *count += 1;
                 error_count += 1;
+                if is_permission_denied(&e) {
+                    permission_count += 1;
+                }
             }
         }
     }
 
-    tracing::info!(
-        "Conversion complete: {} files converted, {} errors",
-        converted_count,
-        error_count
-    );
+    if interrupted {
+        tracing::warn!(
+            "Interrupted: {} files converted, {} errors before stopping",
+            converted_count,
+            error_count
+        );
+    } else if limited {
+        tracing::info!(
+            "Stopped early at --limit: {} files converted, {} errors, more files match",
+            converted_count,
+            error_count
+        );
+    } else {
+        tracing::info!(
+            "Conversion complete: {} files converted, {} errors",
+            converted_count,
+            error_count
+        );
+    }
+    if permission_count > 0 {
+        tracing::warn!(
+            "{} of the errors were denied permissions, fix access to the inputs and output \
+             directories rather than the files themselves",
+            permission_count
+        );
+    }
+    if skipped_count > 0 {
+        tracing::info!(
+            "Skipped {} file(s) without entries matching --entry-prefix",
+            skipped_count
+        );
+    }
+    if converted_count > 0 {
+        tracing::info!(
+            "Read {} and wrote {}, {} hash(es) written as hex, {} warning(s)",
+            HumanBytes(totals.bytes_in),
+            HumanBytes(totals.bytes_out),
+            totals.unresolved_hashes,
+            totals.warnings.len()
+        );
+    }
+    if totals.deduplicated > 0 {
+        tracing::info!(
+            "Linked {} output(s) identical to an earlier one, saving {}",
+            totals.deduplicated,
+            HumanBytes(totals.bytes_saved)
+        );
+    }
+
+    if let Some(report) = report {
+        report.finish(options)?;
+    }
+    if let Some(index) = index {
+        index.finish(hashes)?;
+    }
+
+    if error_count > 1 {
+        let mut counts: Vec<_> = error_counts.into_iter().collect();
+        counts.sort_by(|(a_cause, a), (b_cause, b)| b.cmp(a).then(a_cause.cmp(b_cause)));
+
+        tracing::error!("Errors by cause:");
+        for (cause, count) in counts {
+            tracing::error!("{:>6}× {}", count, cause);
+        }
+    }
 
     if error_count > 0 {
-        Err(miette::miette!("{} file(s) failed to convert", error_count))
+        Err(Failure::Partial(format!("{} file(s) failed to convert", error_count)).into())
+    } else if interrupted {
+        Err(miette::miette!("Conversion interrupted"))
     } else {
         Ok(())
     }
 }
 
-/// Convert a single file based on its extension
-fn convert_file(input_path: &Utf8Path, output: Option<Utf8PathBuf>) -> Result<()> {
-    let extension = input_path.extension().unwrap_or("");
+/// Whether a directory or glob run converts `path`: a file with a supported extension
+/// that passes `--only` and `--since`
+fn is_batch_candidate(path: &Utf8Path, options: &ConvertOptions) -> bool {
+    // Skip directories
+    if path.is_dir() {
+        return false;
+    }
+
+    // Check if file has a supported extension
+    let extension = path.extension().unwrap_or("");
+    let Some(kind) = options.input_extensions.kind(path) else {
+        return false;
+    };
+
+    // Configured extensions also count as `bin` or `py`
+    if !options.only.is_empty()
+        && !options
+            .only
+            .iter()
+            .any(|only| only == extension || only == kind.only_name())
+    {
+        tracing::debug!("Skipping {} (not in --only)", path);
+        return false;
+    }
+
+    if let Some(since) = options.since
+        && !modified_since(path, since)
+    {
+        tracing::debug!("Skipping {} (not modified since --since)", path);
+        return false;
+    }
+
+    true
+}
+
+/// Report the files a batch run would convert, without converting them
+fn count_batch(
+    paths: impl IntoIterator<Item = Utf8PathBuf>,
+    options: &ConvertOptions,
+) -> Result<()> {
+    count_files(
+        paths
+            .into_iter()
+            .filter(|path| is_batch_candidate(path, options))
+            .take(options.limit.unwrap_or(usize::MAX)),
+    )
+}
+
+/// Log how many files there are and their total size, per input extension
+fn count_files(paths: impl IntoIterator<Item = Utf8PathBuf>) -> Result<()> {
+    let mut by_extension: BTreeMap<String, (usize, u64)> = BTreeMap::new();
+    let mut file_count = 0;
+    let mut total_size = 0;
+
+    for path in paths {
+        let size = path
+            .metadata()
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read metadata: {}", path))?
+            .len();
+        let (count, bytes) = by_extension
+            .entry(path.extension().unwrap_or("").to_string())
+            .or_default();
+        *count += 1;
+        *bytes += size;
+        file_count += 1;
+        total_size += size;
+    }
+
+    tracing::info!(
+        "{} file(s) would be converted, {} in total",
+        file_count,
+        HumanBytes(total_size)
+    );
+    for (extension, (count, bytes)) in by_extension {
+        tracing::info!("{:>8}  .{} ({})", count, extension, HumanBytes(bytes));
+    }
+    Ok(())
+}
 
-    match extension {
-        "bin" => convert_bin_to_ritobin(input_path, output),
-        "py" | "ritobin" => convert_ritobin_to_bin(input_path, output),
-        _ => Err(miette::miette!(
-            "Unsupported input file extension: .{}. Supported extensions: .bin, .py, .ritobin",
-            extension
+/// The innermost cause of an error, which is free of per-file context like paths
+fn root_cause(report: &miette::Report) -> String {
+    report
+        .chain()
+        .last()
+        .map(|cause| cause.to_string())
+        .unwrap_or_else(|| report.to_string())
+}
+
+/// Convert a single file. The output extension picks the format when it names one
+/// (.bin, .py/.ritobin or .json), otherwise the input extension decides the direction.
+pub(crate) fn convert_file(
+    input_path: &Utf8Path,
+    output: Option<Utf8PathBuf>,
+    options: &ConvertOptions,
+    hashes: &LazyHashProvider,
+) -> Result<ConversionResult> {
+    let target = output.as_deref().and_then(target_from_output_ext);
+    // Left over from a file that failed part way
+    options.warnings.borrow_mut().clear();
+
+    let Some(kind) = options.input_extensions.kind(input_path) else {
+        return Err(miette::miette!(
+            "Unsupported input file extension: .{}. Supported extensions: {}",
+            input_path.extension().unwrap_or(""),
+            options.input_extensions.describe()
+        ));
+    };
+    let mut result = match (kind, target) {
+        (InputKind::Bin, None | Some(OutputFormat::Ritobin)) => {
+            convert_bin_to_ritobin(input_path, output, options, hashes)
+        }
+        (InputKind::Text, None | Some(OutputFormat::Bin)) if options.entries_depth.is_some() => {
+            Err(miette::miette!(
+                "--entries-only can't write .bin, it only applies to text and JSON output"
+            ))
+        }
+        (InputKind::Text, None | Some(OutputFormat::Bin)) => {
+            convert_ritobin_to_bin(input_path, output, options, hashes)
+        }
+        (_, Some(OutputFormat::Json)) => {
+            // A target only comes from an explicit output, so it is always set here
+            let output_path = output.unwrap_or_default();
+            convert_to_json(input_path, &output_path, options, hashes)
+        }
+        (InputKind::Bin, Some(OutputFormat::Bin)) => Err(miette::miette!(
+            "Input and output are both .bin, nothing to convert"
         )),
+        (InputKind::Text, Some(OutputFormat::Ritobin)) => Err(miette::miette!(
+            "Input and output are both ritobin text, use `fmt` to reformat it"
+        )),
+    }?;
+
+    result.bytes_in = fs::metadata(input_path).map_or(0, |m| m.len());
+    for path in result.output_paths.iter().filter(|p| *p != STDOUT_OUTPUT) {
+        // Not following links, only `dedup` writes them and they don't take the space of
+        // the file they point to
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            continue;
+        };
+        result.bytes_out += metadata.len();
+        if options.dedup && metadata.is_symlink() {
+            result.deduplicated += 1;
+            result.bytes_saved += fs::metadata(path).map_or(0, |m| m.len());
+        }
     }
+    result.warnings = options.warnings.take();
+    Ok(result)
 }
 
-/// Convert a .bin file to ritobin text format (.py)
-fn convert_bin_to_ritobin(input_path: &Utf8Path, output: Option<Utf8PathBuf>) -> Result<()> {
-    let (config, _) = load_or_create_config()?;
+/// Load a .bin input, memory-mapped with `--mmap`
+fn read_bin_input(input_path: &Utf8Path, options: &ConvertOptions) -> Result<BinTree> {
+    if !options.mmap {
+        return bin_io::read_bin_file(input_path);
+    }
 
-    // Load the .bin file
     let file = File::open(input_path)
-        .into_diagnostic()
+        .read_access()
         .wrap_err_with(|| format!("Failed to open input file: {}", input_path))?;
-    let mut reader = BufReader::new(file);
 
-    let tree = BinTree::from_reader(&mut reader)
+    // SAFETY: the mapping is read-only and dropped right after parsing. If another
+    // process truncates the file meanwhile, reads fault instead of erroring, which
+    // is why mapping is opt-in.
+    let map = unsafe { Mmap::map(&file) }
         .into_diagnostic()
-        .wrap_err("Failed to parse .bin file")?;
+        .wrap_err_with(|| format!("Failed to memory-map input file: {}", input_path))?;
+    bin_io::read_bin_bytes(&map)
+}
+
+/// Convert a .bin file to ritobin text format (.py)
+fn convert_bin_to_ritobin(
+    input_path: &Utf8Path,
+    output: Option<Utf8PathBuf>,
+    options: &ConvertOptions,
+    hashes: &LazyHashProvider,
+) -> Result<ConversionResult> {
+    let mut timings = FileTimings::default();
+
+    // Load the .bin file
+    let started = Instant::now();
+    let mut tree = read_bin_input(input_path, options)?;
+    timings.parse = started.elapsed();
+
+    let names = FallbackProvider {
+        primary: hashes.get()?,
+        fallback: HashMapProvider::default(),
+    };
+    if !options.filter_entries(&options.display_path(input_path), &mut tree, &names)? {
+        return Ok(ConversionResult::default());
+    }
+    options.check_schema(input_path, &tree, &names)?;
+    options.prune(&mut tree);
 
     // Convert to ritobin text format using hashtable provider if available,
     // otherwise fall back to hex hash provider
-    let ritobin_text = if let Some(hashtable_dir) = config.hashtable_dir.as_ref() {
-        let mut hashtable_provider = HashMapProvider::new();
-        hashtable_provider.load_from_directory(hashtable_dir);
-
-        ltk_ritobin::write_with_config_and_hashes(
+    let started = Instant::now();
+    let (mut ritobin_text, unresolved_hashes) = options
+        .tree_to_text(&tree, hashes)
+        .wrap_err_with(|| format!("Failed to convert {}", input_path))?;
+    if options.header {
+        let comment = provenance_comment(input_path, hashes)?;
+        ritobin_text = insert_after_header(&ritobin_text, &comment);
+    }
+    if let Some(root) = &options.resolve_links {
+        ritobin_text.push_str(&linked_bins_summary(
             &tree,
-            WriterConfig::default(),
-            &hashtable_provider,
-        )
-    } else {
-        ltk_ritobin::write_with_config_and_hashes(&tree, WriterConfig::default(), &HexHashProvider)
+            root,
+            &names,
+            &options.display_path(input_path),
+        ));
     }
-    .into_diagnostic()
-    .wrap_err("Failed to convert to ritobin format")?;
+    let ritobin_text = options.line_ending.apply(ritobin_text);
+    timings.serialize = started.elapsed();
 
-    // Determine output path
-    let output_path = output.unwrap_or_else(|| {
-        // Replace .bin extension with .py (ritobin text format)
-        let stem = input_path.file_stem().unwrap_or("output");
-        let parent = input_path.parent().unwrap_or(Utf8Path::new("."));
-        parent.join(format!("{}.py", stem))
-    });
+    // Determine output path, .bin is replaced with .py or .ritobin (ritobin text format)
+    let ext = options.text_extension.unwrap_or_default().as_str();
+    let output_path = output
+        .unwrap_or_else(|| default_output_path(input_path, ext, ritobin_text.as_bytes(), options));
+    let output_path = options.text_output_path(output_path);
 
-    // Write output file
-    let output_file = File::create(&output_path)
-        .into_diagnostic()
-        .wrap_err_with(|| format!("Failed to create output file: {}", output_path))?;
-    let mut writer = BufWriter::new(output_file);
+    let started = Instant::now();
+    if options.preserve_bom && output_path != STDOUT_OUTPUT && bin_io::file_has_bom(&output_path) {
+        let text = format!("{}{}", bin_io::UTF8_BOM, ritobin_text);
+        options.write_text_output(&output_path, &text)?;
+    } else {
+        options.write_text_output(&output_path, &ritobin_text)?;
+    }
+    timings.write = started.elapsed();
 
-    writer
-        .write_all(ritobin_text.as_bytes())
-        .into_diagnostic()
-        .wrap_err("Failed to write output file")?;
+    options.index_entries(&output_path, &tree, &names);
+    options.converted(input_path, &output_path)?;
+    Ok(ConversionResult::new(
+        output_path,
+        unresolved_hashes,
+        timings,
+    ))
+}
 
-    tracing::info!(
-        "Converted {} -> {}",
-        hyperlink_path(input_path),
-        hyperlink_path(&output_path)
-    );
+/// Comment line recording where text output came from, so a checked-in file can be traced
+/// back to its bin: the source path, the tool version, the time of conversion and the
+/// hashtables that named its hashes
+fn provenance_comment(input_path: &Utf8Path, hashes: &LazyHashProvider) -> Result<String> {
+    let now = OffsetDateTime::now_utc()
+        .replace_nanosecond(0)
+        .unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let date = now
+        .format(&Rfc3339)
+        .into_diagnostic()
+        .wrap_err("Failed to format the conversion date")?;
+    Ok(format!(
+        "# Converted from {} by ritobin-tools {} on {}, hashtables: {}\n",
+        input_path,
+        env!("CARGO_PKG_VERSION"),
+        date,
+        hashes.describe()?
+    ))
+}
 
-    Ok(())
+/// `text` with `line` inserted after the `#PROP_text` header, which has to stay first
+fn insert_after_header(text: &str, line: &str) -> String {
+    match text.split_once('\n') {
+        Some((first, rest)) if first.trim_end().starts_with(DEFAULT_DOCUMENT_SEPARATOR) => {
+            format!("{}\n{}{}", first, line, rest)
+        }
+        _ => format!("{}{}", line, text),
+    }
 }
 
 /// Convert a ritobin text file (.py/.ritobin) to binary .bin format
-fn convert_ritobin_to_bin(input_path: &Utf8Path, output: Option<Utf8PathBuf>) -> Result<()> {
+fn convert_ritobin_to_bin(
+    input_path: &Utf8Path,
+    output: Option<Utf8PathBuf>,
+    options: &ConvertOptions,
+    hashes: &LazyHashProvider,
+) -> Result<ConversionResult> {
+    let mut timings = FileTimings::default();
+
     // Read the ritobin text file
-    let mut file = File::open(input_path)
-        .into_diagnostic()
-        .wrap_err_with(|| format!("Failed to open input file: {}", input_path))?;
+    let started = Instant::now();
+    let ritobin_text = bin_io::read_text_file(input_path)?;
+    timings.parse = started.elapsed();
 
-    let mut ritobin_text = String::new();
-    file.read_to_string(&mut ritobin_text)
-        .into_diagnostic()
-        .wrap_err("Failed to read ritobin file")?;
+    if options.multi {
+        let mut result =
+            convert_multi_document(input_path, output, &ritobin_text, options, hashes)?;
+        result.timings.parse += timings.parse;
+        return Ok(result);
+    }
+
+    options.warn_lost_comments(input_path, &comment_lines(&ritobin_text));
+
+    let started = Instant::now();
+    if options.strict {
+        check_duplicate_keys(&options.display_path(input_path), &ritobin_text, 0)?;
+    }
 
     // Parse ritobin text to BinTree
-    let tree = ltk_ritobin::parse_to_bin_tree(&ritobin_text)
-        .into_diagnostic()
-        .wrap_err("Failed to parse ritobin file")?;
+    let mut tree = options.parse_text(input_path, &ritobin_text, 0)?;
+    timings.parse += started.elapsed();
 
-    // Determine output path
-    let output_path = output.unwrap_or_else(|| {
-        // Replace .py/.ritobin extension with .bin
-        let stem = input_path.file_stem().unwrap_or("output");
-        let parent = input_path.parent().unwrap_or(Utf8Path::new("."));
-        parent.join(format!("{}.bin", stem))
+    let text_names = names_from_text(&ritobin_text);
+    if !options.filter_entries(&options.display_path(input_path), &mut tree, &text_names)? {
+        return Ok(ConversionResult::default());
+    }
+    options.check_schema(input_path, &tree, &text_names)?;
+    options.check_known_names(input_path, &tree, &ritobin_text, 0, hashes)?;
+    drop(ritobin_text);
+
+    // Determine output path, .py/.ritobin is replaced with .bin.
+    // Unless the name depends on the content or `dedup` compares it, the tree is streamed
+    // straight to the file instead of being serialized into memory first. Stdout can't
+    // seek, which writing a tree needs, so it gets the serialized bytes too.
+    let needs_content = options.dedup
+        || match &output {
+            Some(output) => output == STDOUT_OUTPUT,
+            None => options
+                .name_template
+                .as_ref()
+                .is_some_and(NameTemplate::needs_content),
+        };
+    let output_path = if needs_content {
+        let started = Instant::now();
+        let bytes = bin_io::tree_to_bytes(&tree)?;
+        timings.serialize = started.elapsed();
+        let output_path =
+            output.unwrap_or_else(|| default_output_path(input_path, "bin", &bytes, options));
+        let started = Instant::now();
+        write_output(&output_path, &bytes, options)?;
+        timings.write = started.elapsed();
+        output_path
+    } else {
+        // The content is only used by `{hash8}`, which isn't in the template here
+        let output_path =
+            output.unwrap_or_else(|| default_output_path(input_path, "bin", &[], options));
+        let started = Instant::now();
+        write_tree_output(&output_path, &tree, options)?;
+        timings.write = started.elapsed();
+        output_path
+    };
+
+    options.index_entries(&output_path, &tree, &text_names);
+    options.converted(input_path, &output_path)?;
+    Ok(ConversionResult::new(output_path, 0, timings))
+}
+
+/// Convert a .bin or ritobin text file to JSON.
+/// Names written in ritobin input are kept even when the hashtables don't know them.
+fn convert_to_json(
+    input_path: &Utf8Path,
+    output_path: &Utf8Path,
+    options: &ConvertOptions,
+    hashes: &LazyHashProvider,
+) -> Result<ConversionResult> {
+    let mut timings = FileTimings::default();
+
+    let started = Instant::now();
+    let (mut tree, text_names) =
+        if options.input_extensions.kind(input_path) == Some(InputKind::Bin) {
+            (read_bin_input(input_path, options)?, None)
+        } else {
+            let text = bin_io::read_text_file(input_path)?;
+            (
+                options.parse_text(input_path, &text, 0)?,
+                Some(names_from_text(&text)),
+            )
+        };
+    timings.parse = started.elapsed();
+
+    let names = FallbackProvider {
+        primary: hashes.get()?,
+        fallback: text_names.unwrap_or_default(),
+    };
+    if !options.filter_entries(&options.display_path(input_path), &mut tree, &names)? {
+        return Ok(ConversionResult::default());
+    }
+    options.check_schema(input_path, &tree, &names)?;
+    options.prune(&mut tree);
+
+    let names = FallbackProvider {
+        primary: Some(&names),
+        fallback: bin_io::resolver_names(&tree, &names)?,
+    };
+    let started = Instant::now();
+    let recording = RecordingProvider::new(&names);
+    let json = bin_json::tree_to_json(&tree, &recording)
+        .wrap_err_with(|| format!("Failed to convert {}", input_path))?;
+    let unresolved_hashes = recording.missing_count();
+    timings.serialize = started.elapsed();
+
+    let output_path = &options.text_output_path(output_path.to_path_buf());
+    let started = Instant::now();
+    options.write_text_output(output_path, &json)?;
+    timings.write = started.elapsed();
+
+    options.index_entries(output_path, &tree, &names);
+    options.converted(input_path, output_path)?;
+    Ok(ConversionResult::new(
+        output_path.clone(),
+        unresolved_hashes,
+        timings,
+    ))
+}
+
+/// Convert a ritobin file holding several documents into numbered .bin files
+/// (`name.0.bin`, `name.1.bin`, ...), one per document. Returns the outputs and timings of
+/// all documents added up.
+fn convert_multi_document(
+    input_path: &Utf8Path,
+    output: Option<Utf8PathBuf>,
+    ritobin_text: &str,
+    options: &ConvertOptions,
+    hashes: &LazyHashProvider,
+) -> Result<ConversionResult> {
+    let mut result = ConversionResult::default();
+    let timings = &mut result.timings;
+
+    let documents = split_documents(ritobin_text, options.separator());
+    if documents.is_empty() {
+        return Err(miette::miette!("No documents found in {}", input_path));
+    }
+
+    // Checked per document, as the headers starting later documents aren't comments
+    let comments: Vec<usize> = documents
+        .iter()
+        .flat_map(|(line_offset, document)| {
+            comment_lines(document)
+                .into_iter()
+                .map(move |line| line + line_offset)
+        })
+        .collect();
+    options.warn_lost_comments(input_path, &comments);
+
+    for (index, (line_offset, document)) in documents.iter().enumerate() {
+        let started = Instant::now();
+        if options.strict {
+            check_duplicate_keys(&options.display_path(input_path), document, *line_offset)?;
+        }
+
+        let mut tree = options
+            .parse_text(input_path, document, *line_offset)
+            .wrap_err_with(|| {
+                format!(
+                    "Failed to parse document {} (line {})",
+                    index,
+                    line_offset + 1
+                )
+            })?;
+        timings.parse += started.elapsed();
+
+        let text_names = names_from_text(document);
+        let label = format!("{} (document {})", options.display_path(input_path), index);
+        if !options.filter_entries(&label, &mut tree, &text_names)? {
+            continue;
+        }
+        options.check_schema(input_path, &tree, &text_names)?;
+        options.check_known_names(input_path, &tree, document, *line_offset, hashes)?;
+
+        let started = Instant::now();
+        let bytes = bin_io::tree_to_bytes(&tree)?;
+        timings.serialize += started.elapsed();
+
+        let base_path = output
+            .clone()
+            .unwrap_or_else(|| default_output_path(input_path, "bin", &bytes, options));
+        let stem = base_path.file_stem().unwrap_or("output");
+        let ext = base_path.extension().unwrap_or("bin");
+        let output_path = base_path.with_file_name(format!("{}.{}.{}", stem, index, ext));
+
+        let started = Instant::now();
+        write_output(&output_path, &bytes, options)?;
+        timings.write += started.elapsed();
+
+        options.index_entries(&output_path, &tree, &text_names);
+        log_file_progress(format_args!(
+            "Converted {} (document {}) -> {}",
+            options.display_path(input_path),
+            index,
+            options.display_path(&output_path)
+        ));
+        options.print_output(&output_path);
+        if let Some(exec) = &options.exec {
+            exec.run(input_path, &output_path)?;
+        }
+        result.output_paths.push(output_path);
+    }
+
+    Ok(result)
+}
+
+/// Drop everything more than `depth` levels below the entries. Entry fields are the first
+/// level, and every struct, container, map or optional adds one for what it holds. Values
+/// at the cut keep their type but lose their contents, e.g. a struct becomes `Type {}`.
+fn prune_tree(tree: &mut BinTree, depth: usize) {
+    for object in tree.objects.values_mut() {
+        if depth == 0 {
+            object.properties.clear();
+            continue;
+        }
+        for property in object.properties.values_mut() {
+            prune_value(&mut property.value, depth - 1);
+        }
+    }
+}
+
+fn prune_value(value: &mut PropertyValueEnum, depth: usize) {
+    let Some(depth) = depth.checked_sub(1) else {
+        match value {
+            PropertyValueEnum::Struct(value)
+            | PropertyValueEnum::Embedded(EmbeddedValue(value)) => value.properties.clear(),
+            PropertyValueEnum::Container(container)
+            | PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)) => {
+                container.items.clear()
+            }
+            PropertyValueEnum::Map(map) => map.entries.clear(),
+            PropertyValueEnum::Optional(optional) => optional.value = None,
+            _ => {}
+        }
+        return;
+    };
+
+    match value {
+        PropertyValueEnum::Struct(value) | PropertyValueEnum::Embedded(EmbeddedValue(value)) => {
+            for property in value.properties.values_mut() {
+                prune_value(&mut property.value, depth);
+            }
+        }
+        PropertyValueEnum::Container(container)
+        | PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)) => {
+            container
+                .items
+                .iter_mut()
+                .for_each(|item| prune_value(item, depth));
+        }
+        PropertyValueEnum::Map(map) => {
+            map.entries
+                .values_mut()
+                .for_each(|value| prune_value(value, depth));
+        }
+        PropertyValueEnum::Optional(optional) => {
+            if let Some(value) = optional.value.as_deref_mut() {
+                prune_value(value, depth);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Split text on lines equal to `separator`, dropping empty documents. Each document is
+/// returned with the number of lines preceding it in the input. The default separator is
+/// the `#PROP_text` header, so it stays at the start of the document it begins and every
+/// document is standalone ritobin text; other separator lines are dropped.
+fn split_documents<'a>(text: &'a str, separator: &str) -> Vec<(usize, &'a str)> {
+    let keep_separator = separator == DEFAULT_DOCUMENT_SEPARATOR;
+    let mut documents = Vec::new();
+    let mut start = 0;
+    let mut start_line = 0;
+    let mut offset = 0;
+
+    for (line_index, line) in text.split_inclusive('\n').enumerate() {
+        if line.trim() == separator {
+            documents.push((start_line, &text[start..offset]));
+            (start, start_line) = if keep_separator {
+                (offset, line_index)
+            } else {
+                (offset + line.len(), line_index + 1)
+            };
+        }
+        offset += line.len();
+    }
+    documents.push((start_line, &text[start..]));
+
+    documents.retain(|(_, document)| {
+        document.lines().any(|line| {
+            let line = line.trim();
+            !line.is_empty() && line != separator
+        })
     });
+    documents
+}
+
+/// Fail if the ritobin text defines a key twice in the same block.
+/// The parser silently keeps only one definition, so these are almost always mistakes.
+/// `line_offset` is added to reported lines when the text is part of a larger file.
+fn check_duplicate_keys(input_label: &str, ritobin_text: &str, line_offset: usize) -> Result<()> {
+    let duplicates = find_duplicate_keys(ritobin_text);
+    if duplicates.is_empty() {
+        return Ok(());
+    }
+
+    for duplicate in &duplicates {
+        tracing::warn!(
+            "{}:{}: duplicate key '{}' ({}), first defined on line {}",
+            input_label,
+            duplicate.line + line_offset,
+            duplicate.key,
+            format_hash(duplicate.hash),
+            duplicate.first_line + line_offset
+        );
+    }
+
+    Err(miette::miette!(
+        "{} duplicate key(s) in {}",
+        duplicates.len(),
+        input_label
+    ))
+}
 
-    // Write binary output file
-    // BinTree::to_writer requires Seek, so we write to a cursor first then to file
-    let mut cursor = Cursor::new(Vec::new());
-    tree.to_writer(&mut cursor)
+/// Output path used when none is given explicitly: the name template if set,
+/// otherwise the input's directory and stem with the output format's extension
+fn default_output_path(
+    input_path: &Utf8Path,
+    ext: &str,
+    bytes: &[u8],
+    options: &ConvertOptions,
+) -> Utf8PathBuf {
+    let path = match &options.name_template {
+        Some(template) => template.render(input_path, ext, bytes),
+        None => {
+            let stem = input_path.file_stem().unwrap_or("output");
+            let parent = input_path.parent().unwrap_or(Utf8Path::new("."));
+            parent.join(format!("{}.{}", stem, ext))
+        }
+    };
+
+    let Some(output_dir) = &options.output_dir else {
+        return path;
+    };
+    let name = path.file_name().unwrap_or("output");
+    if !options.flat {
+        // Same place below the output directory as the input has below the input directory
+        let relative = options
+            .input_root
+            .as_deref()
+            .and_then(|root| path.strip_prefix(root).ok());
+        return output_dir.join(relative.unwrap_or(Utf8Path::new(name)));
+    }
+
+    // --flat keeps only the file name, prefixed if it collides with another input's
+    match options.flat_prefixes.get(input_path) {
+        Some(prefix) => output_dir.join(format!("{}_{}", prefix, name)),
+        None => output_dir.join(name),
+    }
+}
+
+/// Serialize a bin tree directly into the output file, then write its checksum if requested.
+/// Serializing needs `Seek`, so the bytes aren't kept and the checksum rereads the file.
+fn write_tree_output(
+    output_path: &Utf8Path,
+    tree: &BinTree,
+    options: &ConvertOptions,
+) -> Result<()> {
+    options.prepare_output(output_path)?;
+    let output_file = File::create(output_path)
+        .write_access(options.output_hint())
+        .wrap_err_with(|| format!("Failed to create output file: {}", output_path))?;
+    let mut writer = BufWriter::with_capacity(io_buffer_size(DEFAULT_IO_BUFFER_SIZE), output_file);
+
+    tree.to_writer(&mut writer)
         .into_diagnostic()
         .wrap_err("Failed to convert to binary format")?;
-
-    let output_file = File::create(&output_path)
+    writer
+        .flush()
         .into_diagnostic()
+        .wrap_err("Failed to write output file")?;
+
+    if options.sha256 {
+        // Hash the written file in chunks rather than keeping the serialized bytes around
+        let mut hasher = Sha256::new();
+        let file = File::open(output_path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to reopen output file: {}", output_path))?;
+        let mut reader = BufReader::with_capacity(io_buffer_size(DEFAULT_IO_BUFFER_SIZE), file);
+        io::copy(&mut reader, &mut hasher)
+            .into_diagnostic()
+            .wrap_err("Failed to hash output file")?;
+
+        write_checksum(output_path, &format!("{:x}", hasher.finalize()), options)?;
+    }
+
+    Ok(())
+}
+
+/// Write converted bytes to the output file.
+/// Its checksum is computed from `bytes`, so this output never has to be reread.
+/// [`STDOUT_OUTPUT`] writes the bytes to stdout instead of a file.
+fn write_output(output_path: &Utf8Path, bytes: &[u8], options: &ConvertOptions) -> Result<()> {
+    if output_path == STDOUT_OUTPUT {
+        let mut stdout = io::stdout().lock();
+        return stdout
+            .write_all(bytes)
+            .and_then(|_| stdout.flush())
+            .into_diagnostic()
+            .wrap_err("Failed to write to stdout");
+    }
+
+    options.prepare_output(output_path)?;
+    let digest = (options.sha256 || options.dedup).then(|| Sha256::digest(bytes));
+    let checksum = digest
+        .filter(|_| options.sha256)
+        .map(|digest| format!("{:x}", digest));
+
+    if let Some(digest) = digest
+        && options.dedup
+        && options.link_duplicate(output_path, digest.into())
+    {
+        if let Some(checksum) = checksum {
+            write_checksum(output_path, &checksum, options)?;
+        }
+        return Ok(());
+    }
+
+    let output_file = File::create(output_path)
+        .write_access(options.output_hint())
         .wrap_err_with(|| format!("Failed to create output file: {}", output_path))?;
-    let mut writer = BufWriter::new(output_file);
+    let mut writer = BufWriter::with_capacity(io_buffer_size(DEFAULT_IO_BUFFER_SIZE), output_file);
 
     writer
-        .write_all(cursor.get_ref())
+        .write_all(bytes)
+        .and_then(|_| writer.flush())
         .into_diagnostic()
         .wrap_err("Failed to write output file")?;
+    if let Some(digest) = digest
+        && options.dedup
+    {
+        options.record_written(output_path, digest.into());
+    }
 
-    tracing::info!(
-        "Converted {} -> {}",
-        hyperlink_path(input_path),
-        hyperlink_path(&output_path)
-    );
+    if let Some(checksum) = checksum {
+        write_checksum(output_path, &checksum, options)?;
+    }
+
+    Ok(())
+}
+
+/// Path to `target` as seen from the directory `link` is in, so links keep working when
+/// the whole output directory is moved. Paths that share no root stay absolute.
+fn link_target(link: &Utf8Path, target: &Utf8Path) -> Utf8PathBuf {
+    let absolute = |path: &Utf8Path| {
+        let path = if path.as_str().is_empty() {
+            Utf8Path::new(".")
+        } else {
+            path
+        };
+        std::path::absolute(path)
+            .ok()
+            .and_then(|path| Utf8PathBuf::from_path_buf(path).ok())
+    };
+    let (Some(dir), Some(target)) = (absolute(link.parent().unwrap_or(link)), absolute(target))
+    else {
+        return target.to_path_buf();
+    };
+
+    let dir: Vec<_> = dir.components().collect();
+    let target_components: Vec<_> = target.components().collect();
+    let common = dir
+        .iter()
+        .zip(&target_components)
+        .take_while(|(a, b)| a == b)
+        .count();
+    if common == 0 {
+        return target;
+    }
+    let mut relative = Utf8PathBuf::new();
+    for _ in common..dir.len() {
+        relative.push("..");
+    }
+    for component in &target_components[common..] {
+        relative.push(component);
+    }
+    relative
+}
+
+#[cfg(unix)]
+fn symlink_file(target: &Utf8Path, link: &Utf8Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+/// Needs developer mode or the symlink privilege, without them this fails and a copy is
+/// written instead
+#[cfg(windows)]
+fn symlink_file(target: &Utf8Path, link: &Utf8Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+/// Write `<output>.sha256` in the same layout as `sha256sum`,
+/// so the file can be verified with `sha256sum -c`
+fn write_checksum(output_path: &Utf8Path, checksum: &str, options: &ConvertOptions) -> Result<()> {
+    let checksum_path = Utf8PathBuf::from(format!("{}.sha256", output_path));
+    let file_name = output_path.file_name().unwrap_or(output_path.as_str());
+    std::fs::write(&checksum_path, format!("{}  {}\n", checksum, file_name))
+        .write_access(options.output_hint())
+        .wrap_err_with(|| format!("Failed to write checksum file: {}", checksum_path))?;
 
+    log_file_progress(format_args!(
+        "SHA-256 {} {}",
+        checksum,
+        options.display_path(output_path)
+    ));
     Ok(())
 }
@@ -1,39 +1,460 @@
-use std::fs::File;
-use std::io::{BufReader, Read};
+use std::borrow::Cow;
+use std::fs;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::ops::Range;
+use std::process::Command;
 
 use camino::Utf8Path;
+use clap::ValueEnum;
 use colored::Colorize;
-use ltk_meta::BinTree;
-use ltk_ritobin::{HashMapProvider, HexHashProvider, WriterConfig};
+use indicatif::HumanBytes;
+use ltk_meta::value::{EmbeddedValue, UnorderedContainerValue};
+use ltk_meta::{BinTree, PropertyValueEnum};
+use ltk_ritobin::{HashMapProvider, HexHashProvider};
 use miette::{IntoDiagnostic, Result, WrapErr};
-use similar::{ChangeTag, TextDiff};
+use similar::{Algorithm, ChangeTag, DiffOp, DiffTag, capture_diff_slices, group_diff_ops};
 
+use crate::utils::bin_io::{self, FallbackProvider};
 use crate::utils::config::load_or_create_config;
+use crate::utils::exit_code::Failure;
+use crate::utils::hashtable::LazyHashProvider;
+use crate::utils::hyperlink_path;
+use crate::utils::ritobin_text::names_from_text;
+use crate::utils::tree_diff::{TreeChange, diff_trees};
 
 /// Supported file extensions for diffing
-const SUPPORTED_EXTENSIONS: &[&str] = &["bin", "py", "ritobin"];
+pub(crate) const SUPPORTED_EXTENSIONS: &[&str] = &["bin", "py", "ritobin"];
+
+/// Ritobin text size above which a line-by-line diff suggests `--large`
+const LARGE_TEXT_SIZE: usize = 64 * 1024 * 1024;
+
+/// Lines per side up to which `--large` diffs a changed run of blocks line by line.
+/// Longer runs are printed as removed and added whole.
+const MAX_LARGE_REGION_LINES: usize = 20_000;
+
+/// Options controlling how files are compared and the diff is shown
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+    /// Number of unchanged lines shown around each change
+    pub context_lines: usize,
+    /// Blank scalar values before diffing so only changes to keys, types and nesting show up
+    pub structural: bool,
+    /// Compare lines with whitespace collapsed and ignore blank lines
+    pub ignore_whitespace: bool,
+    /// Compare the parsed trees and list changed property paths instead of text hunks
+    pub semantic: bool,
+    /// How printed text is encoded, files are always compared as they are
+    pub output_encoding: OutputEncoding,
+    /// Print nothing for identical files instead of saying they are identical
+    pub only_different: bool,
+    /// Compare entry by entry and only diff the changed entries line by line, see
+    /// [`display_large_diff`]
+    pub large: bool,
+}
+
+/// How diff output is written to the terminal
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputEncoding {
+    /// Print text as it is
+    #[default]
+    Utf8,
+    /// Escape every non-ASCII character as `\u{...}`, for consoles that show UTF-8 as mojibake
+    Ascii,
+}
+
+impl OutputEncoding {
+    /// `text` as it is printed
+    fn encode(self, text: &str) -> Cow<'_, str> {
+        if self == OutputEncoding::Utf8 || text.is_ascii() {
+            return Cow::Borrowed(text);
+        }
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if c.is_ascii() {
+                escaped.push(c);
+            } else {
+                escaped.extend(c.escape_unicode());
+            }
+        }
+        Cow::Owned(escaped)
+    }
+}
 
 /// Diff two .bin or .ritobin files against each other.
 ///
 /// Both files are converted to the ritobin text format internally,
 /// and a unified diff is displayed showing the differences.
-pub fn diff(file1: String, file2: String, context_lines: usize, no_color: bool) -> Result<()> {
+/// With `revision`, the old side is `file1` as committed in that git revision and the
+/// new side is `file2`, or the working copy of `file1` when `file2` is omitted.
+pub fn diff(
+    file1: String,
+    file2: Option<String>,
+    revision: Option<String>,
+    options: DiffOptions,
+) -> Result<()> {
+    let structural = options.structural;
     let path1 = Utf8Path::new(&file1);
-    let path2 = Utf8Path::new(&file2);
+    let path2 = Utf8Path::new(file2.as_deref().unwrap_or(&file1));
 
     // Validate file extensions
     validate_extension(path1)?;
     validate_extension(path2)?;
 
-    // Load config for hashtable provider
+    // Load config for hashtable provider, shared by both sides of the diff
     let (config, _) = load_or_create_config()?;
+    let hashes = LazyHashProvider::new(config.hashtable_dir, config.combined_hashtable);
+
+    if options.semantic {
+        let (label1, bytes1) = match &revision {
+            Some(rev) => (format!("{}:{}", rev, path1), read_git_blob(rev, path1)?),
+            None => (hyperlink_path(path1), read_file(path1)?),
+        };
+        let (tree1, names1) = bytes_to_tree(&bytes1, path1, structural)?;
+        let (tree2, names2) = bytes_to_tree(&read_file(path2)?, path2, structural)?;
+
+        // Hashtable names first, then the names spelled out in text inputs
+        let names = FallbackProvider {
+            primary: hashes.get()?,
+            fallback: FallbackProvider {
+                primary: names2.as_ref(),
+                fallback: FallbackProvider {
+                    primary: names1.as_ref(),
+                    fallback: HexHashProvider,
+                },
+            },
+        };
+        let changes = diff_trees(&tree1, &tree2, &names);
+        display_semantic_diff(&changes, &label1, &hyperlink_path(path2), &options);
+        return Ok(());
+    }
 
     // Convert both files to ritobin text format
-    let text1 = file_to_ritobin_text(path1, &config)?;
-    let text2 = file_to_ritobin_text(path2, &config)?;
+    let (label1, text1) = match &revision {
+        Some(rev) => {
+            let bytes = read_git_blob(rev, path1)?;
+            (
+                format!("{}:{}", rev, path1),
+                bytes_to_ritobin_text(&bytes, path1, &hashes, structural)?,
+            )
+        }
+        None => (
+            hyperlink_path(path1),
+            file_to_ritobin_text(path1, &hashes, structural)?,
+        ),
+    };
+    let text2 = file_to_ritobin_text(path2, &hashes, structural)?;
 
     // Compute and display the diff
-    display_diff(&text1, &text2, path1, path2, context_lines, no_color);
+    show_diff(&text1, &text2, &label1, &hyperlink_path(path2), &options);
+
+    Ok(())
+}
+
+/// Diff one baseline file against each of `candidates`.
+///
+/// The baseline is loaded and converted once, then every candidate is compared to it
+/// and gets its own diff and summary, or with `only_different` only the candidates that
+/// differ do. Candidates that can't be loaded are reported and skipped, failing the command
+/// at the end.
+pub fn diff_against_base(
+    base: String,
+    candidates: Vec<String>,
+    options: DiffOptions,
+) -> Result<()> {
+    let base_path = Utf8Path::new(&base);
+    validate_extension(base_path)?;
+
+    let (config, _) = load_or_create_config()?;
+    let hashes = LazyHashProvider::new(config.hashtable_dir, config.combined_hashtable);
+
+    let base_text = file_to_ritobin_text(base_path, &hashes, options.structural)?;
+    let base_lines = DiffLines::new(&base_text, options.ignore_whitespace);
+    let base_label = hyperlink_path(base_path);
+
+    let mut differing = 0;
+    let mut failed = 0;
+    let mut shown = 0;
+    for (index, candidate) in candidates.iter().enumerate() {
+        let path = Utf8Path::new(candidate);
+        let text = validate_extension(path)
+            .and_then(|_| file_to_ritobin_text(path, &hashes, options.structural));
+        if options.only_different
+            && let Ok(text) = &text
+            && DiffLines::new(text, options.ignore_whitespace).keys == base_lines.keys
+        {
+            continue;
+        }
+
+        if shown > 0 {
+            println!();
+        }
+        shown += 1;
+        println!(
+            "{} {}",
+            format!("[{}/{}]", index + 1, candidates.len()).bright_black(),
+            options.output_encoding.encode(&hyperlink_path(path)).bold()
+        );
+        match text {
+            Ok(text) => {
+                if display_diff(
+                    &base_lines,
+                    &text,
+                    &base_label,
+                    &hyperlink_path(path),
+                    &options,
+                ) {
+                    differing += 1;
+                }
+            }
+            Err(e) => {
+                tracing::error!("{:?}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!();
+    println!(
+        "{} {} of {} candidate(s) differ from {}",
+        "Total:".bold(),
+        differing,
+        candidates.len(),
+        options.output_encoding.encode(&base_label)
+    );
+
+    if failed > 0 {
+        return Err(
+            Failure::Partial(format!("{} candidate(s) could not be compared", failed)).into(),
+        );
+    }
+    Ok(())
+}
+
+/// Widest a column of a [`diff_multi`] table gets, longer lines are cut off
+const MAX_COLUMN_WIDTH: usize = 40;
+
+/// Diff several files at once, e.g. one bin across many game versions.
+///
+/// Every file is converted to ritobin text and aligned with the first one. Each region of
+/// the first file that any other file changes is printed once, as a table with a column
+/// per file holding that file's lines for the region. Cells that differ from the first
+/// file's are highlighted.
+pub fn diff_multi(files: Vec<String>, options: DiffOptions) -> Result<()> {
+    let paths: Vec<&Utf8Path> = files.iter().map(Utf8Path::new).collect();
+    for path in &paths {
+        validate_extension(path)?;
+    }
+
+    let (config, _) = load_or_create_config()?;
+    let hashes = LazyHashProvider::new(config.hashtable_dir, config.combined_hashtable);
+
+    let texts = paths
+        .iter()
+        .map(|path| file_to_ritobin_text(path, &hashes, options.structural))
+        .collect::<Result<Vec<_>>>()?;
+    let sides: Vec<DiffLines> = texts
+        .iter()
+        .map(|text| DiffLines::new(text, options.ignore_whitespace))
+        .collect();
+    let (base, others) = sides
+        .split_first()
+        .expect("diff --multi takes at least two files");
+    let base_keys: Vec<&str> = base.keys.iter().map(AsRef::as_ref).collect();
+
+    // For every other file, which of its lines each base line is equal to, if any
+    let mut alignments = Vec::with_capacity(others.len());
+    let mut changed = Vec::new();
+    for side in others {
+        let keys: Vec<&str> = side.keys.iter().map(AsRef::as_ref).collect();
+        let mut equal_to = vec![None; base_keys.len()];
+        for op in capture_diff_slices(Algorithm::Myers, &base_keys, &keys) {
+            if op.tag() == DiffTag::Equal {
+                for (old, new) in op.old_range().zip(op.new_range()) {
+                    equal_to[old] = Some(new);
+                }
+            } else {
+                changed.push(op.old_range());
+            }
+        }
+        alignments.push(equal_to);
+    }
+
+    let regions = merge_ranges(changed);
+    if regions.is_empty() {
+        if !options.only_different {
+            println!("{}", "Files are identical".green());
+        }
+        return Ok(());
+    }
+
+    let encoding = options.output_encoding;
+    for (index, path) in paths.iter().enumerate() {
+        let label = format!("[{}]", index + 1);
+        let suffix = if index == 0 { " (base)" } else { "" };
+        println!(
+            "{} {}{}",
+            label.bright_black(),
+            encoding.encode(&hyperlink_path(path)).bold(),
+            suffix
+        );
+    }
+
+    let mut context_start = 0;
+    for region in &regions {
+        println!();
+        println!(
+            "{}",
+            format!("@@ {} @@", base.hunk_range(region.clone())).cyan()
+        );
+        for line in &base.lines[region
+            .start
+            .saturating_sub(options.context_lines)
+            .max(context_start)..region.start]
+        {
+            print!(" {}", encoding.encode(line));
+        }
+        context_start = region.end;
+
+        // Base lines around the region are equal in every file, so they bound it there too
+        let mut columns = vec![&base.lines[region.clone()]];
+        for (side, equal_to) in others.iter().zip(&alignments) {
+            let start = region
+                .start
+                .checked_sub(1)
+                .and_then(|before| equal_to[before])
+                .map_or(0, |before| before + 1);
+            let end = equal_to
+                .get(region.end)
+                .copied()
+                .flatten()
+                .unwrap_or(side.lines.len());
+            columns.push(&side.lines[start..end]);
+        }
+        print_columns(&columns, encoding);
+    }
+
+    println!();
+    println!(
+        "{} {} region(s) differ across {} file(s)",
+        "Summary:".bold(),
+        regions.len(),
+        paths.len()
+    );
+    Ok(())
+}
+
+/// Sort `ranges` and merge the ones that overlap or touch. Empty ranges (insertions)
+/// merge with a range they touch as well.
+fn merge_ranges(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.sort_by_key(|range| (range.start, range.end));
+    let mut merged: Vec<Range<usize>> = Vec::new();
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Print lines side by side, one column per file labelled with its number. Cells that
+/// differ from the first column's on the same row are highlighted.
+fn print_columns(columns: &[&[&str]], encoding: OutputEncoding) {
+    let rows = columns.iter().map(|lines| lines.len()).max().unwrap_or(0);
+    let cell = |lines: &[&str], row: usize| -> String {
+        lines
+            .get(row)
+            .map(|line| encoding.encode(line.trim()).into_owned())
+            .unwrap_or_default()
+    };
+    let widths: Vec<usize> = columns
+        .iter()
+        .enumerate()
+        .map(|(index, lines)| {
+            (0..rows)
+                .map(|row| cell(lines, row).chars().count())
+                .chain([format!("[{}]", index + 1).len()])
+                .max()
+                .unwrap_or(0)
+                .min(MAX_COLUMN_WIDTH)
+        })
+        .collect();
+    // The last column isn't padded, so lines don't end in spaces
+    let last = columns.len() - 1;
+
+    let header: Vec<String> = widths
+        .iter()
+        .enumerate()
+        .map(|(index, &width)| {
+            let width = if index == last { 0 } else { width };
+            format!("{:<width$}", format!("[{}]", index + 1))
+        })
+        .collect();
+    println!("  {}", header.join(" | ").bright_black());
+
+    for row in 0..rows {
+        let base = cell(columns[0], row);
+        let cells: Vec<String> = columns
+            .iter()
+            .zip(&widths)
+            .enumerate()
+            .map(|(index, (lines, &width))| {
+                let text = cell(lines, row);
+                let pad = if index == last { 0 } else { width };
+                let padded = format!("{:<pad$}", truncate(&text, width));
+                if index > 0 && text != base {
+                    padded.yellow().to_string()
+                } else {
+                    padded
+                }
+            })
+            .collect();
+        println!("  {}", cells.join(&" | ".bright_black().to_string()));
+    }
+}
+
+/// `text` cut to `width` characters, ending in `…` when it was longer
+fn truncate(text: &str, width: usize) -> Cow<'_, str> {
+    if text.chars().count() <= width {
+        return Cow::Borrowed(text);
+    }
+    let mut cut: String = text.chars().take(width.saturating_sub(1)).collect();
+    cut.push('…');
+    Cow::Owned(cut)
+}
+
+/// Diff a .bin file against its own round-trip through ritobin text.
+///
+/// The file is converted to ritobin, that text is parsed and serialized back to .bin, and
+/// the result is converted to ritobin again. Any difference between the two texts is a
+/// place where reading or writing one of the formats loses or changes data.
+pub fn diff_self(file: String, options: DiffOptions) -> Result<()> {
+    let path = Utf8Path::new(&file);
+    if path.extension() != Some("bin") {
+        return Err(miette::miette!(
+            "--self needs a .bin file, got {}",
+            hyperlink_path(path)
+        ));
+    }
+
+    let (config, _) = load_or_create_config()?;
+    let hashes = LazyHashProvider::new(config.hashtable_dir, config.combined_hashtable);
+
+    let text = file_to_ritobin_text(path, &hashes, false)?;
+    let reparsed = bin_io::parse_text(&text)
+        .wrap_err_with(|| format!("Failed to parse the ritobin written for {}", path))?;
+    let bytes = bin_io::tree_to_bytes(&reparsed)?;
+    let round_tripped = bytes_to_ritobin_text(&bytes, path, &hashes, false)
+        .wrap_err_with(|| format!("Failed to read {} back after round-tripping it", path))?;
+
+    let label = hyperlink_path(path);
+    show_diff(
+        &text,
+        &round_tripped,
+        &label,
+        &format!("{} (round-tripped)", label),
+        &options,
+    );
 
     Ok(())
 }
@@ -50,38 +471,84 @@ fn validate_extension(path: &Utf8Path) -> Result<()> {
     Ok(())
 }
 
+fn read_file(path: &Utf8Path) -> Result<Vec<u8>> {
+    fs::read(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read file: {}", path))
+}
+
 /// Load a file and convert it to ritobin text format
 fn file_to_ritobin_text(
     path: &Utf8Path,
-    config: &crate::utils::config::AppConfig,
+    hashes: &LazyHashProvider,
+    structural: bool,
+) -> Result<String> {
+    bytes_to_ritobin_text(&read_file(path)?, path, hashes, structural)
+}
+
+/// Parse file contents into a bin tree, along with the names spelled out in text input.
+/// `path` is only used to pick the format by extension and for error messages.
+fn bytes_to_tree(
+    bytes: &[u8],
+    path: &Utf8Path,
+    structural: bool,
+) -> Result<(BinTree, Option<HashMapProvider>)> {
+    let (mut tree, names) = match path.extension().unwrap_or("") {
+        "bin" => (
+            bin_io::read_bin_bytes(bytes).wrap_err_with(|| format!("Failed to read {}", path))?,
+            None,
+        ),
+        _ => {
+            let text = std::str::from_utf8(bytes)
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to read file: {}", path))?;
+            let tree =
+                bin_io::parse_text(text).wrap_err_with(|| format!("Failed to read {}", path))?;
+            (tree, Some(names_from_text(text)))
+        }
+    };
+
+    if structural {
+        strip_values(&mut tree);
+    }
+    Ok((tree, names))
+}
+
+/// Convert file contents to ritobin text format.
+/// `path` is only used to pick the format by extension and for error messages.
+fn bytes_to_ritobin_text(
+    bytes: &[u8],
+    path: &Utf8Path,
+    hashes: &LazyHashProvider,
+    structural: bool,
 ) -> Result<String> {
     let extension = path.extension().unwrap_or("");
 
     match extension {
         "bin" => {
-            let tree = load_bin_file(path)?;
-            let ritobin_text = if let Some(hashtable_dir) = config.hashtable_dir.as_ref() {
-                let mut hashtable_provider = HashMapProvider::new();
-                hashtable_provider.load_from_directory(hashtable_dir);
-
-                ltk_ritobin::write_with_config_and_hashes(
-                    &tree,
-                    WriterConfig::default(),
-                    &hashtable_provider,
-                )
-            } else {
-                ltk_ritobin::write_with_config_and_hashes(
-                    &tree,
-                    WriterConfig::default(),
-                    &HexHashProvider,
-                )
+            let mut tree = bin_io::read_bin_bytes(bytes)
+                .wrap_err_with(|| format!("Failed to read {}", path))?;
+            if structural {
+                strip_values(&mut tree);
+            }
+            bin_io::tree_to_text(&tree, hashes.get()?)
+                .wrap_err_with(|| format!("Failed to convert {}", path))
+        }
+        "py" | "ritobin" => {
+            let text = String::from_utf8(bytes.to_vec())
+                .into_diagnostic()
+                .wrap_err_with(|| format!("Failed to read file: {}", path))?;
+            if !structural {
+                return Ok(text);
             }
-            .into_diagnostic()
-            .wrap_err_with(|| format!("Failed to convert {} to ritobin format", path))?;
 
-            Ok(ritobin_text)
+            // Parsing hashes every name, write them back the way the file spells them
+            let mut tree =
+                bin_io::parse_text(&text).wrap_err_with(|| format!("Failed to read {}", path))?;
+            strip_values(&mut tree);
+            bin_io::tree_to_text(&tree, Some(&names_from_text(&text)))
+                .wrap_err_with(|| format!("Failed to convert {}", path))
         }
-        "py" | "ritobin" => read_text_file(path),
         _ => Err(miette::miette!(
             "Unsupported file extension: .{}",
             extension
@@ -89,135 +556,513 @@ fn file_to_ritobin_text(
     }
 }
 
-/// Load a .bin file into a BinTree
-fn load_bin_file(path: &Utf8Path) -> Result<BinTree> {
-    let file = File::open(path)
-        .into_diagnostic()
-        .wrap_err_with(|| format!("Failed to open file: {}", path))?;
-    let mut reader = BufReader::new(file);
+/// Replace every scalar value with its type's zero value, keeping keys, types and nesting
+fn strip_values(tree: &mut BinTree) {
+    for object in tree.objects.values_mut() {
+        for property in object.properties.values_mut() {
+            strip_value(&mut property.value);
+        }
+    }
+}
 
-    BinTree::from_reader(&mut reader)
-        .into_diagnostic()
-        .wrap_err_with(|| format!("Failed to parse .bin file: {}", path))
+fn strip_value(value: &mut PropertyValueEnum) {
+    match value {
+        PropertyValueEnum::Container(container)
+        | PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)) => {
+            container.items.iter_mut().for_each(strip_value);
+        }
+        PropertyValueEnum::Struct(value) | PropertyValueEnum::Embedded(EmbeddedValue(value)) => {
+            for property in value.properties.values_mut() {
+                strip_value(&mut property.value);
+            }
+        }
+        PropertyValueEnum::Optional(optional) => {
+            if let Some(value) = optional.value.as_deref_mut() {
+                strip_value(value);
+            }
+        }
+        // Map keys identify entries, so only the values are stripped
+        PropertyValueEnum::Map(map) => map.entries.values_mut().for_each(strip_value),
+        PropertyValueEnum::None(_) => {}
+        PropertyValueEnum::Bool(v) => v.0 = false,
+        PropertyValueEnum::BitBool(v) => v.0 = false,
+        PropertyValueEnum::I8(v) => v.0 = 0,
+        PropertyValueEnum::U8(v) => v.0 = 0,
+        PropertyValueEnum::I16(v) => v.0 = 0,
+        PropertyValueEnum::U16(v) => v.0 = 0,
+        PropertyValueEnum::I32(v) => v.0 = 0,
+        PropertyValueEnum::U32(v) => v.0 = 0,
+        PropertyValueEnum::I64(v) => v.0 = 0,
+        PropertyValueEnum::U64(v) => v.0 = 0,
+        PropertyValueEnum::F32(v) => v.0 = 0.0,
+        PropertyValueEnum::Vector2(v) => v.0 = Default::default(),
+        PropertyValueEnum::Vector3(v) => v.0 = Default::default(),
+        PropertyValueEnum::Vector4(v) => v.0 = Default::default(),
+        PropertyValueEnum::Matrix44(v) => v.0 = Default::default(),
+        PropertyValueEnum::Color(v) => {
+            let color = &mut v.0;
+            (color.r, color.g, color.b, color.a) = (0, 0, 0, 0);
+        }
+        PropertyValueEnum::String(v) => v.0.clear(),
+        PropertyValueEnum::Hash(v) => v.0 = 0,
+        PropertyValueEnum::WadChunkLink(v) => v.0 = 0,
+        PropertyValueEnum::ObjectLink(v) => v.0 = 0,
+    }
 }
 
-/// Read a text file (.py/.ritobin) directly
-fn read_text_file(path: &Utf8Path) -> Result<String> {
-    let mut file = File::open(path)
-        .into_diagnostic()
-        .wrap_err_with(|| format!("Failed to open file: {}", path))?;
+/// Read the contents of `path` as committed in git revision `rev`
+fn read_git_blob(rev: &str, path: &Utf8Path) -> Result<Vec<u8>> {
+    // Run git from the file's directory so `./name` resolves relative to it,
+    // which works for both relative and absolute paths
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_str().is_empty() => parent,
+        _ => Utf8Path::new("."),
+    };
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| miette::miette!("Not a file path: {}", path))?;
 
-    let mut content = String::new();
-    file.read_to_string(&mut content)
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .arg("show")
+        .arg(format!("{}:./{}", rev, file_name))
+        .output()
         .into_diagnostic()
-        .wrap_err_with(|| format!("Failed to read file: {}", path))?;
+        .wrap_err("Failed to run git, is it installed and on PATH?")?;
+
+    if !output.status.success() {
+        return Err(miette::miette!(
+            "git show {}:{} failed: {}",
+            rev,
+            path,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(output.stdout)
+}
+
+/// What a line is compared by. Lines ending in `\r\n` compare equal to the same line
+/// ending in `\n`. With `ignore_whitespace`, lines are compared with whitespace collapsed
+/// and blank lines are left out entirely, returning `None`.
+fn line_key(line: &str, ignore_whitespace: bool) -> Option<Cow<'_, str>> {
+    if ignore_whitespace {
+        let normalized = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        (!normalized.is_empty()).then_some(Cow::Owned(normalized))
+    } else if let Some(content) = line.strip_suffix("\r\n") {
+        Some(Cow::Owned(format!("{}\n", content)))
+    } else {
+        Some(Cow::Borrowed(line))
+    }
+}
+
+/// One side of a diff: the original lines and the keys they are compared by
+#[derive(Default)]
+struct DiffLines<'a> {
+    /// Original lines, including their line endings
+    lines: Vec<&'a str>,
+    /// 1-based number of each line in the original text
+    numbers: Vec<usize>,
+    /// What each line is compared by, see [`line_key`]
+    keys: Vec<Cow<'a, str>>,
+    /// Number of the first line of the text, where an empty side is placed
+    first_line: usize,
+}
+
+impl<'a> DiffLines<'a> {
+    /// Split `text` into lines, see [`line_key`] for how they are compared
+    fn new(text: &'a str, ignore_whitespace: bool) -> Self {
+        Self::starting_at(text, ignore_whitespace, 1)
+    }
+
+    /// Split `text`, a part of a longer text starting at line `first_line`, into lines
+    fn starting_at(text: &'a str, ignore_whitespace: bool, first_line: usize) -> Self {
+        let mut side = Self {
+            first_line,
+            ..Self::default()
+        };
+        for (index, line) in text.split_inclusive('\n').enumerate() {
+            let Some(key) = line_key(line, ignore_whitespace) else {
+                continue;
+            };
+            side.lines.push(line);
+            side.numbers.push(first_line + index);
+            side.keys.push(key);
+        }
+        side
+    }
+
+    /// Unified diff range (`start,len`) of the lines in `range`, in original line numbers
+    fn hunk_range(&self, range: Range<usize>) -> String {
+        hunk_range(
+            match self.numbers.get(range.start) {
+                Some(&number) => number,
+                None => self
+                    .numbers
+                    .last()
+                    .map_or(self.first_line, |&number| number + 1),
+            },
+            range.len(),
+        )
+    }
+}
+
+/// Unified diff range (`start,len`) of `len` lines starting at line `start`
+fn hunk_range(start: usize, len: usize) -> String {
+    match len {
+        1 => start.to_string(),
+        // Empty ranges begin at the line just before them
+        0 => format!("{},0", start - 1),
+        _ => format!("{},{}", start, len),
+    }
+}
 
-    Ok(content)
+/// Display the diff between two ritobin texts, block by block with `large` and line by
+/// line otherwise. Texts big enough for `large` to matter get a hint about it.
+fn show_diff(text1: &str, text2: &str, label1: &str, label2: &str, options: &DiffOptions) {
+    if options.large {
+        display_large_diff(text1, text2, label1, label2, options);
+        return;
+    }
+    let size = text1.len().max(text2.len());
+    if size > LARGE_TEXT_SIZE {
+        tracing::warn!(
+            "Comparing {} of ritobin text line by line, pass --large to compare it entry by entry with less memory",
+            HumanBytes(size as u64)
+        );
+    }
+    let old = DiffLines::new(text1, options.ignore_whitespace);
+    display_diff(&old, text2, label1, label2, options);
 }
 
-/// Display the diff between two ritobin text representations
+/// Display the diff between two ritobin text representations, returning whether they differ.
+/// `old` is split ahead of time so it can be reused across several diffs.
 fn display_diff(
-    text1: &str,
+    old: &DiffLines,
     text2: &str,
-    path1: &Utf8Path,
-    path2: &Utf8Path,
-    context_lines: usize,
-    no_color: bool,
-) {
-    let diff = TextDiff::from_lines(text1, text2);
+    label1: &str,
+    label2: &str,
+    options: &DiffOptions,
+) -> bool {
+    let new = DiffLines::new(text2, options.ignore_whitespace);
+    let old_keys: Vec<&str> = old.keys.iter().map(AsRef::as_ref).collect();
+    let new_keys: Vec<&str> = new.keys.iter().map(AsRef::as_ref).collect();
+
+    let ops = capture_diff_slices(Algorithm::Myers, &old_keys, &new_keys);
 
     // Check if files are identical
-    if diff.ratio() == 1.0 {
-        if no_color {
-            println!("Files are identical");
-        } else {
+    if ops.iter().all(|op| op.tag() == DiffTag::Equal) {
+        if !options.only_different {
             println!("{}", "Files are identical".green());
         }
-        return;
+        return false;
     }
 
-    // Count insertions and deletions
-    let mut insertions = 0;
-    let mut deletions = 0;
-    for change in diff.iter_all_changes() {
-        match change.tag() {
-            ChangeTag::Insert => insertions += 1,
-            ChangeTag::Delete => deletions += 1,
-            ChangeTag::Equal => {}
-        }
-    }
+    print_diff_header(label1, label2, options.output_encoding);
+    let mut counts = ChangeCounts::default();
+    print_hunks(old, &new, ops, options, &mut counts);
+    counts.print_summary();
+    true
+}
 
-    // Print header
-    if no_color {
-        println!("--- {}", path1);
-        println!("+++ {}", path2);
-    } else {
-        println!("{} {}", "---".red(), path1.to_string().red());
-        println!("{} {}", "+++".green(), path2.to_string().green());
+/// Lines added and removed over all hunks of a diff
+#[derive(Default)]
+struct ChangeCounts {
+    insertions: usize,
+    deletions: usize,
+}
+
+impl ChangeCounts {
+    fn print_summary(&self) {
+        println!();
+        println!(
+            "{} {} {}{} {} {}",
+            "Summary:".bold(),
+            self.insertions.to_string().green(),
+            "insertion(s)".green(),
+            ",".white(),
+            self.deletions.to_string().red(),
+            "deletion(s)".red(),
+        );
     }
+}
+
+fn print_diff_header(label1: &str, label2: &str, encoding: OutputEncoding) {
+    println!("{} {}", "---".red(), encoding.encode(label1).red());
+    println!("{} {}", "+++".green(), encoding.encode(label2).green());
+}
+
+/// Print `ops` between `old` and `new` as unified diff hunks with context, adding the
+/// changed lines to `counts`
+fn print_hunks(
+    old: &DiffLines,
+    new: &DiffLines,
+    ops: Vec<DiffOp>,
+    options: &DiffOptions,
+    counts: &mut ChangeCounts,
+) {
+    let old_keys: Vec<&str> = old.keys.iter().map(AsRef::as_ref).collect();
+    let new_keys: Vec<&str> = new.keys.iter().map(AsRef::as_ref).collect();
+    for group in group_diff_ops(ops, options.context_lines) {
+        let (Some(first), Some(last)) = (group.first(), group.last()) else {
+            continue;
+        };
 
-    // Print unified diff with context
-    for hunk in diff
-        .unified_diff()
-        .context_radius(context_lines)
-        .iter_hunks()
-    {
         // Print hunk header
-        let header = hunk.header().to_string();
-        if no_color {
-            print!("{}", header);
-        } else {
-            print!("{}", header.cyan());
-        }
-
-        // Print changes
-        for change in hunk.iter_changes() {
-            let sign = match change.tag() {
-                ChangeTag::Delete => "-",
-                ChangeTag::Insert => "+",
-                ChangeTag::Equal => " ",
+        let header = format!(
+            "@@ -{} +{} @@",
+            old.hunk_range(first.old_range().start..last.old_range().end),
+            new.hunk_range(first.new_range().start..last.new_range().end)
+        );
+        println!("{}", header.cyan());
+
+        // Print changes, showing the original lines even when comparing normalized ones
+        for change in group
+            .iter()
+            .flat_map(|op| op.iter_changes(&old_keys, &new_keys))
+        {
+            let line = match (change.tag(), change.old_index(), change.new_index()) {
+                (ChangeTag::Insert, _, Some(index)) => new.lines[index],
+                (_, Some(index), _) => old.lines[index],
+                _ => continue,
             };
+            match change.tag() {
+                ChangeTag::Insert => counts.insertions += 1,
+                ChangeTag::Delete => counts.deletions += 1,
+                ChangeTag::Equal => {}
+            }
+            print_diff_line(change.tag(), line, options.output_encoding);
+        }
+    }
+}
 
-            let line = change.value();
+fn print_diff_line(tag: ChangeTag, line: &str, encoding: OutputEncoding) {
+    let line = encoding.encode(line);
+    match tag {
+        ChangeTag::Delete => print!("{}{}", "-".red(), line.red()),
+        ChangeTag::Insert => print!("{}{}", "+".green(), line.green()),
+        ChangeTag::Equal => print!(" {}", line),
+    }
 
-            if no_color {
-                print!("{}{}", sign, line);
-            } else {
-                match change.tag() {
-                    ChangeTag::Delete => print!("{}{}", sign.red(), line.red()),
-                    ChangeTag::Insert => print!("{}{}", sign.green(), line.green()),
-                    ChangeTag::Equal => print!("{}{}", sign, line),
-                }
-            }
+    // Handle missing newline at end of file
+    if !line.ends_with('\n') {
+        println!();
+        println!("{}", "\\ No newline at end of file".yellow());
+    }
+}
 
-            // Handle missing newline at end of file
-            if change.missing_newline() {
-                println!();
-                if no_color {
-                    println!("\\ No newline at end of file");
-                } else {
-                    println!("{}", "\\ No newline at end of file".yellow());
+/// A ritobin text cut into blocks for `--large`: the top-level keys, and every entry and
+/// `linked` path, with the lines nested in them
+struct Blocks<'a> {
+    text: &'a str,
+    /// Byte offset each block starts at
+    starts: Vec<usize>,
+    /// Line each block starts at, 1-based
+    first_lines: Vec<usize>,
+    /// What each block is compared by, a hash of its [`line_key`]s
+    keys: Vec<u64>,
+    /// Lines in the whole text
+    line_count: usize,
+}
+
+impl<'a> Blocks<'a> {
+    fn new(text: &'a str, ignore_whitespace: bool) -> Self {
+        let mut blocks = Self {
+            text,
+            starts: Vec::new(),
+            first_lines: Vec::new(),
+            keys: Vec::new(),
+            line_count: 0,
+        };
+        let mut hasher = DefaultHasher::new();
+        let mut offset = 0;
+        for (index, line) in text.split_inclusive('\n').enumerate() {
+            if blocks.starts.is_empty() || starts_block(line) {
+                if !blocks.starts.is_empty() {
+                    blocks.keys.push(hasher.finish());
+                    hasher = DefaultHasher::new();
                 }
+                blocks.starts.push(offset);
+                blocks.first_lines.push(index + 1);
+            }
+            if let Some(key) = line_key(line, ignore_whitespace) {
+                key.hash(&mut hasher);
             }
+            offset += line.len();
+            blocks.line_count = index + 1;
+        }
+        if !blocks.starts.is_empty() {
+            blocks.keys.push(hasher.finish());
         }
+        blocks
     }
 
-    // Print summary statistics
-    println!();
-    if no_color {
-        println!(
-            "Summary: {} insertion(s), {} deletion(s)",
-            insertions, deletions
-        );
-    } else {
-        println!(
-            "{} {} {}{} {} {}",
-            "Summary:".bold(),
-            insertions.to_string().green(),
-            "insertion(s)".green(),
-            ",".white(),
-            deletions.to_string().red(),
-            "deletion(s)".red(),
+    /// The text of the blocks in `range`
+    fn text(&self, range: Range<usize>) -> &'a str {
+        let offset = |block: usize| self.starts.get(block).copied().unwrap_or(self.text.len());
+        &self.text[offset(range.start)..offset(range.end)]
+    }
+
+    /// The line the blocks in `range` start at, or would be inserted at when it's empty
+    fn first_line(&self, range: Range<usize>) -> usize {
+        self.first_lines
+            .get(range.start)
+            .copied()
+            .unwrap_or(self.line_count + 1)
+    }
+}
+
+/// Whether `line` starts a [`Blocks`] block: it's nested at most one level (four spaces)
+/// and doesn't close a block, like `    "Characters/Ahri" = SkinCharacterDataProperties {`
+fn starts_block(line: &str) -> bool {
+    let content = line.trim_start_matches(' ');
+    line.len() - content.len() <= 4 && !content.trim().is_empty() && !content.starts_with('}')
+}
+
+/// Display the diff between two ritobin texts for `--large`, returning whether they differ.
+///
+/// The texts are cut into [`Blocks`], mostly one per entry, which are matched by a hash of
+/// their lines. Only changed runs of blocks are split into lines and diffed, so memory
+/// grows with the largest change instead of the whole file. Hunks don't extend past the
+/// changed blocks, and a run longer than [`MAX_LARGE_REGION_LINES`] is printed as removed
+/// and added whole instead of line by line.
+fn display_large_diff(
+    text1: &str,
+    text2: &str,
+    label1: &str,
+    label2: &str,
+    options: &DiffOptions,
+) -> bool {
+    let old = Blocks::new(text1, options.ignore_whitespace);
+    let new = Blocks::new(text2, options.ignore_whitespace);
+    let ops = capture_diff_slices(Algorithm::Myers, &old.keys, &new.keys);
+
+    if ops.iter().all(|op| op.tag() == DiffTag::Equal) {
+        if !options.only_different {
+            println!("{}", "Files are identical".green());
+        }
+        return false;
+    }
+
+    print_diff_header(label1, label2, options.output_encoding);
+    let mut counts = ChangeCounts::default();
+    let mut coarse = 0;
+    for op in ops.iter().filter(|op| op.tag() != DiffTag::Equal) {
+        let old_text = old.text(op.old_range());
+        let new_text = new.text(op.new_range());
+        let old_first = old.first_line(op.old_range());
+        let new_first = new.first_line(op.new_range());
+
+        let old_len = old_text.split_inclusive('\n').count();
+        let new_len = new_text.split_inclusive('\n').count();
+        if old_len.max(new_len) > MAX_LARGE_REGION_LINES {
+            coarse += 1;
+            println!(
+                "{}",
+                format!(
+                    "@@ -{} +{} @@",
+                    hunk_range(old_first, old_len),
+                    hunk_range(new_first, new_len)
+                )
+                .cyan()
+            );
+            for line in old_text.split_inclusive('\n') {
+                print_diff_line(ChangeTag::Delete, line, options.output_encoding);
+            }
+            for line in new_text.split_inclusive('\n') {
+                print_diff_line(ChangeTag::Insert, line, options.output_encoding);
+            }
+            counts.deletions += old_len;
+            counts.insertions += new_len;
+            continue;
+        }
+
+        let old_lines = DiffLines::starting_at(old_text, options.ignore_whitespace, old_first);
+        let new_lines = DiffLines::starting_at(new_text, options.ignore_whitespace, new_first);
+        let old_keys: Vec<&str> = old_lines.keys.iter().map(AsRef::as_ref).collect();
+        let new_keys: Vec<&str> = new_lines.keys.iter().map(AsRef::as_ref).collect();
+        let line_ops = capture_diff_slices(Algorithm::Myers, &old_keys, &new_keys);
+        print_hunks(&old_lines, &new_lines, line_ops, options, &mut counts);
+    }
+    counts.print_summary();
+    if coarse > 0 {
+        tracing::warn!(
+            "{} change(s) spanned more than {} lines and are shown as removed and added whole",
+            coarse,
+            MAX_LARGE_REGION_LINES
         );
     }
+    true
+}
+
+/// Print property-level changes between two trees. Returns whether there were any.
+fn display_semantic_diff(
+    changes: &[TreeChange],
+    label1: &str,
+    label2: &str,
+    options: &DiffOptions,
+) -> bool {
+    if changes.is_empty() {
+        if !options.only_different {
+            println!("{}", "Files are semantically identical".green());
+        }
+        return false;
+    }
+    let encoding = options.output_encoding;
+
+    println!("{} {}", "---".red(), encoding.encode(label1).red());
+    println!("{} {}", "+++".green(), encoding.encode(label2).green());
+
+    let (mut changed, mut added, mut removed) = (0, 0, 0);
+    for change in changes {
+        let path = encoding.encode(&change.path);
+        match (&change.old, &change.new) {
+            (Some(old), Some(new)) => {
+                changed += 1;
+                println!(
+                    "{} {}: {} {} {}",
+                    "~".yellow(),
+                    path,
+                    encoding.encode(old).red(),
+                    "->".bright_black(),
+                    encoding.encode(new).green()
+                );
+            }
+            (Some(old), None) => {
+                removed += 1;
+                println!(
+                    "{} {}: {}",
+                    "-".red(),
+                    path.red(),
+                    encoding.encode(old).red()
+                );
+            }
+            (None, Some(new)) => {
+                added += 1;
+                println!(
+                    "{} {}: {}",
+                    "+".green(),
+                    path.green(),
+                    encoding.encode(new).green()
+                );
+            }
+            (None, None) => {}
+        }
+    }
+
+    println!();
+    println!(
+        "{} {} {}{} {} {}{} {} {}",
+        "Summary:".bold(),
+        changed.to_string().yellow(),
+        "changed".yellow(),
+        ",".white(),
+        added.to_string().green(),
+        "added".green(),
+        ",".white(),
+        removed.to_string().red(),
+        "removed".red(),
+    );
+    true
 }
@@ -0,0 +1,275 @@
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+use colored::Colorize;
+use indicatif::HumanBytes;
+use ltk_hash::fnv1a::hash_lower;
+use miette::Result;
+
+use crate::utils::bin_io;
+use crate::utils::config::{self, AppConfig};
+use crate::utils::hashtable::{self, HASHTABLE_FILES, LazyHashProvider};
+use crate::utils::{colors_enabled, hyperlink_path, links_enabled};
+
+/// A tiny bin naming one entry, one type and one field that every CommunityDragon
+/// hashtable knows, converted with the configured hashtables to see they resolve names
+const TEST_BIN: &str = r#"#PROP_text
+type: string = "PROP"
+version: u32 = 3
+linked: list[string] = {}
+entries: map[hash,embed] = {
+    "Characters/Ahri/Skins/Skin0" = SkinCharacterDataProperties {
+        championSkinName: string = "Ahri"
+    }
+}
+"#;
+
+/// The names in [`TEST_BIN`]
+const TEST_NAMES: &[&str] = &[
+    "Characters/Ahri/Skins/Skin0",
+    "SkinCharacterDataProperties",
+    "championSkinName",
+];
+
+/// Checks printed as a ✓/✗ list under section headings, counting the failed ones
+#[derive(Default)]
+struct Checklist {
+    failed: usize,
+}
+
+impl Checklist {
+    fn section(&self, title: &str) {
+        println!();
+        println!("  {}", title.bold());
+    }
+
+    /// A check that makes `doctor` fail when it doesn't pass
+    fn check(&mut self, passed: bool, label: &str, detail: impl AsRef<str>) {
+        if !passed {
+            self.failed += 1;
+        }
+        self.info(passed, label, detail);
+    }
+
+    /// A check that only describes the environment, e.g. whether colors are enabled
+    fn info(&self, passed: bool, label: &str, detail: impl AsRef<str>) {
+        let mark = if passed {
+            "✓".bright_green()
+        } else {
+            "✗".bright_red()
+        };
+        println!(
+            "    {} {} {}",
+            mark,
+            label.bright_white(),
+            detail.as_ref().bright_black()
+        );
+    }
+}
+
+/// Check the environment: the config file, the hashtables, whether names resolve, and
+/// what the terminal supports. Fails when any check other than the terminal ones does.
+pub fn doctor() -> Result<()> {
+    let mut checklist = Checklist::default();
+
+    checklist.section("Config");
+    let config = check_config(&mut checklist);
+
+    checklist.section("Hashtables");
+    check_hashtables(&mut checklist, &config);
+
+    checklist.section("Name resolution");
+    check_names(&mut checklist, &config);
+
+    checklist.section("Terminal");
+    checklist.info(
+        colors_enabled(),
+        "colors",
+        if colors_enabled() {
+            "enabled"
+        } else {
+            "disabled by --color, NO_COLOR or a terminal without color support"
+        },
+    );
+    let link_style = config.link_style.unwrap_or_default();
+    checklist.info(
+        links_enabled(),
+        "hyperlinks",
+        match (links_enabled(), io::stdout().is_terminal()) {
+            (true, _) => format!("enabled (link_style {})", link_style),
+            (false, false) => format!(
+                "disabled, stdout isn't a terminal (link_style {})",
+                link_style
+            ),
+            (false, true) => format!("disabled (link_style {})", link_style),
+        },
+    );
+    println!();
+
+    if checklist.failed > 0 {
+        return Err(miette::miette!(
+            "{} check(s) failed, see the ✗ lines above",
+            checklist.failed
+        ));
+    }
+    println!("  {}", "Everything looks fine".bright_green());
+    println!();
+    Ok(())
+}
+
+/// Check that config.toml parses, returning the configuration the other checks use:
+/// the defaults when it doesn't
+fn check_config(checklist: &mut Checklist) -> AppConfig {
+    let Some(path) = config::default_config_path() else {
+        checklist.check(
+            false,
+            "config.toml",
+            "the executable's directory is unknown",
+        );
+        return AppConfig::default();
+    };
+    if !path.exists() {
+        checklist.info(
+            true,
+            "config.toml",
+            format!("{} not created yet, using the defaults", path),
+        );
+        return AppConfig::default();
+    }
+
+    match config::read_config_file(&path).and_then(|_| config::load_or_create_config()) {
+        Ok((config, _)) => {
+            checklist.check(true, "config.toml", hyperlink_path(&path));
+            config
+        }
+        Err(e) => {
+            let causes: Vec<_> = e.chain().map(ToString::to_string).collect();
+            checklist.check(
+                false,
+                "config.toml",
+                format!("{}: {}", hyperlink_path(&path), causes.join(": ")),
+            );
+            AppConfig::default()
+        }
+    }
+}
+
+/// Check that the hashtable directory exists and holds the four hashtables, and how old
+/// they are
+fn check_hashtables(checklist: &mut Checklist, config: &AppConfig) {
+    let Some(dir) = &config.hashtable_dir else {
+        checklist.check(false, "hashtable_dir", "not set");
+        return;
+    };
+    if !dir.is_dir() {
+        checklist.check(
+            false,
+            "hashtable_dir",
+            format!("{} doesn't exist", hyperlink_path(dir)),
+        );
+        return;
+    }
+    checklist.check(true, "hashtable_dir", hyperlink_path(dir));
+
+    let max_age = config.hashtable_max_age_days.map(hashtable::max_age);
+    for file in HASHTABLE_FILES {
+        let Ok(metadata) = fs::metadata(dir.join(file)) else {
+            checklist.check(false, file, "missing, run `ritobin-tools download-hashes`");
+            continue;
+        };
+        let age = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .unwrap_or_default();
+        let detail = format!("{}, {}", HumanBytes(metadata.len()), describe_age(age));
+        match max_age {
+            Some(max_age) if age > max_age => checklist.check(
+                false,
+                file,
+                format!(
+                    "{}, older than hashtable_max_age_days, run `ritobin-tools download-hashes`",
+                    detail
+                ),
+            ),
+            _ => checklist.check(true, file, detail),
+        }
+    }
+}
+
+/// Check that the configured hashtables name the hashes of [`TEST_BIN`] after a round
+/// trip through its binary form
+fn check_names(checklist: &mut Checklist, config: &AppConfig) {
+    let hashes = LazyHashProvider::new(
+        config.hashtable_dir.clone(),
+        config.combined_hashtable.clone(),
+    );
+    let tree = bin_io::parse_text(TEST_BIN)
+        .and_then(|tree| bin_io::tree_to_bytes(&tree))
+        .and_then(|bytes| bin_io::read_bin_bytes(&bytes));
+    let tree = match tree {
+        Ok(tree) => tree,
+        Err(e) => {
+            checklist.check(false, "test bin", format!("failed to round trip: {}", e));
+            return;
+        }
+    };
+    let provider = match hashes.get() {
+        Ok(Some(provider)) => provider,
+        Ok(None) => {
+            checklist.check(
+                false,
+                "test bin",
+                "no hashtable directory to load names from",
+            );
+            return;
+        }
+        Err(e) => {
+            checklist.check(
+                false,
+                "test bin",
+                format!("hashtables failed to load: {}", e),
+            );
+            return;
+        }
+    };
+    let unresolved = match bin_io::unresolved_hashes(&tree, provider) {
+        Ok(unresolved) => unresolved,
+        Err(e) => {
+            checklist.check(false, "test bin", format!("failed to convert: {}", e));
+            return;
+        }
+    };
+
+    let missing: Vec<&str> = TEST_NAMES
+        .iter()
+        .copied()
+        .filter(|name| unresolved.iter().any(|(_, hash)| *hash == hash_lower(name)))
+        .collect();
+    let resolved = TEST_NAMES.len() - missing.len();
+    let detail = format!(
+        "{} of {} names resolved, {} names loaded",
+        resolved,
+        TEST_NAMES.len(),
+        provider.total_count()
+    );
+    if missing.is_empty() {
+        checklist.check(true, "test bin", detail);
+    } else {
+        checklist.check(
+            false,
+            "test bin",
+            format!("{}, written as hex: {}", detail, missing.join(", ")),
+        );
+    }
+}
+
+/// `updated today`, `1 day old` or `N days old`
+fn describe_age(age: Duration) -> String {
+    match age.as_secs() / (24 * 60 * 60) {
+        0 => "updated today".to_string(),
+        1 => "1 day old".to_string(),
+        days => format!("{} days old", days),
+    }
+}
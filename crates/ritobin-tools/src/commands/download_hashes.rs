@@ -1,14 +1,21 @@
-use camino::Utf8PathBuf;
-use indicatif::ProgressStyle;
+use camino::{Utf8Path, Utf8PathBuf};
+use indicatif::{HumanBytes, ProgressStyle};
 use miette::{IntoDiagnostic, Result, WrapErr};
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::env;
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use tracing_indicatif::span_ext::IndicatifSpanExt;
 
-use crate::utils::config::load_or_create_config;
-use crate::utils::hyperlink_path;
+use crate::utils::config::{DEFAULT_DOWNLOAD_TIMEOUT_SECS, load_or_create_config};
+use crate::utils::exit_code::Failure;
+use crate::utils::permission::{HASHTABLE_DIR_HINT, IntoAccessDiagnostic};
+use crate::utils::{hyperlink_path, io_buffer_size, log_file_progress};
 
-/// Hash files loaded by `load_from_directory` in ltk_ritobin
+/// Hash files loaded by `load_text_hashtables`
 const HASH_FILES: &[(&str, &str)] = &[
     (
         "hashes.binentries.txt",
@@ -28,37 +35,314 @@ const HASH_FILES: &[(&str, &str)] = &[
     ),
 ];
 
+/// Read buffer for downloads unless `io_buffer_size` is configured
 const DOWNLOAD_BUFFER_SIZE: usize = 64 * 1024;
 
-/// Download hashtable files from CommunityDragon to the configured hashtable directory.
-pub fn download_hashes() -> Result<()> {
+/// Retries per file unless `--retries` is given
+pub const DEFAULT_RETRIES: u32 = 3;
+
+/// Delay before the first retry, doubled after every failed attempt
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest delay between retries, however many attempts failed
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// How much of each end of an existing file is read to check that it's a hashtable
+const SANITY_CHECK_LEN: u64 = 4096;
+
+/// A failed download attempt, classified by whether retrying could help
+enum AttemptError {
+    /// Server errors, timeouts and dropped connections
+    Transient(miette::Report),
+    /// Client errors (4xx) and local I/O failures
+    Fatal(miette::Report),
+}
+
+/// Download hashtable files from CommunityDragon, and the configured `extra_hashtables`,
+/// to the configured hashtable directory.
+///
+/// `timeout` overrides the configured `download_timeout_secs`. Each file is retried up to
+/// `retries` times with exponential backoff when the failure looks transient.
+/// `proxy` overrides the proxy picked up from the environment.
+///
+/// Files that already exist, look like hashtables and have the size the server reports
+/// are kept instead of downloaded again, unless `force` is set.
+pub fn download_hashes(
+    timeout: Option<u64>,
+    retries: u32,
+    proxy: Option<String>,
+    force: bool,
+) -> Result<()> {
     let (config, _) = load_or_create_config()?;
 
+    let timeout = Duration::from_secs(
+        timeout
+            .or(config.download_timeout_secs)
+            .unwrap_or(DEFAULT_DOWNLOAD_TIMEOUT_SECS),
+    );
+
+    let files: Vec<(&str, &str)> = HASH_FILES
+        .iter()
+        .copied()
+        .chain(
+            config
+                .extra_hashtables
+                .iter()
+                .flatten()
+                .map(|extra| (extra.file.as_str(), extra.url.as_str())),
+        )
+        .collect();
+
+    // Extra hashtables may live on other hosts, with their own proxy decision
+    let mut agents: HashMap<Option<String>, ureq::Agent> = HashMap::new();
+    for (_, url) in &files {
+        let proxy_url = resolve_proxy_url(proxy.clone(), url);
+        if let Entry::Vacant(entry) = agents.entry(proxy_url) {
+            let agent = build_agent(timeout, entry.key().as_deref())?;
+            entry.insert(agent);
+        }
+    }
+
     let target_dir = config
         .hashtable_dir
         .ok_or_else(|| miette::miette!("No hashtable directory configured"))?;
 
     fs::create_dir_all(target_dir.as_std_path())
-        .into_diagnostic()
+        .write_access(HASHTABLE_DIR_HINT)
         .wrap_err_with(|| format!("Failed to create directory: {}", target_dir))?;
 
     tracing::info!("Downloading hashtables to {}", hyperlink_path(&target_dir));
 
-    for (filename, url) in HASH_FILES {
-        download_file_with_progress(url, filename, &target_dir)?;
+    let started = Instant::now();
+    let mut total_bytes = 0;
+    let mut downloaded_count = 0;
+    {
+        // Overall "n of m" bar, the per-file bars are nested below it
+        let span = tracing::info_span!("download_hashes");
+        span.pb_set_style(
+            &ProgressStyle::with_template("{msg} [{pos}/{len}] {elapsed_precise}").unwrap(),
+        );
+        span.pb_set_length(files.len() as u64);
+        span.pb_set_message("Hashtables");
+        let _entered = span.enter();
+
+        for (filename, url) in &files {
+            let agent = &agents[&resolve_proxy_url(proxy.clone(), url)];
+            if !force && is_up_to_date(agent, url, &target_dir.join(filename)) {
+                span.pb_inc(1);
+                continue;
+            }
+            total_bytes += download_file_with_retry(agent, url, filename, &target_dir, retries)?;
+            downloaded_count += 1;
+            span.pb_inc(1);
+        }
     }
 
+    let up_to_date = files.len() - downloaded_count;
+    if downloaded_count == 0 {
+        tracing::info!(
+            "Hashtables in {} are up to date, pass --force to download them anyway",
+            hyperlink_path(&target_dir)
+        );
+        return Ok(());
+    }
     tracing::info!(
-        "Successfully downloaded all hashtables to {}",
-        hyperlink_path(&target_dir)
+        "Downloaded {} hashtables ({}) to {} in {:.1}s{}",
+        downloaded_count,
+        HumanBytes(total_bytes),
+        hyperlink_path(&target_dir),
+        started.elapsed().as_secs_f64(),
+        if up_to_date > 0 {
+            format!(", {} already up to date", up_to_date)
+        } else {
+            String::new()
+        }
     );
     Ok(())
 }
 
-fn download_file_with_progress(url: &str, filename: &str, target_dir: &Utf8PathBuf) -> Result<()> {
-    let response = ureq::get(url)
-        .call()
-        .map_err(|e| miette::miette!("Failed to download {}: {}", filename, e))?;
+/// Whether `path` already holds the file at `url`: it looks like a complete hashtable and
+/// has the size a HEAD request reports. Without a size to compare, or when the request
+/// fails, the file is downloaded again. Kept files get a fresh modification time, since
+/// that's what the hashtables' age is judged by.
+fn is_up_to_date(agent: &ureq::Agent, url: &str, path: &Utf8Path) -> bool {
+    let Some(local_size) = sane_hashtable_size(path) else {
+        return false;
+    };
+    // ureq asks for gzip by default, and drops Content-Length from compressed responses
+    let remote_size = match agent.head(url).set("Accept-Encoding", "identity").call() {
+        Ok(response) => response
+            .header("Content-Length")
+            .and_then(|s| s.parse::<u64>().ok()),
+        Err(e) => {
+            tracing::debug!("Checking {} failed, downloading it: {}", url, e);
+            None
+        }
+    };
+    if remote_size != Some(local_size) {
+        return false;
+    }
+
+    if let Err(e) = File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(SystemTime::now()))
+    {
+        tracing::debug!("Failed to update the modification time of {}: {}", path, e);
+    }
+    log_file_progress(format_args!(
+        "Kept {} ({} bytes, up to date)",
+        hyperlink_path(path),
+        local_size
+    ));
+    true
+}
+
+/// The size of `path` if it looks like a complete hashtable: not empty, ending with a
+/// newline, and with `<hex hash> <name>` as its first and last lines. Only the ends of
+/// the file are read, a truncated or garbled download fails at least one of them.
+fn sane_hashtable_size(path: &Utf8Path) -> Option<u64> {
+    let mut file = File::open(path).ok()?;
+    let size = file.metadata().ok()?.len();
+    if size == 0 {
+        return None;
+    }
+
+    let mut head = Vec::new();
+    (&mut file)
+        .take(SANITY_CHECK_LEN)
+        .read_to_end(&mut head)
+        .ok()?;
+    let mut tail = Vec::new();
+    file.seek(SeekFrom::Start(size.saturating_sub(SANITY_CHECK_LEN)))
+        .ok()?;
+    file.read_to_end(&mut tail).ok()?;
+
+    let first_line = head.split(|&b| b == b'\n').next()?;
+    let last_line = tail.strip_suffix(b"\n")?.rsplit(|&b| b == b'\n').next()?;
+    (is_hashtable_line(first_line) && is_hashtable_line(last_line)).then_some(size)
+}
+
+/// Whether `line` is `<hex hash> <name>`
+fn is_hashtable_line(line: &[u8]) -> bool {
+    let Ok(line) = std::str::from_utf8(line) else {
+        return false;
+    };
+    let Some((hash, name)) = line.trim_end_matches('\r').split_once(' ') else {
+        return false;
+    };
+    !hash.is_empty()
+        && hash.len() <= 16
+        && hash.bytes().all(|b| b.is_ascii_hexdigit())
+        && !name.trim().is_empty()
+}
+
+/// An agent with the download timeouts, going through `proxy_url` if one is given
+fn build_agent(timeout: Duration, proxy_url: Option<&str>) -> Result<ureq::Agent> {
+    let mut agent_builder = ureq::AgentBuilder::new()
+        .timeout_connect(timeout)
+        .timeout_read(timeout)
+        .try_proxy_from_env(false);
+    if let Some(proxy_url) = proxy_url {
+        tracing::debug!("Using proxy {}", proxy_url);
+        let proxy = ureq::Proxy::new(proxy_url)
+            .map_err(|e| miette::miette!("Invalid proxy '{}': {}", proxy_url, e))?;
+        agent_builder = agent_builder.proxy(proxy);
+    }
+    Ok(agent_builder.build())
+}
+
+/// Picks the proxy for `url`. An explicit proxy always wins, otherwise the standard
+/// `HTTPS_PROXY`/`HTTP_PROXY`/`ALL_PROXY` variables are used unless `NO_PROXY` matches the host.
+fn resolve_proxy_url(explicit: Option<String>, url: &str) -> Option<String> {
+    if explicit.is_some() {
+        return explicit;
+    }
+
+    let (scheme, rest) = url.split_once("://")?;
+    let host = rest
+        .split(['/', '?', '#'])
+        .next()
+        .and_then(|authority| authority.rsplit('@').next())
+        .map(|authority| authority.split(':').next().unwrap_or(authority))?;
+
+    if is_no_proxy_host(host) {
+        return None;
+    }
+
+    let candidates: &[&str] = match scheme {
+        "https" => &["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"],
+        _ => &["HTTP_PROXY", "http_proxy", "ALL_PROXY", "all_proxy"],
+    };
+    candidates
+        .iter()
+        .filter_map(|var| env::var(var).ok())
+        .find(|value| !value.trim().is_empty())
+}
+
+/// Whether `NO_PROXY` excludes `host`, either via `*` or a matching domain suffix
+fn is_no_proxy_host(host: &str) -> bool {
+    let Some(no_proxy) = env::var("NO_PROXY")
+        .ok()
+        .or_else(|| env::var("no_proxy").ok())
+    else {
+        return false;
+    };
+
+    let host = host.to_ascii_lowercase();
+    no_proxy
+        .split(',')
+        .map(|entry| entry.trim().trim_start_matches('.').to_ascii_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| entry == "*" || host == entry || host.ends_with(&format!(".{}", entry)))
+}
+
+fn download_file_with_retry(
+    agent: &ureq::Agent,
+    url: &str,
+    filename: &str,
+    target_dir: &Utf8PathBuf,
+    retries: u32,
+) -> Result<u64> {
+    let mut attempt = 0;
+    loop {
+        match download_file_with_progress(agent, url, filename, target_dir) {
+            Ok(downloaded) => return Ok(downloaded),
+            Err(AttemptError::Transient(e)) if attempt < retries => {
+                let delay = 2u32
+                    .checked_pow(attempt)
+                    .and_then(|factor| RETRY_BASE_DELAY.checked_mul(factor))
+                    .map_or(RETRY_MAX_DELAY, |delay| delay.min(RETRY_MAX_DELAY));
+                attempt += 1;
+                tracing::warn!(
+                    "{}, retrying in {}s ({}/{})",
+                    e,
+                    delay.as_secs(),
+                    attempt,
+                    retries
+                );
+                thread::sleep(delay);
+            }
+            Err(AttemptError::Transient(e) | AttemptError::Fatal(e)) => return Err(e),
+        }
+    }
+}
+
+fn download_file_with_progress(
+    agent: &ureq::Agent,
+    url: &str,
+    filename: &str,
+    target_dir: &Utf8PathBuf,
+) -> Result<u64, AttemptError> {
+    let response = agent.get(url).call().map_err(|e| {
+        let report = Failure::Network(format!("Failed to download {}: {}", filename, e)).into();
+        match e {
+            ureq::Error::Status(code, _) if code < 500 && code != 429 => {
+                AttemptError::Fatal(report)
+            }
+            _ => AttemptError::Transient(report),
+        }
+    })?;
 
     // Get content length for progress bar (if available)
     let content_length: Option<u64> = response
@@ -67,11 +351,12 @@ fn download_file_with_progress(url: &str, filename: &str, target_dir: &Utf8PathB
 
     let target_path = target_dir.join(filename);
     let mut file = File::create(target_path.as_std_path())
-        .into_diagnostic()
-        .wrap_err_with(|| format!("Failed to create file: {}", target_path))?;
+        .write_access(HASHTABLE_DIR_HINT)
+        .wrap_err_with(|| format!("Failed to create file: {}", target_path))
+        .map_err(AttemptError::Fatal)?;
 
     let mut reader = response.into_reader();
-    let mut buffer = [0u8; DOWNLOAD_BUFFER_SIZE];
+    let mut buffer = vec![0u8; io_buffer_size(DOWNLOAD_BUFFER_SIZE)];
     let mut downloaded: u64 = 0;
 
     // Create a tracing span for the progress bar
@@ -94,25 +379,35 @@ fn download_file_with_progress(url: &str, filename: &str, target_dir: &Utf8PathB
     span.pb_set_message(filename);
 
     loop {
-        let bytes_read = reader
-            .read(&mut buffer)
-            .into_diagnostic()
-            .wrap_err("Failed to read from download stream")?;
+        let bytes_read = match reader.read(&mut buffer) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => {
+                return Err(AttemptError::Transient(
+                    Failure::Network(format!(
+                        "Failed to read from download stream for {}: {}",
+                        filename, e
+                    ))
+                    .into(),
+                ));
+            }
+        };
         if bytes_read == 0 {
             break;
         }
 
         file.write_all(&buffer[..bytes_read])
             .into_diagnostic()
-            .wrap_err("Failed to write to file")?;
+            .wrap_err("Failed to write to file")
+            .map_err(AttemptError::Fatal)?;
         downloaded += bytes_read as u64;
         span.pb_set_position(downloaded);
     }
 
-    tracing::info!(
+    log_file_progress(format_args!(
         "Saved {} ({} bytes)",
         hyperlink_path(&target_path),
         downloaded
-    );
-    Ok(())
+    ));
+    Ok(downloaded)
 }
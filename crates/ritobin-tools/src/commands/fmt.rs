@@ -0,0 +1,152 @@
+use std::fs;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use ltk_ritobin::WriterConfig;
+use miette::{IntoDiagnostic, Result, WrapErr};
+use walkdir::WalkDir;
+
+use crate::commands::convert::is_hidden;
+use crate::utils::exit_code::Failure;
+use crate::utils::ritobin_text::{comment_lines, names_from_text};
+use crate::utils::{bin_io, hyperlink_path, log_file_progress};
+
+/// Text extensions that can be formatted
+pub(crate) const SUPPORTED_EXTENSIONS: &[&str] = &["py", "ritobin"];
+
+/// Rewrite ritobin text files in the canonical layout produced by the writer.
+///
+/// Names are kept exactly as written in the input (hex stays hex), so formatting never
+/// depends on the hashtables. With `check`, nothing is written and an error is returned
+/// if any file is not already formatted. Files with comments are refused, since the parser
+/// drops them, unless `strip_comments` is set. Hidden files and directories are skipped
+/// unless `include_hidden` is set.
+pub fn fmt(
+    input: String,
+    check: bool,
+    recursive: bool,
+    strip_comments: bool,
+    include_hidden: bool,
+) -> Result<()> {
+    let input_path = Utf8Path::new(&input);
+    let files = collect_files(input_path, recursive, include_hidden);
+
+    let mut unformatted_count = 0;
+    let mut error_count = 0;
+
+    for path in &files {
+        match format_file(path, check, strip_comments) {
+            Ok(true) => {}
+            Ok(false) => unformatted_count += 1,
+            Err(e) => {
+                tracing::error!("Failed to format {}: {}", path, e);
+                error_count += 1;
+            }
+        }
+    }
+
+    if check {
+        tracing::info!(
+            "Checked {} files: {} not formatted, {} errors",
+            files.len(),
+            unformatted_count,
+            error_count
+        );
+        if unformatted_count > 0 {
+            return Err(miette::miette!(
+                "{} file(s) are not formatted",
+                unformatted_count
+            ));
+        }
+    } else {
+        tracing::info!(
+            "Formatting complete: {} files reformatted, {} errors",
+            unformatted_count,
+            error_count
+        );
+    }
+
+    if error_count > 0 {
+        Err(Failure::Partial(format!("{} file(s) failed to format", error_count)).into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Collect the text files to format, a single file input is taken as-is
+fn collect_files(input_path: &Utf8Path, recursive: bool, include_hidden: bool) -> Vec<Utf8PathBuf> {
+    if !input_path.is_dir() {
+        return vec![input_path.to_path_buf()];
+    }
+
+    let walker = if recursive {
+        WalkDir::new(input_path)
+    } else {
+        WalkDir::new(input_path).max_depth(1)
+    };
+
+    walker
+        .into_iter()
+        .filter_entry(|e| include_hidden || e.depth() == 0 || !is_hidden(e.file_name()))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| Utf8PathBuf::from_path_buf(e.into_path()).ok())
+        .filter(|p| SUPPORTED_EXTENSIONS.contains(&p.extension().unwrap_or("")))
+        .collect()
+}
+
+/// Format a single file. Returns whether it was already formatted.
+/// A leading byte order mark is kept.
+fn format_file(path: &Utf8Path, check: bool, strip_comments: bool) -> Result<bool> {
+    let content = fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read file: {}", path))?;
+    let text = bin_io::strip_bom(&content);
+    let bom = if text.len() < content.len() {
+        bin_io::UTF8_BOM
+    } else {
+        ""
+    };
+
+    // Comments don't survive parsing, refuse rather than silently dropping them
+    let comments = comment_lines(text);
+    if let Some(first) = comments.first() {
+        if !strip_comments {
+            return Err(miette::miette!(
+                "contains {} comment(s) (first on line {}) that formatting would remove, \
+                 use --strip-comments to drop them",
+                comments.len(),
+                first
+            ));
+        }
+        tracing::debug!("Dropping {} comment(s) from {}", comments.len(), path);
+    }
+
+    let tree = ltk_ritobin::parse_to_bin_tree(text)
+        .into_diagnostic()
+        .wrap_err("Failed to parse ritobin file")?;
+
+    let formatted = ltk_ritobin::write_with_config_and_hashes(
+        &tree,
+        WriterConfig::default(),
+        &names_from_text(text),
+    )
+    .into_diagnostic()
+    .wrap_err("Failed to write ritobin format")?;
+    let formatted = format!("{}{}", bom, formatted);
+
+    if formatted == content {
+        tracing::debug!("Already formatted: {}", path);
+        return Ok(true);
+    }
+
+    if check {
+        tracing::warn!("Not formatted: {}", hyperlink_path(path));
+    } else {
+        fs::write(path, formatted)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to write file: {}", path))?;
+        log_file_progress(format_args!("Formatted {}", hyperlink_path(path)));
+    }
+
+    Ok(false)
+}
@@ -0,0 +1,64 @@
+use colored::Colorize;
+use miette::Result;
+
+use crate::commands::convert::OutputFormat;
+use crate::commands::{analyze, convert, diff, fmt, rename, set, validate};
+
+/// Print the file formats the tool reads and writes, and which extensions each command accepts.
+/// Everything is taken from the extension lists the commands check against.
+pub fn formats() -> Result<()> {
+    let width = OutputFormat::ALL
+        .iter()
+        .flat_map(|format| [format.extensions(), format.sources()])
+        .map(|extensions| list_extensions(extensions).len())
+        .max()
+        .unwrap_or_default();
+
+    println!();
+    println!("{}", "Formats".bold());
+    for format in OutputFormat::ALL {
+        println!(
+            "  {:<width$}  {}",
+            list_extensions(format.extensions()).cyan(),
+            format.description()
+        );
+    }
+
+    println!();
+    println!("{}", "Conversions (convert)".bold());
+    for format in OutputFormat::ALL {
+        println!(
+            "  {:<width$}  {} {}",
+            list_extensions(format.sources()).cyan(),
+            "->".bright_black(),
+            list_extensions(format.extensions())
+        );
+    }
+
+    let commands: [(&str, &[&str]); 7] = [
+        ("convert", convert::SUPPORTED_EXTENSIONS),
+        ("diff", diff::SUPPORTED_EXTENSIONS),
+        ("fmt", fmt::SUPPORTED_EXTENSIONS),
+        ("validate", validate::SUPPORTED_EXTENSIONS),
+        ("analyze", analyze::SUPPORTED_EXTENSIONS),
+        ("set", set::SUPPORTED_EXTENSIONS),
+        ("rename", rename::SUPPORTED_EXTENSIONS),
+    ];
+    println!();
+    println!("{}", "Input extensions".bold());
+    for (command, extensions) in commands {
+        println!("  {:<10}{}", command.cyan(), list_extensions(extensions));
+    }
+    println!();
+
+    Ok(())
+}
+
+/// `.bin, .py` style list of extensions
+fn list_extensions(extensions: &[&str]) -> String {
+    extensions
+        .iter()
+        .map(|extension| format!(".{}", extension))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
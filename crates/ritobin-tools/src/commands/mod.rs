@@ -1,6 +1,19 @@
+pub mod analyze;
+pub mod bench;
+pub mod build_hash_cache;
+pub mod check_hashtable;
+pub mod completions;
 pub mod config_cmd;
 pub mod convert;
 pub mod diff;
+pub mod doctor;
 pub mod download_hashes;
+pub mod fmt;
+pub mod formats;
+pub mod rename;
+pub mod resolve;
+pub mod set;
+pub mod sync;
+pub mod validate;
 
 pub use config_cmd::ensure_config_exists;
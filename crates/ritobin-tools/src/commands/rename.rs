@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::fs;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use indexmap::IndexMap;
+use ltk_meta::value::{EmbeddedValue, UnorderedContainerValue};
+use ltk_meta::{BinProperty, BinTree, PropertyValueEnum};
+use miette::{IntoDiagnostic, Result, WrapErr};
+
+use crate::commands::convert::directory_paths;
+use crate::utils::exit_code::Failure;
+use crate::utils::ritobin_text::key_hash;
+use crate::utils::{bin_io, format_hash, hyperlink_path, log_file_progress};
+
+/// File extensions that can be renamed in
+pub(crate) const SUPPORTED_EXTENSIONS: &[&str] = &["bin"];
+
+/// Rename fields and types in .bin files according to a mapping file.
+///
+/// `map` is a TSV file with one `old_name<TAB>new_name` pair per line. Blank lines and
+/// lines starting with `#` are ignored, and names may be given as hex hashes. Every field
+/// and class whose hash matches an old name is rewritten to the hash of the new name.
+/// `input` is a .bin file or a directory, searched recursively with `recursive`.
+/// Hidden files and directories are skipped unless `include_hidden` is set. Files that
+/// fail are reported and skipped, the rest are still renamed.
+pub fn rename(input: String, map: String, recursive: bool, include_hidden: bool) -> Result<()> {
+    let renames = read_rename_map(Utf8Path::new(&map))?;
+
+    let input_path = Utf8Path::new(&input);
+    let mut paths: Vec<Utf8PathBuf> = if input_path.is_dir() {
+        directory_paths(input_path, (!recursive).then_some(1), false, include_hidden)
+            .into_iter()
+            .filter(|path| path.is_file() && is_supported(path))
+            .collect()
+    } else if is_supported(input_path) {
+        vec![input_path.to_path_buf()]
+    } else {
+        return Err(miette::miette!(
+            "Expected a .bin file or a directory, got: {}",
+            input
+        ));
+    };
+    paths.sort();
+
+    let mut total = 0;
+    let mut changed_files = 0;
+    let mut error_count = 0;
+    for path in &paths {
+        let count = match rename_file(path, &renames) {
+            Ok(count) => count,
+            Err(e) => {
+                tracing::error!("Failed to rename in {}: {:?}", path, e);
+                error_count += 1;
+                continue;
+            }
+        };
+        if count > 0 {
+            log_file_progress(format_args!(
+                "Renamed {} name(s) in {}",
+                count,
+                hyperlink_path(path)
+            ));
+            total += count;
+            changed_files += 1;
+        } else {
+            tracing::debug!("No names to rename in {}", path);
+        }
+    }
+
+    tracing::info!(
+        "Rename complete: {} name(s) renamed in {} of {} file(s), {} errors",
+        total,
+        changed_files,
+        paths.len(),
+        error_count
+    );
+
+    if error_count > 0 {
+        Err(Failure::Partial(format!("{} file(s) failed to rename", error_count)).into())
+    } else {
+        Ok(())
+    }
+}
+
+fn is_supported(path: &Utf8Path) -> bool {
+    SUPPORTED_EXTENSIONS.contains(&path.extension().unwrap_or(""))
+}
+
+/// Parse the TSV mapping into old hash -> new hash
+fn read_rename_map(path: &Utf8Path) -> Result<HashMap<u32, u32>> {
+    let content = fs::read_to_string(path)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read rename map: {}", path))?;
+
+    let mut renames = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((old, new)) = line.split_once('\t') else {
+            return Err(miette::miette!(
+                "{}:{}: expected 'old_name<TAB>new_name', got '{}'",
+                path,
+                index + 1,
+                line
+            ));
+        };
+        let (old, new) = (old.trim(), new.trim());
+        if old.is_empty() || new.is_empty() || new.contains('\t') {
+            return Err(miette::miette!(
+                "{}:{}: expected 'old_name<TAB>new_name', got '{}'",
+                path,
+                index + 1,
+                line
+            ));
+        }
+
+        renames.insert(key_hash(old), key_hash(new));
+    }
+
+    if renames.is_empty() {
+        return Err(miette::miette!("No renames found in {}", path));
+    }
+    Ok(renames)
+}
+
+/// Rename names in one file, writing it back only if anything changed
+fn rename_file(path: &Utf8Path, renames: &HashMap<u32, u32>) -> Result<usize> {
+    let mut tree = bin_io::read_bin_file(path)?;
+    let count = rename_in_tree(&mut tree, renames)
+        .wrap_err_with(|| format!("Failed to rename names in {}", path))?;
+
+    if count > 0 {
+        let bytes = bin_io::tree_to_bytes(&tree)?;
+        replace_file(path, &bytes).wrap_err_with(|| format!("Failed to write file: {}", path))?;
+    }
+    Ok(count)
+}
+
+/// Write `bytes` to a temporary file next to `path`, then move it over `path`, so a
+/// failed write leaves the original file untouched
+fn replace_file(path: &Utf8Path, bytes: &[u8]) -> Result<()> {
+    let file_name = path.file_name().unwrap_or("output");
+    let temp_path = path.with_file_name(format!(".{}.rename-tmp", file_name));
+
+    let result = fs::write(&temp_path, bytes).and_then(|_| fs::rename(&temp_path, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result.into_diagnostic()
+}
+
+fn rename_in_tree(tree: &mut BinTree, renames: &HashMap<u32, u32>) -> Result<usize> {
+    let mut count = 0;
+    for object in tree.objects.values_mut() {
+        count += rename_class(&mut object.class_hash, renames);
+        count += rename_properties(&mut object.properties, renames)?;
+    }
+    Ok(count)
+}
+
+fn rename_class(class_hash: &mut u32, renames: &HashMap<u32, u32>) -> usize {
+    match renames.get(class_hash) {
+        Some(&new) => {
+            *class_hash = new;
+            1
+        }
+        None => 0,
+    }
+}
+
+/// Rename the fields of a struct, keeping their order.
+/// Fails if a field would be renamed onto another field of the same struct.
+fn rename_properties(
+    properties: &mut IndexMap<u32, BinProperty>,
+    renames: &HashMap<u32, u32>,
+) -> Result<usize> {
+    let mut count = 0;
+    let mut renamed = IndexMap::with_capacity(properties.len());
+
+    for (hash, mut property) in properties.drain(..) {
+        count += rename_value(&mut property.value, renames)?;
+
+        let new_hash = match renames.get(&hash) {
+            Some(&new) => {
+                count += 1;
+                new
+            }
+            None => hash,
+        };
+        property.name_hash = new_hash;
+
+        if renamed.insert(new_hash, property).is_some() {
+            return Err(miette::miette!(
+                "Two fields of one struct would both be named {} after renaming",
+                format_hash(new_hash)
+            ));
+        }
+    }
+
+    *properties = renamed;
+    Ok(count)
+}
+
+fn rename_value(value: &mut PropertyValueEnum, renames: &HashMap<u32, u32>) -> Result<usize> {
+    match value {
+        PropertyValueEnum::Struct(value) | PropertyValueEnum::Embedded(EmbeddedValue(value)) => {
+            Ok(rename_class(&mut value.class_hash, renames)
+                + rename_properties(&mut value.properties, renames)?)
+        }
+        PropertyValueEnum::Container(container)
+        | PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)) => container
+            .items
+            .iter_mut()
+            .map(|item| rename_value(item, renames))
+            .sum(),
+        PropertyValueEnum::Map(map) => map
+            .entries
+            .values_mut()
+            .map(|value| rename_value(value, renames))
+            .sum(),
+        PropertyValueEnum::Optional(optional) => match optional.value.as_deref_mut() {
+            Some(value) => rename_value(value, renames),
+            None => Ok(0),
+        },
+        _ => Ok(0),
+    }
+}
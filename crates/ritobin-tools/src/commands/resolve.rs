@@ -0,0 +1,88 @@
+use colored::Colorize;
+use fuzzy_matcher::FuzzyMatcher;
+use fuzzy_matcher::skim::SkimMatcherV2;
+use miette::Result;
+
+use crate::utils::config::load_or_create_config;
+use crate::utils::format_hash;
+use crate::utils::hashtable::load_hash_provider;
+
+/// A hashtable name matching the query
+struct ResolvedName<'a> {
+    score: i64,
+    category: &'static str,
+    hash: u32,
+    name: &'a str,
+}
+
+/// Search the loaded hashtables for names fuzzily matching `query`.
+///
+/// Matches from all hash categories (entries, fields, hashes, types) are ranked
+/// by match quality and printed alongside their hash.
+pub fn resolve(query: String, limit: usize) -> Result<()> {
+    let (config, _) = load_or_create_config()?;
+
+    let hashtable_dir = config
+        .hashtable_dir
+        .ok_or_else(|| miette::miette!("No hashtable directory configured"))?;
+    let provider = load_hash_provider(&hashtable_dir, config.combined_hashtable.as_deref())?;
+
+    let matcher = SkimMatcherV2::default().ignore_case();
+    let tables = [
+        ("entry", &provider.entries),
+        ("field", &provider.fields),
+        ("hash", &provider.hashes),
+        ("type", &provider.types),
+    ];
+
+    let mut matches: Vec<ResolvedName> = tables
+        .iter()
+        .flat_map(|(category, table)| {
+            table
+                .iter()
+                .map(move |(hash, name)| (*category, *hash, name))
+        })
+        .filter_map(|(category, hash, name)| {
+            matcher.fuzzy_match(name, &query).map(|score| ResolvedName {
+                score,
+                category,
+                hash,
+                name,
+            })
+        })
+        .collect();
+
+    if matches.is_empty() {
+        println!("{}", format!("No names matching '{}'", query).yellow());
+        return Ok(());
+    }
+
+    // Best matches first, shorter names break ties since they are closer to the query
+    matches.sort_by(|a, b| {
+        b.score
+            .cmp(&a.score)
+            .then(a.name.len().cmp(&b.name.len()))
+            .then(a.name.cmp(b.name))
+            .then(a.hash.cmp(&b.hash))
+    });
+    // Names from a combined hashtable are in every category, list them once
+    matches.dedup_by(|a, b| a.hash == b.hash && a.name == b.name);
+
+    for m in matches.iter().take(limit) {
+        println!(
+            "{} {:<5} {}",
+            format_hash(m.hash).cyan(),
+            m.category.bright_black(),
+            m.name
+        );
+    }
+
+    if matches.len() > limit {
+        println!(
+            "{}",
+            format!("... and {} more", matches.len() - limit).bright_black()
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,251 @@
+use std::fs;
+
+use camino::Utf8Path;
+use ltk_hash::fnv1a::hash_lower;
+use ltk_meta::value::{EmbeddedValue, PropertyValueUnsafeEq, UnorderedContainerValue};
+use ltk_meta::{BinPropertyKind, BinTree, PropertyValueEnum};
+use ltk_ritobin::kind_to_type_name;
+use miette::{IntoDiagnostic, Result, WrapErr};
+
+use crate::utils::{bin_io, hyperlink_path};
+
+/// File extensions that can be patched
+pub(crate) const SUPPORTED_EXTENSIONS: &[&str] = &["bin"];
+
+/// One step of a property path
+enum Segment {
+    /// `.name` or `.0x1234abcd`, a struct field (or the entry itself, for the first segment)
+    Field { name: String, hash: u32 },
+    /// `[index]` into a list, or `[key]` into a map
+    Index(String),
+}
+
+/// Replace a single scalar value in a .bin file.
+///
+/// `path` is the entry name followed by field names and `[index]`/`[key]` lookups,
+/// e.g. `Characters/Ahri/Skins/Skin0.skinScale` or `"Items/1001".mEffects[0].value`.
+/// Names can also be given as hex hashes (`0x1234abcd`). `value` is written in ritobin
+/// syntax and must parse as the type of the value it replaces.
+pub fn set(input: String, path: String, value: String, output: Option<String>) -> Result<()> {
+    let input_path = Utf8Path::new(&input);
+    if !SUPPORTED_EXTENSIONS.contains(&input_path.extension().unwrap_or("")) {
+        return Err(miette::miette!("Expected a .bin file, got: {}", input_path));
+    }
+    let output_path = Utf8Path::new(output.as_deref().unwrap_or(&input));
+
+    let segments = parse_path(&path)?;
+    let mut tree = bin_io::read_bin_file(input_path)?;
+
+    let target = find_value(&mut tree, &segments)
+        .wrap_err_with(|| format!("Invalid property path: {}", path))?;
+
+    let kind = target.kind();
+    if !is_scalar(kind) {
+        return Err(miette::miette!(
+            "{} is a {}, only scalar values can be set",
+            path,
+            kind_to_type_name(kind)
+        ));
+    }
+    *target = parse_value(kind, &value)?;
+
+    let bytes = bin_io::tree_to_bytes(&tree)?;
+    fs::write(output_path, bytes)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write file: {}", output_path))?;
+
+    tracing::info!(
+        "Set {} = {} in {}",
+        path,
+        value,
+        hyperlink_path(output_path)
+    );
+    Ok(())
+}
+
+/// Split a property path into segments. The entry name may be quoted when it contains `.` or `[`.
+fn parse_path(path: &str) -> Result<Vec<Segment>> {
+    let invalid = |reason: &str| miette::miette!("Invalid property path '{}': {}", path, reason);
+
+    let mut segments = Vec::new();
+    let mut rest = path;
+
+    if let Some(quoted) = rest.strip_prefix('"') {
+        let end = quoted.find('"').ok_or_else(|| invalid("unclosed quote"))?;
+        segments.push(field_segment(&quoted[..end]));
+        rest = &quoted[end + 1..];
+    } else {
+        let end = rest.find(['.', '[']).unwrap_or(rest.len());
+        segments.push(field_segment(&rest[..end]));
+        rest = &rest[end..];
+    }
+
+    while !rest.is_empty() {
+        if let Some(field) = rest.strip_prefix('.') {
+            let end = field.find(['.', '[']).unwrap_or(field.len());
+            if end == 0 {
+                return Err(invalid("empty field name"));
+            }
+            segments.push(field_segment(&field[..end]));
+            rest = &field[end..];
+        } else if let Some(index) = rest.strip_prefix('[') {
+            let end = index.find(']').ok_or_else(|| invalid("unclosed '['"))?;
+            segments.push(Segment::Index(index[..end].trim().to_string()));
+            rest = &index[end + 1..];
+        } else {
+            return Err(invalid("expected '.' or '[' between segments"));
+        }
+    }
+
+    match segments.as_slice() {
+        [Segment::Field { name, .. }, ..] if name.is_empty() => Err(invalid("missing entry name")),
+        [_, Segment::Field { .. }, ..] => Ok(segments),
+        _ => Err(invalid("expected an entry name followed by a field")),
+    }
+}
+
+/// A name segment, hex names like `0x1234abcd` are taken as the hash itself
+fn field_segment(name: &str) -> Segment {
+    let hash = name
+        .strip_prefix("0x")
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .unwrap_or_else(|| hash_lower(name));
+
+    Segment::Field {
+        name: name.to_string(),
+        hash,
+    }
+}
+
+/// Walk the tree along `segments`, returning the value they point to
+fn find_value<'a>(
+    tree: &'a mut BinTree,
+    segments: &[Segment],
+) -> Result<&'a mut PropertyValueEnum> {
+    let [
+        Segment::Field {
+            name: entry_name,
+            hash: entry_hash,
+        },
+        Segment::Field {
+            name: field_name,
+            hash: field_hash,
+        },
+        rest @ ..,
+    ] = segments
+    else {
+        unreachable!("parse_path checks the entry and first field")
+    };
+
+    let object = tree
+        .objects
+        .get_mut(entry_hash)
+        .ok_or_else(|| miette::miette!("No entry named '{}'", entry_name))?;
+    let mut value = &mut object
+        .properties
+        .get_mut(field_hash)
+        .ok_or_else(|| miette::miette!("'{}' has no field '{}'", entry_name, field_name))?
+        .value;
+
+    for segment in rest {
+        value = step(value, segment)?;
+    }
+
+    Ok(value)
+}
+
+/// Follow one segment from `value`. Optionals are looked through transparently.
+fn step<'a>(
+    value: &'a mut PropertyValueEnum,
+    segment: &Segment,
+) -> Result<&'a mut PropertyValueEnum> {
+    let value = match value {
+        PropertyValueEnum::Optional(optional) => optional
+            .value
+            .as_deref_mut()
+            .ok_or_else(|| miette::miette!("Optional value is empty"))?,
+        value => value,
+    };
+    let kind = value.kind();
+
+    match (value, segment) {
+        (PropertyValueEnum::Struct(value), Segment::Field { name, hash })
+        | (PropertyValueEnum::Embedded(EmbeddedValue(value)), Segment::Field { name, hash }) => {
+            value
+                .properties
+                .get_mut(hash)
+                .map(|property| &mut property.value)
+                .ok_or_else(|| miette::miette!("No field named '{}'", name))
+        }
+        (PropertyValueEnum::Container(container), Segment::Index(index))
+        | (
+            PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)),
+            Segment::Index(index),
+        ) => {
+            let len = container.items.len();
+            index
+                .parse::<usize>()
+                .ok()
+                .and_then(|i| container.items.get_mut(i))
+                .ok_or_else(|| {
+                    miette::miette!("Invalid index [{}] into a list of {} item(s)", index, len)
+                })
+        }
+        (PropertyValueEnum::Map(map), Segment::Index(key)) => {
+            let parsed = PropertyValueUnsafeEq(parse_value(map.key_kind, key)?);
+            map.entries
+                .get_mut(&parsed)
+                .ok_or_else(|| miette::miette!("No map entry with key [{}]", key))
+        }
+        (_, Segment::Field { name, .. }) => Err(miette::miette!(
+            "Can't look up field '{}' in a {}",
+            name,
+            kind_to_type_name(kind)
+        )),
+        (_, Segment::Index(index)) => Err(miette::miette!(
+            "Can't index [{}] into a {}",
+            index,
+            kind_to_type_name(kind)
+        )),
+    }
+}
+
+/// Whether values of `kind` can be replaced by `set`
+fn is_scalar(kind: BinPropertyKind) -> bool {
+    match kind {
+        BinPropertyKind::None => false,
+        BinPropertyKind::ObjectLink | BinPropertyKind::BitBool => true,
+        kind => kind.is_primitive(),
+    }
+}
+
+/// Parse `text` as a ritobin value of type `kind`.
+/// Strings and names may be given without quotes.
+fn parse_value(kind: BinPropertyKind, text: &str) -> Result<PropertyValueEnum> {
+    let type_name = kind_to_type_name(kind);
+    let needs_quotes = match kind {
+        BinPropertyKind::String => true,
+        BinPropertyKind::Hash | BinPropertyKind::ObjectLink | BinPropertyKind::WadChunkLink => {
+            !text.starts_with("0x") && text.parse::<u64>().is_err()
+        }
+        _ => false,
+    };
+    let literal = if needs_quotes && !text.starts_with(['"', '\'']) {
+        format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        text.to_string()
+    };
+
+    // Parse as a one-field ritobin document so values use exactly the text format's syntax
+    let source = format!("value: {} = {}\n", type_name, literal);
+    let invalid = || miette::miette!("'{}' is not a valid {} value", text, type_name);
+    let mut file = ltk_ritobin::parse(&source).map_err(|_| invalid())?;
+    if file.entries.len() != 1 {
+        return Err(invalid());
+    }
+
+    file.entries
+        .swap_remove("value")
+        .map(|property| property.value)
+        .ok_or_else(invalid)
+}
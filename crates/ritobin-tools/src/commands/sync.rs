@@ -0,0 +1,167 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::time::SystemTime;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use miette::{IntoDiagnostic, Result, WrapErr};
+
+use crate::commands::convert::{ConvertOptions, convert_file, directory_paths};
+use crate::utils::config::load_or_create_config;
+use crate::utils::exit_code::Failure;
+use crate::utils::hashtable::LazyHashProvider;
+use crate::utils::{hyperlink_path, log_file_progress};
+
+/// The two files of one bin/text pair, by path below the synced directories without extension
+#[derive(Debug, Default)]
+struct Pair {
+    bin: Option<Utf8PathBuf>,
+    text: Option<Utf8PathBuf>,
+}
+
+/// Which way a pair needs converting
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    ToText,
+    ToBin,
+}
+
+/// Keep a directory of .bin files and a mirrored directory of .py files in sync.
+///
+/// A .bin without a .py at the same relative path, or newer than it, is converted to text,
+/// and a .py without a .bin, or newer than it, back to binary. Outputs get the modification
+/// time of the file they were converted from, so the next sync sees the pair as up to date.
+/// With `dry_run`, only the conversions that would happen are reported.
+pub fn sync(bin_dir: String, text_dir: String, dry_run: bool) -> Result<()> {
+    let bin_dir = Utf8PathBuf::from(bin_dir);
+    let text_dir = Utf8PathBuf::from(text_dir);
+    for dir in [&bin_dir, &text_dir] {
+        if !dir.is_dir() {
+            return Err(miette::miette!("{} is not a directory", dir));
+        }
+    }
+
+    let mut pairs: BTreeMap<Utf8PathBuf, Pair> = BTreeMap::new();
+    for path in files_with_extension(&bin_dir, "bin") {
+        let key = relative_stem(&bin_dir, &path);
+        pairs.entry(key).or_default().bin = Some(path);
+    }
+    for path in files_with_extension(&text_dir, "py") {
+        let key = relative_stem(&text_dir, &path);
+        pairs.entry(key).or_default().text = Some(path);
+    }
+
+    let (config, _) = load_or_create_config()?;
+    let hashes = LazyHashProvider::new(config.hashtable_dir, config.combined_hashtable);
+    let options = ConvertOptions::default();
+
+    let mut to_text = 0;
+    let mut to_bin = 0;
+    let mut up_to_date = 0;
+    let mut error_count = 0;
+    for (key, pair) in &pairs {
+        let (input, output, direction) = match (&pair.bin, &pair.text) {
+            (Some(bin), None) => (bin, text_dir.join(format!("{}.py", key)), Direction::ToText),
+            (None, Some(text)) => (text, bin_dir.join(format!("{}.bin", key)), Direction::ToBin),
+            (Some(bin), Some(text)) => match newer(bin, text) {
+                Some(Direction::ToText) => (bin, text.clone(), Direction::ToText),
+                Some(Direction::ToBin) => (text, bin.clone(), Direction::ToBin),
+                None => {
+                    tracing::debug!("Up to date: {}", key);
+                    up_to_date += 1;
+                    continue;
+                }
+            },
+            (None, None) => continue,
+        };
+
+        if dry_run {
+            log_file_progress(format_args!(
+                "Would convert {} -> {}",
+                hyperlink_path(input),
+                hyperlink_path(&output)
+            ));
+        } else if let Err(e) = sync_file(input, &output, &options, &hashes) {
+            tracing::error!("Failed to sync {}: {}", hyperlink_path(input), e);
+            tracing::debug!("{:?}", e);
+            error_count += 1;
+            continue;
+        }
+        match direction {
+            Direction::ToText => to_text += 1,
+            Direction::ToBin => to_bin += 1,
+        }
+    }
+
+    tracing::info!(
+        "Sync {}: {} to text, {} to bin, {} up to date, {} errors",
+        if dry_run {
+            "dry run complete"
+        } else {
+            "complete"
+        },
+        to_text,
+        to_bin,
+        up_to_date,
+        error_count
+    );
+    if error_count > 0 {
+        return Err(Failure::Partial(format!("{} file(s) failed to sync", error_count)).into());
+    }
+    Ok(())
+}
+
+/// Convert `input` to `output`, then give the output the input's modification time
+fn sync_file(
+    input: &Utf8Path,
+    output: &Utf8Path,
+    options: &ConvertOptions,
+    hashes: &LazyHashProvider,
+) -> Result<()> {
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to create directory: {}", parent))?;
+    }
+    convert_file(input, Some(output.to_path_buf()), options, hashes)?;
+
+    let modified = fs::metadata(input)
+        .and_then(|metadata| metadata.modified())
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read modification time of {}", input))?;
+    File::options()
+        .write(true)
+        .open(output)
+        .and_then(|file| file.set_modified(modified))
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to set modification time of {}", output))
+}
+
+/// Which file of a pair is newer, `None` when they have the same modification time
+fn newer(bin: &Utf8Path, text: &Utf8Path) -> Option<Direction> {
+    let modified = |path: &Utf8Path| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .unwrap_or(SystemTime::UNIX_EPOCH)
+    };
+    let (bin_modified, text_modified) = (modified(bin), modified(text));
+    if bin_modified > text_modified {
+        Some(Direction::ToText)
+    } else if text_modified > bin_modified {
+        Some(Direction::ToBin)
+    } else {
+        None
+    }
+}
+
+/// Every file below `dir` with the given extension, skipping hidden files and directories
+fn files_with_extension(dir: &Utf8Path, extension: &str) -> Vec<Utf8PathBuf> {
+    directory_paths(dir, None, false, false)
+        .into_iter()
+        .filter(|path| path.is_file() && path.extension() == Some(extension))
+        .collect()
+}
+
+/// `path` relative to `dir`, without its extension
+fn relative_stem(dir: &Utf8Path, path: &Utf8Path) -> Utf8PathBuf {
+    path.strip_prefix(dir).unwrap_or(path).with_extension("")
+}
@@ -0,0 +1,278 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::ValueEnum;
+use ltk_ritobin::HashMapProvider;
+use miette::{Diagnostic, Result};
+use serde::Serialize;
+
+use crate::commands::convert::{directory_paths, glob_paths, is_glob_pattern};
+use crate::utils::exit_code::Failure;
+use crate::utils::ritobin_text::names_from_text;
+use crate::utils::schema::Schema;
+use crate::utils::{bin_io, hyperlink_path, log_file_progress, read_file_list};
+
+/// File extensions that can be validated
+pub(crate) const SUPPORTED_EXTENSIONS: &[&str] = &["bin", "py", "ritobin"];
+
+/// How validation results are reported
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum ValidateFormat {
+    /// Log every file and a summary
+    #[default]
+    Text,
+    /// Print a JSON array of `{path, ok, error}` objects to stdout
+    Json,
+}
+
+/// Options for a validation run
+#[derive(Debug, Default)]
+pub struct ValidateOptions {
+    /// Search directory inputs recursively
+    pub recursive: bool,
+    /// Walk into symlinked directories, skipping symlink cycles
+    pub follow_symlinks: bool,
+    /// Include hidden files and directories (names starting with `.`)
+    pub include_hidden: bool,
+    pub format: ValidateFormat,
+    /// Also print every failure as a GitHub Actions `::error` command
+    pub github_annotations: bool,
+    /// Schema every file must match, see [`Schema`]
+    pub schema: Option<Schema>,
+    /// File listing more files to validate one per line, `-` for stdin
+    pub files_from: Option<String>,
+    /// Only validate the first this many files
+    pub limit: Option<usize>,
+}
+
+/// The outcome of validating one file
+#[derive(Debug, Serialize)]
+struct ValidationResult {
+    path: String,
+    ok: bool,
+    error: Option<String>,
+    /// Format version of a .bin file whose header could be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u32>,
+    /// 1-based line of a ritobin parse error, used for annotations
+    #[serde(skip)]
+    line: Option<usize>,
+}
+
+/// Check that .bin and ritobin text files parse, without writing anything.
+///
+/// `input` may be a file, a directory (searched recursively with `recursive`) or a glob
+/// pattern, `follow_symlinks` also walks symlinked directories and `include_hidden`
+/// includes hidden files and directories. The supported files listed in `files_from` are
+/// validated too, and `input` may be left out when it is given. With `github_annotations`,
+/// every failure is also printed as a GitHub Actions `::error` command so it shows up
+/// inline on pull requests. With a `schema`, files that parse but don't match it are
+/// invalid too.
+pub fn validate(input: Option<String>, options: ValidateOptions) -> Result<()> {
+    let ValidateOptions {
+        recursive,
+        follow_symlinks,
+        include_hidden,
+        format,
+        github_annotations,
+        ref schema,
+        ref files_from,
+        limit,
+    } = options;
+    let schema = schema.as_ref();
+
+    let mut paths: Vec<Utf8PathBuf> = match files_from {
+        Some(files_from) => read_file_list(files_from)?
+            .into_iter()
+            .filter(|path| path.is_file() && is_supported(path))
+            .collect(),
+        None => Vec::new(),
+    };
+    if let Some(input) = &input {
+        let input_path = Utf8Path::new(input);
+        if input_path.is_dir() {
+            paths.extend(
+                directory_paths(
+                    input_path,
+                    (!recursive).then_some(1),
+                    follow_symlinks,
+                    include_hidden,
+                )
+                .into_iter()
+                .filter(|path| path.is_file() && is_supported(path)),
+            );
+        } else if !input_path.exists() && is_glob_pattern(input) {
+            paths.extend(
+                glob_paths(input, include_hidden)?
+                    .into_iter()
+                    .filter(|path| path.is_file() && is_supported(path)),
+            );
+        } else if is_supported(input_path) {
+            paths.push(input_path.to_path_buf());
+        } else {
+            return Err(miette::miette!(
+                "Unsupported file extension: {}. Supported extensions: .bin, .py, .ritobin",
+                input_path
+            ));
+        }
+    }
+    // Stable order, so results can be compared between runs
+    paths.sort();
+    paths.dedup();
+    let matched = paths.len();
+    if let Some(limit) = limit {
+        paths.truncate(limit);
+    }
+
+    let results: Vec<ValidationResult> = paths
+        .iter()
+        .map(|path| {
+            let result = validate_file(path, schema);
+            if format == ValidateFormat::Text {
+                match (&result.error, result.version) {
+                    (None, Some(version)) => log_file_progress(format_args!(
+                        "Valid {} (bin version {})",
+                        hyperlink_path(path),
+                        version
+                    )),
+                    (None, None) => {
+                        log_file_progress(format_args!("Valid {}", hyperlink_path(path)))
+                    }
+                    (Some(error), _) => {
+                        tracing::error!("Invalid {}: {}", hyperlink_path(path), error)
+                    }
+                }
+            }
+            result
+        })
+        .collect();
+
+    if github_annotations {
+        for result in results.iter().filter(|result| !result.ok) {
+            println!("{}", github_annotation(result));
+        }
+    }
+
+    let failed = results.iter().filter(|result| !result.ok).count();
+    match format {
+        ValidateFormat::Text => tracing::info!(
+            "Validation complete: {} files valid, {} invalid",
+            results.len() - failed,
+            failed
+        ),
+        ValidateFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&results)
+                .map_err(|e| miette::miette!("Failed to serialize results: {}", e))?
+        ),
+    }
+    // JSON output is the whole of stdout, which the log shares
+    if format == ValidateFormat::Text && matched > results.len() {
+        tracing::info!(
+            "Stopped early at --limit: validated {} of {} matching files",
+            results.len(),
+            matched
+        );
+    }
+
+    if failed > 0 {
+        return Err(Failure::Partial(format!("{} file(s) failed validation", failed)).into());
+    }
+    Ok(())
+}
+
+fn is_supported(path: &Utf8Path) -> bool {
+    SUPPORTED_EXTENSIONS.contains(&path.extension().unwrap_or(""))
+}
+
+/// Parse one file, logging the full error report at debug level
+fn validate_file(path: &Utf8Path, schema: Option<&Schema>) -> ValidationResult {
+    let version = (path.extension() == Some("bin"))
+        .then(|| bin_io::read_bin_header_file(path).ok())
+        .flatten()
+        .map(|header| header.version);
+    let (error, line) = match check_file(path, schema) {
+        Ok(()) => (None, None),
+        Err((report, line)) => {
+            tracing::debug!("{:?}", report);
+            let message = report
+                .chain()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(": ");
+            (Some(message), line)
+        }
+    };
+
+    ValidationResult {
+        path: path.to_string(),
+        ok: error.is_none(),
+        error,
+        version,
+        line,
+    }
+}
+
+/// Parse a file and check it against the schema, returning the error and the line it
+/// points at (for ritobin text parse errors)
+fn check_file(
+    path: &Utf8Path,
+    schema: Option<&Schema>,
+) -> Result<(), (miette::Report, Option<usize>)> {
+    let (tree, names) = if path.extension() == Some("bin") {
+        let tree = bin_io::read_bin_file(path).map_err(|e| (e, None))?;
+        (tree, HashMapProvider::default())
+    } else {
+        let text = bin_io::read_text_file(path).map_err(|e| (e, None))?;
+        let tree = ltk_ritobin::parse_to_bin_tree(&text).map_err(|e| {
+            let line = e
+                .labels()
+                .and_then(|mut labels| labels.next())
+                .map(|label| text[..label.offset().min(text.len())].matches('\n').count() + 1);
+            let report = miette::Report::new(e).wrap_err(format!("Failed to parse {}", path));
+            (report, line)
+        })?;
+        (tree, names_from_text(&text))
+    };
+
+    let Some(schema) = schema else {
+        return Ok(());
+    };
+    let violations = schema.check(&tree, &names);
+    if violations.is_empty() {
+        return Ok(());
+    }
+    let messages: Vec<_> = violations
+        .iter()
+        .map(|violation| format!("{}: {}", violation.path, violation.message))
+        .collect();
+    Err((
+        miette::miette!(
+            "{} schema violation(s): {}",
+            violations.len(),
+            messages.join("; ")
+        ),
+        None,
+    ))
+}
+
+/// Format a failure as a GitHub Actions workflow command
+fn github_annotation(result: &ValidationResult) -> String {
+    let mut properties = format!("file={}", escape_property(&result.path));
+    if let Some(line) = result.line {
+        properties.push_str(&format!(",line={}", line));
+    }
+    let message = result.error.as_deref().unwrap_or_default();
+    format!("::error {}::{}", properties, escape_data(message))
+}
+
+/// Escape a workflow command message
+fn escape_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+/// Escape a workflow command property value
+fn escape_property(value: &str) -> String {
+    escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
@@ -1,15 +1,35 @@
 use clap::builder::{Styles, styling::AnsiColor};
 use clap::{ColorChoice, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
 use miette::Result;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::process::ExitCode;
+use std::time::SystemTime;
 use tracing::Level;
 use tracing_indicatif::IndicatifLayer;
+use tracing_subscriber::field::MakeExt;
 use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::FormatFields;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::{filter, fmt};
 
-use crate::commands::{config_cmd, convert, diff, download_hashes};
+use crate::commands::convert::{
+    Compression, ConvertOptions, ExecCommand, LineEnding, NameTemplate, OnCollision,
+};
+use crate::commands::diff::{DiffOptions, OutputEncoding};
+use crate::commands::validate::{ValidateFormat, ValidateOptions};
+use crate::commands::{
+    analyze, bench, build_hash_cache, check_hashtable, completions, config_cmd, convert, diff,
+    doctor, download_hashes, formats, rename, resolve, set, sync, validate,
+};
+use crate::utils::exit_code::{Failure, exit_code};
+use crate::utils::hashtable::{self, UpdatePolicy};
+use crate::utils::schema::Schema;
+use crate::utils::{LinkStyle, TextExtension};
 
 mod commands;
 mod utils;
@@ -27,6 +47,10 @@ pub enum ConfigAction {
     },
     /// Reset configuration to defaults
     Reset,
+    /// Interactively set up the configuration, optionally downloading the hashtables.
+    /// Writes the defaults without asking when stdin isn't a terminal.
+    #[command(alias = "wizard")]
+    Init,
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
@@ -61,6 +85,47 @@ impl VerbosityLevel {
     }
 }
 
+#[derive(Copy, Clone, Debug, Default, ValueEnum)]
+pub enum ColorMode {
+    /// Color output when writing to a terminal
+    #[default]
+    Auto,
+    /// Always color output
+    Always,
+    /// Never color output
+    Never,
+}
+
+impl ColorMode {
+    /// Resolve to whether colors are used, `auto` checks stdout for a TTY and honors `NO_COLOR`
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorMode::Auto => io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none(),
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human-readable log lines
+    #[default]
+    Text,
+    /// One JSON object per event and line (NDJSON) on stdout
+    Json,
+}
+
+impl From<ColorMode> for ColorChoice {
+    fn from(mode: ColorMode) -> Self {
+        match mode {
+            ColorMode::Auto => ColorChoice::Auto,
+            ColorMode::Always => ColorChoice::Always,
+            ColorMode::Never => ColorChoice::Never,
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None, styles = cli_styles())]
 struct Args {
@@ -68,6 +133,66 @@ struct Args {
     #[arg(short = 'L', long, value_enum, default_value_t = VerbosityLevel::Info)]
     verbosity: VerbosityLevel,
 
+    /// Don't log a line per converted, formatted or validated file, only the summary,
+    /// warnings and errors. The per-file lines are still logged at debug verbosity
+    #[arg(short, long, visible_alias = "silent", global = true)]
+    quiet: bool,
+
+    /// When to use colors and hyperlinks in output
+    #[arg(long, value_enum, default_value_t = ColorMode::Auto, global = true)]
+    color: ColorMode,
+
+    /// Format of log output
+    #[arg(long, value_enum, default_value_t = LogFormat::Text, global = true)]
+    log_format: LogFormat,
+
+    /// How to render paths in output, overrides `link_style` from the config
+    #[arg(long, value_enum, global = true)]
+    link_style: Option<LinkStyle>,
+
+    /// Buffer size for file and network I/O (e.g. 256K, 1M), overrides `io_buffer_size`
+    #[arg(long, value_name = "BYTES", value_parser = utils::parse_byte_size, global = true)]
+    buffer_size: Option<usize>,
+
+    /// Override a config.toml value for this run only, e.g. `--set hashtable_dir=./hashes`.
+    /// Repeatable, nothing is written to config.toml
+    #[arg(
+        long = "set",
+        visible_alias = "config-override",
+        value_name = "KEY=VALUE",
+        value_parser = utils::config::parse_config_override,
+        global = true
+    )]
+    config_overrides: Vec<(String, String)>,
+
+    /// Log every hash lookup made while writing ritobin text: the name it resolved to and
+    /// whether the hashtables or hash_resolver_cmd named it, or that it stays hex.
+    /// Logged at trace level, so only shown with `-L trace`
+    #[arg(long, global = true)]
+    trace_hashes: bool,
+
+    /// Fail when config.toml can't be parsed, instead of warning and using the defaults
+    #[arg(long, global = true)]
+    strict_config: bool,
+
+    /// Fail when a hashtable file can't be loaded, instead of warning and using the others
+    #[arg(long, global = true)]
+    strict_hashtable: bool,
+
+    /// Don't ask before destructive operations like `config reset` or overwriting existing
+    /// outputs, answer yes. Nothing is asked anyway when stdin isn't a terminal
+    #[arg(short, long, visible_alias = "assume-yes", global = true)]
+    yes: bool,
+
+    /// Download fresh hashtables when they are older than `hashtable_max_age_days`,
+    /// overrides `hashtable_auto_update`
+    #[arg(long, global = true, conflicts_with = "no_auto_update")]
+    auto_update: bool,
+
+    /// Only warn about hashtables older than `hashtable_max_age_days`, never download them
+    #[arg(long, global = true)]
+    no_auto_update: bool,
+
     /// Optional path to a config file (TOML). Defaults to `ritobin-tools.toml` if present
     #[arg(long)]
     config: Option<String>,
@@ -81,38 +206,418 @@ struct Args {
     command: Commands,
 }
 
+// Parsed once per run, boxing the big `convert` variant would gain nothing
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Convert between .bin (binary) and .py/.ritobin (text) formats
     Convert {
+        #[arg(required_unless_present = "files_from")]
         /// Path to the input file. The output format is automatically determined based on the file extension.
-        input: String,
+        input: Option<String>,
 
         #[arg(long, short)]
         /// Path to the output file. If not provided, the output will be written to the same directory as the input file.
+        /// A .bin, .py/.ritobin or .json extension selects the output format, e.g. '-o out.json' for a .bin input.
         output: Option<String>,
 
+        #[arg(long, value_name = "DIR", conflicts_with_all = ["output", "bundle", "files_from"])]
+        /// Write the outputs of a directory or glob input into DIR, keeping their subdirectories
+        /// below the input directory (e.g. data/a/b.bin -> DIR/a/b.py)
+        output_dir: Option<String>,
+
         #[arg(long, short)]
         /// Whether to recursively convert all files in the input directory. Only valid if the input is a directory.
         /// If the input is a file, this option is ignored.
         recursive: bool,
+
+        #[arg(long)]
+        /// Write a SHA-256 checksum of every output to `<output>.sha256`. .bin outputs are
+        /// usually streamed to disk and hashed by rereading them
+        sha256: bool,
+
+        #[arg(long)]
+        /// Write an output that is byte for byte identical to an earlier output of the run as a
+        /// symlink to it instead of another copy. Outputs are compared by SHA-256.
+        dedup: bool,
+
+        #[arg(long, value_name = "TEMPLATE", value_parser = NameTemplate::parse)]
+        /// Template for default output paths, e.g. '{stem}.generated.{ext}' or '{dir}/{stem}_{hash8}.bin'.
+        /// Placeholders: {stem}, {ext}, {dir}, {hash8} (short content hash).
+        name_template: Option<NameTemplate>,
+
+        #[arg(long)]
+        /// Fail on ritobin input that defines the same key twice in one block
+        strict: bool,
+
+        #[arg(long)]
+        /// Memory-map .bin input instead of reading it through a buffer.
+        /// Avoids copying large files through the read buffer, but a file modified during conversion can crash the process.
+        mmap: bool,
+
+        #[arg(long, value_name = "EXT", value_delimiter = ',', value_parser = ["bin", "py", "ritobin"])]
+        /// Only convert files with these extensions when converting a directory or glob, e.g. '--only bin'
+        only: Vec<String>,
+
+        #[arg(long, value_enum)]
+        /// Extension of text outputs when .bin is converted without an explicit output path. Only
+        /// the name changes, the content is the same. Overrides `text_extension` from the config.
+        ext: Option<TextExtension>,
+
+        #[arg(long, conflicts_with = "bundle")]
+        /// Treat ritobin input as several documents split by --separator and write numbered .bin files
+        multi: bool,
+
+        #[arg(long, requires = "output")]
+        /// Convert every .bin input into a single ritobin text file at --output
+        bundle: bool,
+
+        #[arg(long, value_name = "LINE")]
+        /// Line separating documents for --multi and --bundle (default: the '#PROP_text' header)
+        separator: Option<String>,
+
+        #[arg(long)]
+        /// Log paths relative to the input directory (or the working directory) for reproducible logs
+        relative_paths: bool,
+
+        #[arg(long)]
+        /// Fail .bin conversions that leave any hash without a name instead of writing it as hex
+        require_names: bool,
+
+        #[arg(long)]
+        /// Fail ritobin to .bin conversions using a name the hashtables don't know, listing each with its line.
+        /// Catches misspelled names, which would otherwise be hashed as written. Hex hashes are always accepted.
+        known_names_only: bool,
+
+        #[arg(long, value_name = "TIME", value_parser = convert::parse_since)]
+        /// Only convert files modified since TIME when converting a directory or glob.
+        /// TIME is a duration ago (e.g. '2h', '3d') or an RFC 3339 timestamp (e.g. '2024-05-01T12:00:00Z').
+        since: Option<SystemTime>,
+
+        #[arg(long, value_name = "N")]
+        /// Descend at most N directory levels when converting a directory (1 = only its own files).
+        /// Takes precedence over --recursive.
+        max_depth: Option<usize>,
+
+        #[arg(long, value_name = "N", conflicts_with = "bundle")]
+        /// Stop after N matching files of a directory, glob or --files-from run, e.g. to
+        /// spot-check a huge dump before converting all of it
+        limit: Option<usize>,
+
+        #[arg(long, value_name = "ROOT")]
+        /// Look up the bins a .bin links to under ROOT (e.g. an extracted game), following
+        /// their links in turn, and list them with their entries as comments at the end of
+        /// the text output. Links that aren't found are listed and warned about
+        resolve_links: Option<String>,
+
+        #[arg(long)]
+        /// Start ritobin text output with a comment naming the source file, tool version,
+        /// date and hashtables used, to trace checked-in text back to the bin it came from
+        header: bool,
+
+        #[arg(long)]
+        /// Follow symlinks when converting a directory, skipping symlink cycles
+        follow_symlinks: bool,
+
+        #[arg(long)]
+        /// Also convert hidden files and files in hidden directories (e.g. .git) of a directory or glob input
+        include_hidden: bool,
+
+        #[arg(long, value_name = "CMD", value_parser = ExecCommand::parse, conflicts_with = "bundle")]
+        /// Run CMD after every converted file, replacing {input} and {output} in its arguments.
+        /// The command is not run through a shell. A failing command counts as a failed conversion.
+        exec: Option<ExecCommand>,
+
+        #[arg(long, conflicts_with = "bundle")]
+        /// Log parse, serialize and write time per file (at debug level) and list the slowest files at the end
+        timings: bool,
+
+        #[arg(long, value_name = "FILE", conflicts_with = "bundle")]
+        /// Write per-file timings in milliseconds to FILE as CSV
+        timings_out: Option<String>,
+
+        #[arg(long)]
+        /// Start .bin to text output with a UTF-8 byte order mark when the file being replaced had one.
+        /// Byte order marks in ritobin input are always ignored.
+        preserve_bom: bool,
+
+        #[arg(long, conflicts_with_all = ["bundle", "output", "timings", "timings_out"])]
+        /// Don't convert anything, only report how many files match the input and filters
+        /// (--only, --since, --max-depth, ...) and their total size
+        count: bool,
+
+        #[arg(long, conflicts_with = "count")]
+        /// Print the path of every written file to stdout, one per line, and send all logs to stderr.
+        /// Useful for scripts, e.g. OUT=$(ritobin-tools convert in.bin --print-output-path)
+        print_output_path: bool,
+
+        #[arg(long, value_name = "FILE")]
+        /// Ritobin template listing the allowed types and their fields. Fail files whose entries
+        /// use other types, unknown fields or fields with a different value type
+        schema: Option<String>,
+
+        #[arg(long, value_name = "DEPTH", num_args = 0..=1, default_missing_value = "0")]
+        /// Write a compact overview of a .bin: only the entries' names and types, or with DEPTH
+        /// also that many levels of their values. Deeper structs, lists and maps are written empty.
+        /// Applies to text and JSON output only
+        entries_only: Option<usize>,
+
+        #[arg(long, value_name = "PREFIX")]
+        /// Only convert the entries whose name starts with PREFIX, ignoring case (e.g. 'Characters/Aatrox/').
+        /// Can be repeated. Inputs without a matching entry are skipped unless --keep-empty is given
+        entry_prefix: Vec<String>,
+
+        #[arg(long, requires = "entry_prefix")]
+        /// Write inputs without entries matching --entry-prefix as files with no entries instead of skipping them
+        keep_empty: bool,
+
+        #[arg(long, value_enum, default_value_t = LineEnding::Lf)]
+        /// Newline style of written ritobin text. `lf` keeps output identical across platforms,
+        /// `native` uses CRLF on Windows
+        line_endings: LineEnding,
+
+        #[arg(long, value_enum, value_name = "FORMAT")]
+        /// Compress text and JSON output, writing e.g. `<output>.py.gz` or `<output>.py.zst`
+        compress: Option<Compression>,
+
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["bundle", "count"])]
+        /// Record every converted file of a directory or glob run in FILE. Rerunning with the
+        /// same checkpoint skips the files already in it, e.g. after an interrupted run
+        checkpoint: Option<String>,
+
+        #[arg(long, requires = "checkpoint")]
+        /// Empty the checkpoint and convert every file again
+        no_resume: bool,
+
+        #[arg(long, requires = "output_dir", conflicts_with_all = ["bundle", "count"])]
+        /// Write every output of a directory or glob input straight into the --output-dir
+        /// directory, ignoring the subdirectories the inputs are in
+        flat: bool,
+
+        #[arg(long, value_enum, default_value_t = OnCollision::Error, requires = "flat")]
+        /// What --flat does when two inputs would write the same file name: fail, or prefix
+        /// both with their parent directories (e.g. `champions_ahri_character.py`)
+        on_collision: OnCollision,
+
+        #[arg(long)]
+        /// When ritobin input doesn't parse, convert the entries that do and warn about the
+        /// ones left out, instead of failing the file
+        best_effort: bool,
+
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["bundle", "count"])]
+        /// Write which entries every output of a directory or glob run contains to FILE,
+        /// as JSON for a .json FILE and as TSV (output, hash, name) otherwise
+        index: Option<String>,
+
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["output", "bundle"])]
+        /// Also convert the files listed in FILE, one path per line (`-` reads the list from stdin),
+        /// e.g. the output of `git diff --name-only`. Listed files that don't exist are skipped
+        files_from: Option<String>,
     },
 
     /// Diff two .bin or .ritobin files and show the differences
     Diff {
-        /// Path to the first file to compare
+        /// Path to the first file to compare, or the first candidate with --base
         file1: String,
 
-        /// Path to the second file to compare
-        file2: String,
+        #[arg(required_unless_present_any = ["rev", "base", "self_diff"], conflicts_with = "self_diff")]
+        /// Path to the second file to compare, defaults to FILE1 when using --rev
+        file2: Option<String>,
+
+        /// More candidates to compare against --base, or more files to compare with --multi
+        candidates: Vec<String>,
+
+        #[arg(long, value_name = "REV", conflicts_with = "base")]
+        /// Compare FILE1 as committed in this git revision (e.g. HEAD~1) instead of its working copy
+        rev: Option<String>,
+
+        #[arg(long, value_name = "FILE")]
+        /// Compare this file against every given file, loading it only once
+        base: Option<String>,
 
         #[arg(long, short = 'C', default_value = "3")]
         /// Number of context lines to show around changes
         context: usize,
 
         #[arg(long)]
-        /// Disable colored output
+        /// Disable colored output, same as `--color never`
         no_color: bool,
+
+        #[arg(long)]
+        /// Only compare structure (keys, types and nesting), ignoring scalar values
+        structural: bool,
+
+        #[arg(long, conflicts_with_all = ["base", "ignore_whitespace", "context"])]
+        /// Compare the parsed files and list changed property paths with old and new values,
+        /// ignoring differences in formatting
+        semantic: bool,
+
+        #[arg(long, short = 'w')]
+        /// Ignore whitespace-only changes, including added or removed blank lines
+        ignore_whitespace: bool,
+
+        #[arg(long = "self", conflicts_with_all = ["rev", "base", "structural", "semantic"])]
+        /// Diff the .bin FILE1 as ritobin against the same text after parsing it and writing it
+        /// back to .bin, showing where the round-trip isn't lossless. No diff means it round-trips cleanly.
+        self_diff: bool,
+
+        #[arg(long, value_enum, default_value_t = OutputEncoding::Utf8)]
+        /// How to print the diff: `ascii` escapes non-ASCII characters as \u{...} for consoles
+        /// that can't show UTF-8. Files are still compared as they are
+        output_encoding: OutputEncoding,
+
+        #[arg(long, conflicts_with = "output_encoding")]
+        /// Same as --output-encoding ascii
+        ascii: bool,
+
+        #[arg(long)]
+        /// Print nothing for identical files instead of "Files are identical". With --base,
+        /// only the candidates that differ are listed
+        only_different: bool,
+
+        #[arg(long, conflicts_with_all = ["rev", "base", "self_diff", "semantic"])]
+        /// Compare all given files at once, showing each region that differs from FILE1 as
+        /// columns with the lines of every file side by side
+        multi: bool,
+
+        #[arg(long, conflicts_with_all = ["base", "semantic", "multi"])]
+        /// Compare very large files entry by entry and only diff changed entries line by line,
+        /// using far less memory. Hunks stop at entry boundaries and huge changes are shown as
+        /// removed and added whole, so the output can be coarser
+        large: bool,
+    },
+
+    /// Rewrite .py/.ritobin files in the canonical ritobin layout
+    Fmt {
+        /// Path to the file or directory to format
+        input: String,
+
+        #[arg(long)]
+        /// Don't write anything, exit with an error if any file isn't already formatted
+        check: bool,
+
+        #[arg(long, short)]
+        /// Whether to recursively format all files in the input directory
+        recursive: bool,
+
+        #[arg(long)]
+        /// Format files that contain comments too, removing the comments.
+        /// Without this, such files are reported as errors and left untouched.
+        strip_comments: bool,
+
+        #[arg(long)]
+        /// Also format hidden files and files in hidden directories (e.g. .git)
+        include_hidden: bool,
+    },
+
+    /// Check that .bin and .py/.ritobin files parse, without converting them
+    Validate {
+        #[arg(required_unless_present = "files_from")]
+        /// Path to a file, a directory or a glob pattern to validate
+        input: Option<String>,
+
+        #[arg(long, short)]
+        /// Whether to recursively validate all files in the input directory
+        recursive: bool,
+
+        #[arg(long)]
+        /// Follow symlinks when validating a directory, skipping symlink cycles
+        follow_symlinks: bool,
+
+        #[arg(long)]
+        /// Also validate hidden files and files in hidden directories (e.g. .git) of a directory or glob input
+        include_hidden: bool,
+
+        #[arg(long, value_enum, default_value_t = ValidateFormat::Text)]
+        /// Output format for the results
+        format: ValidateFormat,
+
+        #[arg(long, conflicts_with = "format")]
+        /// Also print failures as GitHub Actions `::error` annotations
+        github_annotations: bool,
+
+        #[arg(long, value_name = "FILE")]
+        /// Ritobin template listing the allowed types and their fields. Files whose entries
+        /// use other types, unknown fields or fields with a different value type are invalid
+        schema: Option<String>,
+
+        #[arg(long, value_name = "FILE")]
+        /// Also validate the files listed in FILE, one path per line (`-` reads the list from stdin),
+        /// e.g. the output of `git diff --name-only`. Listed files that don't exist are skipped
+        files_from: Option<String>,
+
+        #[arg(long, value_name = "N")]
+        /// Stop after the first N matching files, e.g. to spot-check a huge dump
+        limit: Option<usize>,
+    },
+
+    /// Look for things in a .bin or ritobin file that parse fine but tend to misbehave in game:
+    /// empty lists and maps, null or dangling links, embeds without a type or fields,
+    /// suspiciously large lists and, with --schema, values that don't match the schema
+    Analyze {
+        /// Path to the .bin or ritobin file to analyze
+        input: String,
+
+        #[arg(long, value_name = "FILE")]
+        /// Ritobin template listing the allowed types and their fields, see `validate --schema`.
+        /// Mismatches are reported as errors
+        schema: Option<String>,
+
+        #[arg(long, value_name = "N", default_value_t = analyze::DEFAULT_MAX_ITEMS)]
+        /// Report lists with more than N items
+        max_items: usize,
+    },
+
+    /// Time in-memory conversions of a file or directory, without writing anything, to
+    /// track conversion speed across versions: mean, median and p95 parse and serialize
+    /// times per file and files per second
+    Bench {
+        /// Path to a file, a directory or a glob pattern to benchmark
+        input: String,
+
+        #[arg(long, short)]
+        /// Whether to recursively benchmark all files in the input directory
+        recursive: bool,
+
+        #[arg(long, short = 'n', value_name = "N", default_value_t = bench::DEFAULT_ITERATIONS,
+              value_parser = clap::value_parser!(u32).range(1..))]
+        /// Timed passes over the inputs, after one untimed warmup pass
+        iterations: u32,
+    },
+
+    /// Replace a single scalar value in a .bin file
+    Set {
+        /// Path to the .bin file to modify
+        input: String,
+
+        /// Property path, e.g. 'Characters/Ahri/Skins/Skin0.skinScale' or '"Items/1001".mData[0]'
+        path: String,
+
+        /// New value in ritobin syntax, must match the type of the current value
+        #[arg(allow_hyphen_values = true)]
+        value: String,
+
+        #[arg(short, long)]
+        /// Path to write the modified file to, defaults to overwriting the input
+        output: Option<String>,
+    },
+
+    /// Rename fields and types in .bin files using a TSV mapping file
+    Rename {
+        /// Path to a .bin file or a directory
+        input: String,
+
+        #[arg(long, short)]
+        /// TSV file with one 'old_name<TAB>new_name' pair per line
+        map: String,
+
+        #[arg(long, short)]
+        /// Whether to recursively rename in all files in the input directory
+        recursive: bool,
+
+        #[arg(long)]
+        /// Also rename in hidden files and files in hidden directories (e.g. .git)
+        include_hidden: bool,
     },
 
     /// Manage application configuration
@@ -123,7 +628,70 @@ pub enum Commands {
 
     /// Download hashtable files from CommunityDragon
     #[command(alias = "dl")]
-    DownloadHashes,
+    DownloadHashes {
+        #[arg(long, value_name = "SECS")]
+        /// Connect/read timeout in seconds. Overrides `download_timeout_secs` from the config.
+        timeout: Option<u64>,
+
+        #[arg(long, default_value_t = download_hashes::DEFAULT_RETRIES)]
+        /// Number of times to retry a file after a transient failure (5xx, timeout)
+        retries: u32,
+
+        #[arg(long, value_name = "URL")]
+        /// Proxy to download through. Defaults to the HTTPS_PROXY/HTTP_PROXY environment variables,
+        /// respecting NO_PROXY.
+        proxy: Option<String>,
+
+        #[arg(long)]
+        /// Download every hashtable again, even those that exist and have the size the server
+        /// reports
+        force: bool,
+    },
+
+    /// Build a binary cache of the hashtables for faster loading
+    BuildHashCache,
+
+    /// Check the hashtables in the hashtable directory for malformed or conflicting lines
+    CheckHashtable,
+
+    /// Check the config file, the hashtables, name resolution and terminal support, printing
+    /// a checklist. The first thing to run when something isn't working.
+    Doctor,
+
+    /// Search the hashtables for names matching a partial or misspelled query
+    Resolve {
+        /// Partial name to look up (e.g. 'movespeed')
+        query: String,
+
+        #[arg(long, short = 'n', default_value = "20")]
+        /// Maximum number of matches to show
+        limit: usize,
+    },
+
+    /// Keep a directory of .bin files and a mirrored directory of .py files in sync, converting
+    /// whichever file of each pair was modified last
+    Sync {
+        /// Directory with the .bin files
+        bin_dir: String,
+
+        /// Directory with the .py files, mirroring the layout of BIN_DIR
+        text_dir: String,
+
+        #[arg(long, short = 'n')]
+        /// Only report what would be converted
+        dry_run: bool,
+    },
+
+    /// List the supported file formats, conversions and the extensions each command accepts
+    Formats,
+
+    /// Print a shell completion script to stdout
+    #[command(hide = true)]
+    Completions {
+        /// Shell to generate completions for
+        #[arg(value_enum)]
+        shell: Shell,
+    },
 }
 
 fn parse_args() -> Args {
@@ -134,47 +702,396 @@ fn parse_args() -> Args {
         .literal(AnsiColor::Cyan.on_default())
         .placeholder(AnsiColor::Blue.on_default());
 
+    // Help and usage errors are printed while parsing, so pick up --color from the raw args
     let matches = Args::command()
         .styles(styles)
-        .color(ColorChoice::Auto)
+        .color(raw_color_mode().into())
         .get_matches();
 
     Args::from_arg_matches(&matches).expect("failed to parse arguments")
 }
 
-fn main() -> Result<()> {
-    let _ = crate::commands::ensure_config_exists();
+/// Finds `--color <MODE>` or `--color=<MODE>` before clap has parsed anything
+fn raw_color_mode() -> ColorMode {
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let value = match arg.strip_prefix("--color") {
+            Some("") => args.next(),
+            Some(rest) => rest.strip_prefix('=').map(str::to_string),
+            None => continue,
+        };
+        if let Some(mode) = value.and_then(|v| ColorMode::from_str(&v, true).ok()) {
+            return mode;
+        }
+    }
+    ColorMode::Auto
+}
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(report) => {
+            eprintln!("Error: {:?}", report);
+            exit_code(&report)
+        }
+    }
+}
 
+fn run() -> Result<()> {
     let args = parse_args();
 
-    initialize_tracing(args.verbosity, false)?;
+    let color = match args.command {
+        Commands::Diff { no_color: true, .. } => ColorMode::Never,
+        _ => args.color,
+    };
+    let use_color = color.enabled();
+    colored::control::set_override(use_color);
+
+    // Only downloads report progress through spans
+    let show_progress = matches!(args.command, Commands::DownloadHashes { .. });
+    // Converted output or paths on stdout must not be interleaved with log lines
+    let logs_to_stderr = match &args.command {
+        Commands::Convert {
+            output,
+            print_output_path,
+            ..
+        } => *print_output_path || output.as_deref() == Some(convert::STDOUT_OUTPUT),
+        _ => false,
+    };
+    initialize_tracing(
+        args.verbosity,
+        args.log_format,
+        show_progress,
+        logs_to_stderr,
+        use_color,
+    )?;
+
+    utils::config::set_strict_config(args.strict_config);
+    utils::config::set_config_overrides(
+        args.config_overrides
+            .iter()
+            .map(|(key, value)| (key.clone(), config_cmd::parse_toml_value(key, value)))
+            .collect(),
+    );
+    hashtable::set_strict_hashtables(args.strict_hashtable);
+    utils::set_quiet(args.quiet);
+    utils::set_assume_yes(args.yes);
+    utils::bin_io::set_trace_hashes(args.trace_hashes);
+    if args.trace_hashes && !matches!(args.verbosity, VerbosityLevel::Trace) {
+        tracing::warn!("--trace-hashes logs at trace level, add -L trace to see the lookups");
+    }
+    // Completion scripts are usually generated from shell startup files, don't create a config there
+    let config = match args.command {
+        Commands::Completions { .. } => None,
+        _ => crate::commands::ensure_config_exists().ok(),
+    };
+    // Hyperlink escapes would end up inside the JSON strings, so only an explicit flag enables them
+    let config_link_style = match args.log_format {
+        LogFormat::Text => config.as_ref().and_then(|config| config.link_style),
+        LogFormat::Json => Some(LinkStyle::Plain),
+    };
+    utils::set_link_style(args.link_style.or(config_link_style).unwrap_or_default());
+    // A zero-sized buffer would make downloads stop after the first read
+    let config_buffer_size = config
+        .as_ref()
+        .and_then(|config| config.io_buffer_size)
+        .filter(|&size| size > 0);
+    if let Some(size) = args.buffer_size.or(config_buffer_size) {
+        utils::set_io_buffer_size(size);
+    }
+    if let Some(days) = config
+        .as_ref()
+        .and_then(|config| config.hashtable_max_age_days)
+    {
+        // CI runs are often offline, so the config alone doesn't download there
+        let auto_update = if args.auto_update || args.no_auto_update {
+            args.auto_update
+        } else {
+            config
+                .as_ref()
+                .and_then(|config| config.hashtable_auto_update)
+                == Some(true)
+                && env::var_os("CI").is_none()
+        };
+        hashtable::set_update_policy(UpdatePolicy {
+            max_age: hashtable::max_age(days),
+            update: auto_update.then_some(|| {
+                download_hashes::download_hashes(
+                    None,
+                    download_hashes::DEFAULT_RETRIES,
+                    None,
+                    false,
+                )
+            }),
+        });
+    }
+    if let Some(extra) = config
+        .as_ref()
+        .and_then(|config| config.extra_hashtables.as_ref())
+    {
+        hashtable::set_extra_hashtables(extra.iter().map(|extra| extra.file.clone()).collect());
+    }
+    // Validated when the config was parsed
+    if let Some(Ok(command)) = config
+        .as_ref()
+        .and_then(|config| config.hash_resolver_cmd.as_deref())
+        .map(utils::config::resolver_command)
+    {
+        utils::hash_resolver::set_hash_resolver(command);
+    }
 
     match args.command {
         Commands::Convert {
             input,
             output,
             recursive,
-        } => convert::convert(input, output, recursive),
+            sha256,
+            dedup,
+            name_template,
+            strict,
+            mmap,
+            only,
+            ext,
+            multi,
+            bundle,
+            separator,
+            relative_paths,
+            require_names,
+            known_names_only,
+            since,
+            max_depth,
+            limit,
+            resolve_links,
+            header,
+            follow_symlinks,
+            include_hidden,
+            exec,
+            timings,
+            timings_out,
+            preserve_bom,
+            count,
+            print_output_path,
+            schema,
+            entries_only,
+            entry_prefix,
+            keep_empty,
+            line_endings,
+            compress,
+            checkpoint,
+            no_resume,
+            flat,
+            on_collision,
+            best_effort,
+            index,
+            files_from,
+            output_dir,
+        } => convert::convert(
+            input,
+            output,
+            recursive,
+            ConvertOptions {
+                sha256,
+                dedup,
+                name_template,
+                strict,
+                mmap,
+                only,
+                text_extension: ext,
+                multi,
+                bundle,
+                separator,
+                relative_paths,
+                require_names,
+                known_names_only,
+                since,
+                max_depth,
+                limit,
+                resolve_links: resolve_links.map(Into::into),
+                header,
+                follow_symlinks,
+                include_hidden,
+                exec,
+                timings,
+                timings_out: timings_out.map(Into::into),
+                preserve_bom,
+                count,
+                print_output_path,
+                schema: load_schema(schema)?,
+                entries_depth: entries_only,
+                entry_prefixes: entry_prefix,
+                keep_empty,
+                line_ending: line_endings,
+                compress,
+                checkpoint: checkpoint.map(Into::into),
+                no_resume,
+                flat,
+                on_collision,
+                best_effort,
+                index: index.map(Into::into),
+                files_from,
+                output_dir: output_dir.map(Into::into),
+                confirm_overwrite: true,
+                ..Default::default()
+            },
+        ),
         Commands::Diff {
             file1,
             file2,
+            candidates,
+            rev,
+            base,
             context,
-            no_color,
-        } => diff::diff(file1, file2, context, no_color),
+            no_color: _,
+            structural,
+            semantic,
+            ignore_whitespace,
+            self_diff,
+            output_encoding,
+            ascii,
+            only_different,
+            multi,
+            large,
+        } => {
+            let options = DiffOptions {
+                context_lines: context,
+                structural,
+                ignore_whitespace,
+                semantic,
+                output_encoding: if ascii {
+                    OutputEncoding::Ascii
+                } else {
+                    output_encoding
+                },
+                only_different,
+                large,
+            };
+            match base {
+                _ if self_diff => diff::diff_self(file1, options),
+                _ if multi => {
+                    let files = [file1].into_iter().chain(file2).chain(candidates);
+                    diff::diff_multi(files.collect(), options)
+                }
+                Some(base) => {
+                    let candidates = [file1].into_iter().chain(file2).chain(candidates);
+                    diff::diff_against_base(base, candidates.collect(), options)
+                }
+                None if !candidates.is_empty() => Err(Failure::Usage(
+                    "More than two files need --base or --multi".to_string(),
+                )
+                .into()),
+                None => diff::diff(file1, file2, rev, options),
+            }
+        }
+        Commands::Fmt {
+            input,
+            check,
+            recursive,
+            strip_comments,
+            include_hidden,
+        } => commands::fmt::fmt(input, check, recursive, strip_comments, include_hidden),
+        Commands::Validate {
+            input,
+            recursive,
+            follow_symlinks,
+            include_hidden,
+            format,
+            github_annotations,
+            schema,
+            files_from,
+            limit,
+        } => validate::validate(
+            input,
+            ValidateOptions {
+                recursive,
+                follow_symlinks,
+                include_hidden,
+                format,
+                github_annotations,
+                schema: load_schema(schema)?,
+                files_from,
+                limit,
+            },
+        ),
+        Commands::Analyze {
+            input,
+            schema,
+            max_items,
+        } => analyze::analyze(input, load_schema(schema)?.as_ref(), max_items),
+        Commands::Bench {
+            input,
+            recursive,
+            iterations,
+        } => bench::bench(input, recursive, iterations),
+        Commands::Set {
+            input,
+            path,
+            value,
+            output,
+        } => set::set(input, path, value, output),
+        Commands::Rename {
+            input,
+            map,
+            recursive,
+            include_hidden,
+        } => rename::rename(input, map, recursive, include_hidden),
         Commands::Config { action } => match action {
             ConfigAction::Show => config_cmd::show_config(),
             ConfigAction::Set { key, value } => config_cmd::set_config_value(&key, &value),
             ConfigAction::Reset => config_cmd::reset_config(),
+            ConfigAction::Init => config_cmd::init_config(),
         },
-        Commands::DownloadHashes => download_hashes::download_hashes(),
+        Commands::DownloadHashes {
+            timeout,
+            retries,
+            proxy,
+            force,
+        } => download_hashes::download_hashes(timeout, retries, proxy, force),
+        Commands::BuildHashCache => build_hash_cache::build_hash_cache(),
+        Commands::CheckHashtable => check_hashtable::check_hashtable(),
+        Commands::Doctor => doctor::doctor(),
+        Commands::Resolve { query, limit } => resolve::resolve(query, limit),
+        Commands::Sync {
+            bin_dir,
+            text_dir,
+            dry_run,
+        } => sync::sync(bin_dir, text_dir, dry_run),
+        Commands::Formats => formats::formats(),
+        Commands::Completions { shell } => completions::completions(shell),
     }
 }
 
-fn initialize_tracing(verbosity: VerbosityLevel, show_progress: bool) -> Result<()> {
+/// Load the `--schema` template of Convert and Validate, if one was given
+fn load_schema(path: Option<String>) -> Result<Option<Schema>> {
+    path.map(|path| Schema::load(path.as_ref())).transpose()
+}
+
+fn initialize_tracing(
+    verbosity: VerbosityLevel,
+    log_format: LogFormat,
+    show_progress: bool,
+    logs_to_stderr: bool,
+    ansi: bool,
+) -> Result<()> {
     let indicatif_layer = IndicatifLayer::new();
+    let stdout_writer = || {
+        if logs_to_stderr {
+            BoxMakeWriter::new(indicatif_layer.get_stderr_writer())
+        } else {
+            BoxMakeWriter::new(indicatif_layer.get_stdout_writer())
+        }
+    };
+
+    // JSON: every event on stdout, progress bars stay on stderr
+    let json_layer = (log_format == LogFormat::Json).then(|| {
+        fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_target(false)
+            .with_writer(stdout_writer())
+    });
 
     let common_format = fmt::format()
-        .with_ansi(true)
+        .with_ansi(ansi)
         .with_level(true)
         .with_source_location(false)
         .with_line_number(false)
@@ -183,7 +1100,8 @@ fn initialize_tracing(verbosity: VerbosityLevel, show_progress: bool) -> Result<
 
     // stdout: INFO/DEBUG/TRACE (when verbosity allows)
     let stdout_layer = fmt::layer()
-        .with_writer(indicatif_layer.get_stdout_writer())
+        .with_writer(stdout_writer())
+        .fmt_fields(unescaped_fields())
         .event_format(common_format.clone())
         .with_filter(filter::filter_fn(move |metadata| {
             let level = *metadata.level();
@@ -210,6 +1128,7 @@ fn initialize_tracing(verbosity: VerbosityLevel, show_progress: bool) -> Result<
     // stderr: WARN/ERROR (for Warning and above) or all high-priority messages
     let stderr_layer = fmt::layer()
         .with_writer(indicatif_layer.get_stderr_writer())
+        .fmt_fields(unescaped_fields())
         .event_format(common_format)
         .with_filter(filter::filter_fn(move |metadata| {
             let level = *metadata.level();
@@ -222,9 +1141,11 @@ fn initialize_tracing(verbosity: VerbosityLevel, show_progress: bool) -> Result<
             }
         }));
 
+    let text_layers = (log_format == LogFormat::Text).then(|| stdout_layer.and_then(stderr_layer));
+
     let registry = tracing_subscriber::registry()
-        .with(stdout_layer)
-        .with(stderr_layer)
+        .with(text_layers)
+        .with(json_layer)
         .with(verbosity.to_level_filter());
 
     if show_progress {
@@ -235,6 +1156,17 @@ fn initialize_tracing(verbosity: VerbosityLevel, show_progress: bool) -> Result<
     Ok(())
 }
 
+/// Field formatter that writes log messages as-is.
+/// The default one escapes control characters in messages, which turns the OSC 8 links
+/// from `hyperlink_path` into literal `\x1b]8;;...` text.
+fn unescaped_fields() -> impl for<'writer> FormatFields<'writer> + Clone + 'static {
+    fmt::format::debug_fn(|writer, field, value| match field.name() {
+        "message" => write!(writer, "{:?}", value),
+        name => write!(writer, "{}={:?}", name, value),
+    })
+    .delimited(" ")
+}
+
 fn cli_styles() -> Styles {
     Styles::styled()
         .header(AnsiColor::Yellow.on_default().bold())
@@ -0,0 +1,636 @@
+//! Conversion between bin trees, binary bytes and ritobin text.
+//!
+//! The core functions work on readers, byte slices and strings so they can be used for
+//! in-memory data (git blobs, stdin). Path-based wrappers add file handling on top.
+
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read, Seek, SeekFrom};
+use std::ops::RangeInclusive;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use camino::Utf8Path;
+use ltk_meta::BinTree;
+use ltk_ritobin::{HashMapProvider, HashProvider, HexHashProvider, WriterConfig};
+use miette::{Diagnostic, IntoDiagnostic, Result, WrapErr};
+
+use crate::utils::hash_resolver;
+use crate::utils::permission::IntoAccessDiagnostic;
+use crate::utils::ritobin_text::split_entries;
+use crate::utils::{DEFAULT_IO_BUFFER_SIZE, format_hash, io_buffer_size};
+
+/// Byte order mark some Windows editors put at the start of UTF-8 files
+pub const UTF8_BOM: &str = "\u{feff}";
+
+/// Bin format versions the parser can read
+pub const SUPPORTED_BIN_VERSIONS: RangeInclusive<u32> = 1..=3;
+
+/// Longest header [`sniff_bin_header`] looks at: `PTCH`, override version, override
+/// count, `PROP` and version
+const MAX_BIN_HEADER_LEN: usize = 20;
+
+/// What the first bytes of a .bin file say about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BinHeader {
+    /// Whether the file starts with a `PTCH` override section
+    pub is_override: bool,
+    pub version: u32,
+}
+
+/// Check the magic and version at the start of a .bin file without parsing the rest.
+///
+/// Files that aren't bins, bins written big-endian and versions the parser can't read
+/// get an error saying so, instead of the generic one a full parse would give.
+pub fn sniff_bin_header(bytes: &[u8]) -> Result<BinHeader> {
+    let word = |index: usize| {
+        bytes
+            .get(index * 4..index * 4 + 4)
+            .map(|word| u32::from_le_bytes(word.try_into().unwrap()))
+            .ok_or_else(|| {
+                miette::miette!("file is too short to be a .bin ({} bytes)", bytes.len())
+            })
+    };
+
+    let magic = word(0)?;
+    let (is_override, version_index) = match magic {
+        BinTree::PROP => (false, 1),
+        BinTree::PTCH => {
+            let override_version = word(1)?;
+            if override_version != 1 {
+                return Err(miette::miette!(
+                    "unsupported override bin version {} (supported: 1)",
+                    override_version
+                ));
+            }
+            if word(3)? != BinTree::PROP {
+                return Err(miette::miette!(
+                    "override bin has no PROP section after its PTCH header"
+                ));
+            }
+            (true, 4)
+        }
+        _ if [BinTree::PROP, BinTree::PTCH].contains(&magic.swap_bytes()) => {
+            return Err(miette::miette!(
+                "byte-swapped .bin (starts with \"{}\"), only little-endian bins are supported",
+                bytes[..4].escape_ascii()
+            ));
+        }
+        _ => {
+            return Err(miette::miette!(
+                "not a .bin file (starts with \"{}\", expected \"PROP\" or \"PTCH\")",
+                bytes[..4].escape_ascii()
+            ));
+        }
+    };
+
+    let version = word(version_index)?;
+    if !SUPPORTED_BIN_VERSIONS.contains(&version) {
+        let (first, last) = SUPPORTED_BIN_VERSIONS.into_inner();
+        if SUPPORTED_BIN_VERSIONS.contains(&version.swap_bytes()) {
+            return Err(miette::miette!(
+                "bin version {} is byte-swapped (reads as {}), only little-endian bins are supported",
+                version.swap_bytes(),
+                version
+            ));
+        }
+        return Err(miette::miette!(
+            "unsupported bin version {} (supported: {}-{})",
+            version,
+            first,
+            last
+        ));
+    }
+
+    Ok(BinHeader {
+        is_override,
+        version,
+    })
+}
+
+/// Read and check the header at the start of a reader, see [`sniff_bin_header`]
+pub fn read_bin_header(reader: &mut impl Read) -> Result<BinHeader> {
+    let mut header = Vec::with_capacity(MAX_BIN_HEADER_LEN);
+    reader
+        .by_ref()
+        .take(MAX_BIN_HEADER_LEN as u64)
+        .read_to_end(&mut header)
+        .into_diagnostic()
+        .wrap_err("Failed to read .bin header")?;
+    sniff_bin_header(&header)
+}
+
+/// Read and check the header of a .bin file on disk, see [`sniff_bin_header`]
+pub fn read_bin_header_file(path: &Utf8Path) -> Result<BinHeader> {
+    let mut file = File::open(path)
+        .read_access()
+        .wrap_err_with(|| format!("Failed to open file: {}", path))?;
+    read_bin_header(&mut file).wrap_err_with(|| format!("Failed to read {}", path))
+}
+
+/// Parse a bin tree from a seekable reader, checking its header first so unsupported
+/// files fail with a clear message
+pub fn read_bin(reader: &mut (impl Read + Seek)) -> Result<BinTree> {
+    let start = reader
+        .stream_position()
+        .into_diagnostic()
+        .wrap_err("Failed to read .bin file")?;
+    read_bin_header(reader).wrap_err("Failed to parse .bin file")?;
+    reader
+        .seek(SeekFrom::Start(start))
+        .into_diagnostic()
+        .wrap_err("Failed to read .bin file")?;
+
+    BinTree::from_reader(reader)
+        .into_diagnostic()
+        .wrap_err("Failed to parse .bin file")
+}
+
+/// Parse a bin tree from the binary contents of a .bin file
+pub fn read_bin_bytes(bytes: &[u8]) -> Result<BinTree> {
+    read_bin(&mut Cursor::new(bytes))
+}
+
+/// Parse a bin tree from a .bin file on disk
+pub fn read_bin_file(path: &Utf8Path) -> Result<BinTree> {
+    let file = File::open(path)
+        .read_access()
+        .wrap_err_with(|| format!("Failed to open file: {}", path))?;
+
+    let mut reader = BufReader::with_capacity(io_buffer_size(DEFAULT_IO_BUFFER_SIZE), file);
+    read_bin(&mut reader).wrap_err_with(|| format!("Failed to read {}", path))
+}
+
+/// Hash provider that resolves names with `primary` and asks `fallback` for its misses.
+/// Without a primary provider every lookup goes to the fallback.
+pub struct FallbackProvider<P, F> {
+    pub primary: Option<P>,
+    pub fallback: F,
+}
+
+impl<P: HashProvider, F: HashProvider> HashProvider for FallbackProvider<P, F> {
+    fn lookup_entry(&self, hash: u32) -> Option<&str> {
+        self.primary
+            .as_ref()
+            .and_then(|primary| primary.lookup_entry(hash))
+            .or_else(|| self.fallback.lookup_entry(hash))
+    }
+
+    fn lookup_field(&self, hash: u32) -> Option<&str> {
+        self.primary
+            .as_ref()
+            .and_then(|primary| primary.lookup_field(hash))
+            .or_else(|| self.fallback.lookup_field(hash))
+    }
+
+    fn lookup_hash(&self, hash: u32) -> Option<&str> {
+        self.primary
+            .as_ref()
+            .and_then(|primary| primary.lookup_hash(hash))
+            .or_else(|| self.fallback.lookup_hash(hash))
+    }
+
+    fn lookup_type(&self, hash: u32) -> Option<&str> {
+        self.primary
+            .as_ref()
+            .and_then(|primary| primary.lookup_type(hash))
+            .or_else(|| self.fallback.lookup_type(hash))
+    }
+}
+
+static TRACE_HASHES: AtomicBool = AtomicBool::new(false);
+
+/// Log every hash lookup of the ritobin writer at trace level for the rest of the run,
+/// see [`TracingProvider`]
+pub fn set_trace_hashes(enabled: bool) {
+    TRACE_HASHES.store(enabled, Ordering::Relaxed);
+}
+
+/// Forwards lookups to a [`FallbackProvider`] and, with [`set_trace_hashes`], logs at trace
+/// level what every hash resolved to and which of the two providers named it, or that it
+/// stays hex
+struct TracingProvider<'a, P, F> {
+    names: &'a FallbackProvider<P, F>,
+    /// What the primary and fallback providers are called in the log
+    sources: (&'static str, &'static str),
+}
+
+impl<P: HashProvider, F: HashProvider> TracingProvider<'_, P, F> {
+    fn trace<'s>(
+        &'s self,
+        category: &str,
+        hash: u32,
+        lookup: impl Fn(&'s dyn HashProvider) -> Option<&'s str>,
+    ) -> Option<&'s str> {
+        if !TRACE_HASHES.load(Ordering::Relaxed) {
+            return lookup(self.names);
+        }
+        let (name, source) = match self.names.primary.as_ref().and_then(|p| lookup(p)) {
+            Some(name) => (Some(name), self.sources.0),
+            None => (lookup(&self.names.fallback), self.sources.1),
+        };
+        match name {
+            Some(name) => {
+                tracing::trace!(
+                    "{} {} -> {} ({})",
+                    category,
+                    format_hash(hash),
+                    name,
+                    source
+                )
+            }
+            None => tracing::trace!(
+                "{} {} has no name, written as hex",
+                category,
+                format_hash(hash)
+            ),
+        }
+        name
+    }
+}
+
+impl<P: HashProvider, F: HashProvider> HashProvider for TracingProvider<'_, P, F> {
+    fn lookup_entry(&self, hash: u32) -> Option<&str> {
+        self.trace("entry", hash, |names| names.lookup_entry(hash))
+    }
+
+    fn lookup_field(&self, hash: u32) -> Option<&str> {
+        self.trace("field", hash, |names| names.lookup_field(hash))
+    }
+
+    fn lookup_hash(&self, hash: u32) -> Option<&str> {
+        self.trace("hash", hash, |names| names.lookup_hash(hash))
+    }
+
+    fn lookup_type(&self, hash: u32) -> Option<&str> {
+        self.trace("type", hash, |names| names.lookup_type(hash))
+    }
+}
+
+/// Write a bin tree as ritobin text.
+/// Names are resolved with `hashes` when available, then with the `hash_resolver_cmd` (see
+/// [`resolver_names`]), and any hash without a name is written as hex.
+pub fn tree_to_text(tree: &BinTree, hashes: Option<&HashMapProvider>) -> Result<String> {
+    tree_to_text_unresolved(tree, hashes).map(|(text, _)| text)
+}
+
+/// Like [`tree_to_text`], also returning how many hashes had no name and were written as hex
+pub fn tree_to_text_unresolved(
+    tree: &BinTree,
+    hashes: Option<&HashMapProvider>,
+) -> Result<(String, usize)> {
+    let names = FallbackProvider {
+        primary: hashes,
+        fallback: resolver_names(
+            tree,
+            &FallbackProvider {
+                primary: hashes,
+                fallback: HexHashProvider,
+            },
+        )?,
+    };
+    let tracing = TracingProvider {
+        names: &names,
+        sources: ("hashtables", "hash_resolver_cmd"),
+    };
+    let provider = RecordingProvider::new(&tracing);
+
+    let text = ltk_ritobin::write_with_config_and_hashes(tree, WriterConfig::default(), &provider)
+        .into_diagnostic()
+        .wrap_err("Failed to convert to ritobin format")?;
+    Ok((text, provider.missing.into_inner().len()))
+}
+
+/// The hashes of `tree` that neither `hashes` nor the `hash_resolver_cmd` can name, as
+/// (category, hash) pairs
+pub fn unresolved_hashes(
+    tree: &BinTree,
+    hashes: &HashMapProvider,
+) -> Result<BTreeSet<(&'static str, u32)>> {
+    let names = FallbackProvider {
+        primary: Some(hashes),
+        fallback: resolver_names(tree, hashes)?,
+    };
+    let provider = RecordingProvider::new(&names);
+    ltk_ritobin::write_with_config_and_hashes(tree, WriterConfig::default(), &provider)
+        .into_diagnostic()
+        .wrap_err("Failed to convert to ritobin format")?;
+    Ok(provider.missing.into_inner())
+}
+
+/// Unresolved hashes listed in a [`tree_to_named_text`] error before the rest are summarized
+const MAX_MISSING_NAMES_SHOWN: usize = 20;
+
+/// Names the `hash_resolver_cmd` gives the hashes of `tree` that `names` can't resolve,
+/// empty without a resolver. Finding those hashes takes a throwaway write of the tree.
+pub fn resolver_names(tree: &BinTree, names: &impl HashProvider) -> Result<HashMapProvider> {
+    let mut resolved = HashMapProvider::default();
+    if !hash_resolver::is_enabled() {
+        return Ok(resolved);
+    }
+
+    let recording = RecordingProvider::new(names);
+    ltk_ritobin::write_with_config_and_hashes(tree, WriterConfig::default(), &recording)
+        .into_diagnostic()
+        .wrap_err("Failed to convert to ritobin format")?;
+    let missing: BTreeSet<u32> = recording
+        .missing
+        .into_inner()
+        .into_iter()
+        .map(|(_, hash)| hash)
+        .collect();
+    if missing.is_empty() {
+        return Ok(resolved);
+    }
+
+    // Like a combined hashtable, a resolved name applies to every category
+    for (hash, name) in hash_resolver::resolve(&missing) {
+        resolved.entries.insert(hash, name.clone());
+        resolved.fields.insert(hash, name.clone());
+        resolved.hashes.insert(hash, name.clone());
+        resolved.types.insert(hash, name);
+    }
+    Ok(resolved)
+}
+
+/// Hash provider that records every hash its inner provider couldn't resolve
+pub(crate) struct RecordingProvider<'a, H> {
+    inner: &'a H,
+    /// (category, hash) pairs, sorted so errors are stable
+    missing: RefCell<BTreeSet<(&'static str, u32)>>,
+}
+
+impl<'a, H> RecordingProvider<'a, H> {
+    pub(crate) fn new(inner: &'a H) -> Self {
+        Self {
+            inner,
+            missing: RefCell::default(),
+        }
+    }
+
+    /// How many hashes couldn't be resolved so far
+    pub(crate) fn missing_count(&self) -> usize {
+        self.missing.borrow().len()
+    }
+
+    fn record<'s>(
+        &self,
+        category: &'static str,
+        hash: u32,
+        name: Option<&'s str>,
+    ) -> Option<&'s str> {
+        if name.is_none() {
+            self.missing.borrow_mut().insert((category, hash));
+        }
+        name
+    }
+}
+
+impl<H: HashProvider> HashProvider for RecordingProvider<'_, H> {
+    fn lookup_entry(&self, hash: u32) -> Option<&str> {
+        self.record("entry", hash, self.inner.lookup_entry(hash))
+    }
+
+    fn lookup_field(&self, hash: u32) -> Option<&str> {
+        self.record("field", hash, self.inner.lookup_field(hash))
+    }
+
+    fn lookup_hash(&self, hash: u32) -> Option<&str> {
+        self.record("hash", hash, self.inner.lookup_hash(hash))
+    }
+
+    fn lookup_type(&self, hash: u32) -> Option<&str> {
+        self.record("type", hash, self.inner.lookup_type(hash))
+    }
+}
+
+/// Write a bin tree as ritobin text, failing if any hash can't be resolved to a name, by
+/// the hashtables or the `hash_resolver_cmd`. The error lists the unresolved hashes, so incomplete hashtables are easy to spot.
+pub fn tree_to_named_text(tree: &BinTree, hashes: Option<&HashMapProvider>) -> Result<String> {
+    let hashes = hashes.ok_or_else(|| {
+        miette::miette!("No hashtables loaded, names are required but none can be resolved")
+    })?;
+    let hashes = FallbackProvider {
+        primary: Some(hashes),
+        fallback: resolver_names(tree, hashes)?,
+    };
+    let tracing = TracingProvider {
+        names: &hashes,
+        sources: ("hashtables", "hash_resolver_cmd"),
+    };
+    let provider = RecordingProvider::new(&tracing);
+
+    let text = ltk_ritobin::write_with_config_and_hashes(tree, WriterConfig::default(), &provider)
+        .into_diagnostic()
+        .wrap_err("Failed to convert to ritobin format")?;
+
+    let missing = provider.missing.into_inner();
+    if missing.is_empty() {
+        return Ok(text);
+    }
+
+    let mut listed: Vec<String> = missing
+        .iter()
+        .take(MAX_MISSING_NAMES_SHOWN)
+        .map(|(category, hash)| format!("{} {}", category, format_hash(*hash)))
+        .collect();
+    if missing.len() > MAX_MISSING_NAMES_SHOWN {
+        listed.push(format!(
+            "... and {} more",
+            missing.len() - MAX_MISSING_NAMES_SHOWN
+        ));
+    }
+
+    Err(miette::miette!(
+        "{} hash(es) have no name in the hashtables: {}",
+        missing.len(),
+        listed.join(", ")
+    ))
+}
+
+/// Parse ritobin text into a bin tree, ignoring a leading byte order mark.
+/// Errors keep the parser's source span, so reports show the lines around the problem.
+pub fn parse_text(text: &str) -> Result<BinTree> {
+    ltk_ritobin::parse_to_bin_tree(strip_bom(text))
+        .map_err(|e| miette::Report::new(e).wrap_err("Failed to parse ritobin file"))
+}
+
+/// A top-level entry [`parse_text_best_effort`] left out because it didn't parse
+#[derive(Debug, Clone)]
+pub struct SkippedEntry {
+    /// 1-based lines the entry spans
+    pub lines: RangeInclusive<usize>,
+    /// 1-based line the parser stopped at
+    pub error_line: usize,
+    pub error: String,
+}
+
+/// Parse ritobin text, leaving out the entries that don't parse instead of failing.
+///
+/// Each entry of the `entries` map is tried on its own, found by its braces. Everything
+/// outside the entries map still has to parse, otherwise the error of parsing the whole
+/// text is returned. Text that parses as a whole skips nothing.
+pub fn parse_text_best_effort(text: &str) -> Result<(BinTree, Vec<SkippedEntry>)> {
+    let text = strip_bom(text);
+    let error = match parse_text(text) {
+        Ok(tree) => return Ok((tree, Vec::new())),
+        Err(e) => e,
+    };
+    let Some(sections) = split_entries(text) else {
+        return Err(error);
+    };
+
+    let head = &text[sections.head];
+    let mut kept = String::new();
+    let mut skipped = Vec::new();
+    for range in sections.entries {
+        let entry = &text[range.clone()];
+        let candidate = format!("{}\n{}\n}}\n", head, entry);
+        match ltk_ritobin::parse_to_bin_tree(&candidate) {
+            Ok(_) => {
+                kept.push_str(entry);
+                kept.push('\n');
+            }
+            Err(e) => {
+                // Map the error back from the candidate text to the entry in `text`
+                let error_offset = e
+                    .labels()
+                    .and_then(|mut labels| labels.next())
+                    .map(|label| label.offset().saturating_sub(head.len() + 1))
+                    .unwrap_or_default()
+                    .min(entry.len());
+                skipped.push(SkippedEntry {
+                    lines: line_at(text, range.start)..=line_at(text, range.end - 1),
+                    error_line: line_at(text, range.start + error_offset),
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    let recovered = format!("{}\n{}}}{}", head, kept, &text[sections.tail]);
+    match ltk_ritobin::parse_to_bin_tree(&recovered) {
+        Ok(tree) => Ok((tree, skipped)),
+        Err(_) => Err(error),
+    }
+}
+
+/// 1-based line of a byte offset
+fn line_at(text: &str, offset: usize) -> usize {
+    text[..offset.min(text.len())].matches('\n').count() + 1
+}
+
+/// Serialize a bin tree to the binary .bin format
+pub fn tree_to_bytes(tree: &BinTree) -> Result<Vec<u8>> {
+    // BinTree::to_writer requires Seek, so write to an in-memory cursor
+    let mut cursor = Cursor::new(Vec::new());
+    tree.to_writer(&mut cursor)
+        .into_diagnostic()
+        .wrap_err("Failed to convert to binary format")?;
+
+    Ok(cursor.into_inner())
+}
+
+/// Read a ritobin text file (.py/.ritobin), dropping a leading byte order mark
+pub fn read_text_file(path: &Utf8Path) -> Result<String> {
+    let mut file = File::open(path)
+        .read_access()
+        .wrap_err_with(|| format!("Failed to open file: {}", path))?;
+
+    let mut content = String::new();
+    file.read_to_string(&mut content)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to read file: {}", path))?;
+
+    if content.starts_with(UTF8_BOM) {
+        content.drain(..UTF8_BOM.len());
+    }
+    Ok(content)
+}
+
+/// `text` without a leading byte order mark
+pub fn strip_bom(text: &str) -> &str {
+    text.strip_prefix(UTF8_BOM).unwrap_or(text)
+}
+
+/// Whether the file at `path` starts with a UTF-8 byte order mark.
+/// Missing or unreadable files count as not having one.
+pub fn file_has_bom(path: &Utf8Path) -> bool {
+    let mut start = [0u8; UTF8_BOM.len()];
+    File::open(path)
+        .and_then(|mut file| file.read_exact(&mut start))
+        .is_ok_and(|_| start == UTF8_BOM.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Ritobin text as the writer formats it, with every hash written as hex
+    const SAMPLE: &str = r#"#PROP_text
+type: string = "PROP"
+version: u32 = 3
+entries: map[hash,embed] = {
+    0x2a5deb8f = 0x9b67e9f6 {
+        0x2d78c328: string = "Ahri"
+        0xa1f805da: f32 = 1.2
+        0x7e0dca60: list[u32] = {
+            1
+            2
+        }
+        0xbdeaa8d7: bool = true
+    }
+}
+"#;
+
+    #[test]
+    fn text_round_trips_through_bin() {
+        let tree = parse_text(SAMPLE).unwrap();
+        let bytes = tree_to_bytes(&tree).unwrap();
+        let text = tree_to_text(&read_bin_bytes(&bytes).unwrap(), None).unwrap();
+        assert_eq!(text, SAMPLE);
+    }
+
+    #[test]
+    fn read_text_file_strips_bom() {
+        let path =
+            std::env::temp_dir().join(format!("ritobin-tools-bom-{}.py", std::process::id()));
+        let path = camino::Utf8PathBuf::from_path_buf(path).unwrap();
+        std::fs::write(&path, format!("{}{}", UTF8_BOM, SAMPLE)).unwrap();
+
+        let text = read_text_file(&path);
+        std::fs::remove_file(&path).unwrap();
+        let text = text.unwrap();
+        assert_eq!(text, SAMPLE);
+        assert!(parse_text(&text).is_ok());
+    }
+
+    fn provider(entries: &[(u32, &str)]) -> HashMapProvider {
+        let mut provider = HashMapProvider::default();
+        for (hash, name) in entries {
+            provider.entries.insert(*hash, name.to_string());
+        }
+        provider
+    }
+
+    #[test]
+    fn fallback_provider_prefers_primary() {
+        let names = FallbackProvider {
+            primary: Some(provider(&[(1, "primary"), (2, "only primary")])),
+            fallback: provider(&[(1, "fallback"), (3, "only fallback")]),
+        };
+        assert_eq!(names.lookup_entry(1), Some("primary"));
+        assert_eq!(names.lookup_entry(2), Some("only primary"));
+        assert_eq!(names.lookup_entry(3), Some("only fallback"));
+        assert_eq!(names.lookup_entry(4), None);
+    }
+
+    #[test]
+    fn fallback_provider_without_primary() {
+        let names = FallbackProvider {
+            primary: None::<HashMapProvider>,
+            fallback: provider(&[(1, "fallback")]),
+        };
+        assert_eq!(names.lookup_entry(1), Some("fallback"));
+    }
+}
@@ -0,0 +1,161 @@
+//! JSON rendering of bin trees.
+//!
+//! The layout follows the ritobin text: a document with `type`, `version`, `linked` and
+//! `entries`, where every entry and struct is an object holding its class as `__type`
+//! followed by its fields. Hashes are written as names where known, otherwise as hex
+//! strings. The output is meant for reading and scripting, it can't be converted back.
+
+use ltk_meta::value::{EmbeddedValue, StructValue, UnorderedContainerValue};
+use ltk_meta::{BinProperty, BinTree, PropertyValueEnum};
+use ltk_ritobin::HashProvider;
+use miette::{IntoDiagnostic, Result, WrapErr};
+use serde_json::{Map, Value, json};
+
+use crate::utils::format_hash;
+
+/// Key holding the class name of entries and structs
+const TYPE_KEY: &str = "__type";
+
+/// Render a tree as pretty-printed JSON, resolving hashes with `names`
+pub fn tree_to_json(tree: &BinTree, names: &impl HashProvider) -> Result<String> {
+    let writer = JsonWriter { names };
+
+    let entries: Map<String, Value> = tree
+        .objects
+        .values()
+        .map(|object| {
+            (
+                writer.hash_name(names.lookup_entry(object.path_hash), object.path_hash),
+                writer.object(object.class_hash, object.properties.values()),
+            )
+        })
+        .collect();
+
+    let document = json!({
+        "type": if tree.is_override { "PTCH" } else { "PROP" },
+        "version": tree.version,
+        "linked": tree.dependencies,
+        "entries": entries,
+    });
+
+    serde_json::to_string_pretty(&document)
+        .map(|json| json + "\n")
+        .into_diagnostic()
+        .wrap_err("Failed to convert to JSON")
+}
+
+struct JsonWriter<'a, H> {
+    names: &'a H,
+}
+
+impl<H: HashProvider> JsonWriter<'_, H> {
+    fn hash_name(&self, name: Option<&str>, hash: u32) -> String {
+        name.map(str::to_string)
+            .unwrap_or_else(|| format_hash(hash))
+    }
+
+    /// An entry or struct: its class under [`TYPE_KEY`], then its fields in order
+    fn object<'p>(
+        &self,
+        class_hash: u32,
+        properties: impl Iterator<Item = &'p BinProperty>,
+    ) -> Value {
+        let mut object = Map::new();
+        object.insert(
+            TYPE_KEY.to_string(),
+            Value::String(self.hash_name(self.names.lookup_type(class_hash), class_hash)),
+        );
+        for property in properties {
+            object.insert(
+                self.hash_name(
+                    self.names.lookup_field(property.name_hash),
+                    property.name_hash,
+                ),
+                self.value(&property.value),
+            );
+        }
+        Value::Object(object)
+    }
+
+    fn struct_value(&self, value: &StructValue) -> Value {
+        // A pointer without a class is a null pointer
+        if value.class_hash == 0 && value.properties.is_empty() {
+            return Value::Null;
+        }
+        self.object(value.class_hash, value.properties.values())
+    }
+
+    fn value(&self, value: &PropertyValueEnum) -> Value {
+        match value {
+            PropertyValueEnum::None(_) => Value::Null,
+            PropertyValueEnum::Bool(v) => json!(v.0),
+            PropertyValueEnum::BitBool(v) => json!(v.0),
+            PropertyValueEnum::I8(v) => json!(v.0),
+            PropertyValueEnum::U8(v) => json!(v.0),
+            PropertyValueEnum::I16(v) => json!(v.0),
+            PropertyValueEnum::U16(v) => json!(v.0),
+            PropertyValueEnum::I32(v) => json!(v.0),
+            PropertyValueEnum::U32(v) => json!(v.0),
+            PropertyValueEnum::I64(v) => json!(v.0),
+            PropertyValueEnum::U64(v) => json!(v.0),
+            PropertyValueEnum::F32(v) => float(v.0),
+            PropertyValueEnum::Vector2(v) => floats(&[v.0.x, v.0.y]),
+            PropertyValueEnum::Vector3(v) => floats(&[v.0.x, v.0.y, v.0.z]),
+            PropertyValueEnum::Vector4(v) => floats(&[v.0.x, v.0.y, v.0.z, v.0.w]),
+            PropertyValueEnum::Matrix44(v) => floats(&v.0.to_cols_array()),
+            PropertyValueEnum::Color(v) => json!([v.0.r, v.0.g, v.0.b, v.0.a]),
+            PropertyValueEnum::String(v) => json!(v.0),
+            PropertyValueEnum::Hash(v) => json!(self.hash_name(self.names.lookup_hash(v.0), v.0)),
+            PropertyValueEnum::ObjectLink(v) => {
+                json!(self.hash_name(self.names.lookup_entry(v.0), v.0))
+            }
+            PropertyValueEnum::WadChunkLink(v) => json!(format_hash(v.0)),
+            PropertyValueEnum::Optional(optional) => match optional.value.as_deref() {
+                Some(value) => self.value(value),
+                None => Value::Null,
+            },
+            PropertyValueEnum::Struct(value)
+            | PropertyValueEnum::Embedded(EmbeddedValue(value)) => self.struct_value(value),
+            PropertyValueEnum::Container(container)
+            | PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)) => {
+                Value::Array(
+                    container
+                        .items
+                        .iter()
+                        .map(|item| self.value(item))
+                        .collect(),
+                )
+            }
+            // JSON keys are strings, so keys are written as their string form
+            PropertyValueEnum::Map(map) => Value::Object(
+                map.entries
+                    .iter()
+                    .map(|(key, value)| (self.key(&key.0), self.value(value)))
+                    .collect(),
+            ),
+        }
+    }
+
+    fn key(&self, key: &PropertyValueEnum) -> String {
+        match self.value(key) {
+            Value::String(key) => key,
+            key => key.to_string(),
+        }
+    }
+}
+
+/// A float as the shortest decimal that reads back as the same `f32`.
+/// JSON has no NaN or infinity, those are written as strings.
+fn float(value: f32) -> Value {
+    value
+        .to_string()
+        .parse::<f64>()
+        .ok()
+        .and_then(serde_json::Number::from_f64)
+        .map(Value::Number)
+        .unwrap_or_else(|| Value::String(value.to_string()))
+}
+
+fn floats(values: &[f32]) -> Value {
+    Value::Array(values.iter().copied().map(float).collect())
+}
@@ -1,6 +1,6 @@
 //! Application configuration management utilities.
 
-use camino::Utf8PathBuf;
+use camino::{Utf8Path, Utf8PathBuf};
 use miette::Context;
 use miette::IntoDiagnostic;
 use miette::Result;
@@ -9,18 +9,104 @@ use std::env;
 use std::fs;
 use std::io;
 use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Once, OnceLock};
+
+use crate::utils::hashtable::HASHTABLE_FILES;
+use crate::utils::{LinkStyle, TextExtension};
+
+/// Default connect/read timeout for hashtable downloads.
+pub const DEFAULT_DOWNLOAD_TIMEOUT_SECS: u64 = 30;
+
+/// Top-level keys understood by [`AppConfig`], anything else in config.toml is ignored.
+pub const CONFIG_KEYS: &[&str] = &[
+    "hashtable_dir",
+    "download_timeout_secs",
+    "link_style",
+    "io_buffer_size",
+    "combined_hashtable",
+    "hashtable_max_age_days",
+    "hashtable_auto_update",
+    "extra_hashtables",
+    "hash_resolver_cmd",
+    "binary_extensions",
+    "text_extensions",
+    "text_extension",
+];
+
+/// Keys holding paths, stored with forward slashes like [`save_config`] does.
+pub const PATH_CONFIG_KEYS: &[&str] = &["hashtable_dir", "combined_hashtable"];
+
+/// Keys holding lists, set as comma-separated values like `bin,troybin`.
+pub const LIST_CONFIG_KEYS: &[&str] = &["binary_extensions", "text_extensions"];
+
+/// Extensions `convert` reads as .bin when `binary_extensions` is unset
+pub const DEFAULT_BINARY_EXTENSIONS: &[&str] = &["bin"];
+
+/// Extensions `convert` reads as ritobin text when `text_extensions` is unset
+pub const DEFAULT_TEXT_EXTENSIONS: &[&str] = &["py", "ritobin"];
 
 /// Application-wide configuration stored in config.toml.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AppConfig {
     /// Directory where ritobin hashtables are stored.
     pub hashtable_dir: Option<Utf8PathBuf>,
+    /// Connect/read timeout in seconds for hashtable downloads.
+    pub download_timeout_secs: Option<u64>,
+    /// How paths are rendered in output: `auto`, `osc8` or `plain`.
+    pub link_style: Option<LinkStyle>,
+    /// Buffer size in bytes for file reads/writes and downloads, unset keeps each default.
+    pub io_buffer_size: Option<usize>,
+    /// Single hashtable file with `hash name` lines of every category, loaded on top of
+    /// the files in `hashtable_dir`.
+    pub combined_hashtable: Option<Utf8PathBuf>,
+    /// Hashtables older than this many days are reported as stale when loaded.
+    pub hashtable_max_age_days: Option<u64>,
+    /// Download stale hashtables instead of only warning. Ignored when the `CI`
+    /// environment variable is set, use `--auto-update` there.
+    pub hashtable_auto_update: Option<bool>,
+    /// Hashtable files besides the default four, downloaded by `download-hashes` and
+    /// loaded from `hashtable_dir` with them.
+    pub extra_hashtables: Option<Vec<ExtraHashtable>>,
+    /// Command naming the hashes the hashtables don't know, see [`hash_resolver`]. Split
+    /// into program and arguments like a shell would, without running a shell.
+    ///
+    /// [`hash_resolver`]: crate::utils::hash_resolver
+    pub hash_resolver_cmd: Option<String>,
+    /// Input extensions `convert` reads as .bin, without the dot. The extension decides
+    /// how a file is read, its header is only checked afterwards.
+    pub binary_extensions: Option<Vec<String>>,
+    /// Input extensions `convert` reads as ritobin text, without the dot.
+    pub text_extensions: Option<Vec<String>>,
+    /// Extension `convert` gives text outputs without an explicit path, `py` if unset.
+    pub text_extension: Option<TextExtension>,
+}
+
+/// A hashtable file outside the CommunityDragon set, e.g. a mod team's private hash list.
+/// Lines are `hash name` like in `combined_hashtable`, so names apply to every category.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ExtraHashtable {
+    /// File name inside `hashtable_dir`
+    pub file: String,
+    /// http(s) URL `download-hashes` fetches the file from
+    pub url: String,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
             hashtable_dir: default_hashtable_dir(),
+            download_timeout_secs: Some(DEFAULT_DOWNLOAD_TIMEOUT_SECS),
+            link_style: Some(LinkStyle::default()),
+            io_buffer_size: None,
+            combined_hashtable: None,
+            hashtable_max_age_days: None,
+            hashtable_auto_update: None,
+            extra_hashtables: None,
+            hash_resolver_cmd: None,
+            binary_extensions: None,
+            text_extensions: None,
+            text_extension: None,
         }
     }
 }
@@ -43,7 +129,7 @@ pub fn default_config_path() -> Option<Utf8PathBuf> {
 }
 
 /// Normalizes a path to use forward slashes
-fn normalize_path(path: &Utf8PathBuf) -> Utf8PathBuf {
+pub fn normalize_path(path: &Utf8PathBuf) -> Utf8PathBuf {
     Utf8PathBuf::from(path.as_str().replace('\\', "/"))
 }
 
@@ -53,6 +139,8 @@ pub fn save_config(cfg: &AppConfig) -> io::Result<()> {
     if let Some(path) = default_config_path() {
         let normalized_cfg = AppConfig {
             hashtable_dir: cfg.hashtable_dir.as_ref().map(normalize_path),
+            combined_hashtable: cfg.combined_hashtable.as_ref().map(normalize_path),
+            ..cfg.clone()
         };
 
         let content = toml::to_string_pretty(&normalized_cfg).map_err(io::Error::other)?;
@@ -65,24 +153,87 @@ pub fn save_config(cfg: &AppConfig) -> io::Result<()> {
     }
 }
 
-/// Loads existing configuration or creates a new one with defaults.
-/// Missing fields in the config file are filled with default values.
+static STRICT_CONFIG: AtomicBool = AtomicBool::new(false);
+
+/// Make a config.toml that fails to parse an error for the rest of the run,
+/// instead of falling back to the defaults.
+pub fn set_strict_config(strict: bool) {
+    STRICT_CONFIG.store(strict, Ordering::Relaxed);
+}
+
+/// Values from `--set key=value`, laid over config.toml for this run
+static CONFIG_OVERRIDES: OnceLock<toml::Table> = OnceLock::new();
+
+/// Lay `overrides` over the configuration [`load_or_create_config`] returns for the rest of
+/// the run, without writing them to config.toml.
+pub fn set_config_overrides(overrides: toml::Table) {
+    let _ = CONFIG_OVERRIDES.set(overrides);
+}
+
+/// Parse a `--set key=value` argument, rejecting keys that aren't in [`CONFIG_KEYS`]
+pub fn parse_config_override(arg: &str) -> Result<(String, String), String> {
+    let (key, value) = arg
+        .split_once('=')
+        .ok_or_else(|| format!("expected key=value, got '{}'", arg))?;
+    let key = key.trim();
+    if !CONFIG_KEYS.contains(&key) {
+        return Err(format!(
+            "unknown config key '{}' (known keys: {})",
+            key,
+            CONFIG_KEYS.join(", ")
+        ));
+    }
+    Ok((key.to_string(), value.trim().to_string()))
+}
+
+/// Loads the configuration like [`load_saved_config`], resolves its relative hashtable
+/// paths against the config file's directory, and lays the `--set` values from
+/// [`set_config_overrides`] over it. Relative paths in those stay relative to the working
+/// directory.
 pub fn load_or_create_config() -> Result<(AppConfig, Utf8PathBuf)> {
+    let (mut cfg, path) = load_saved_config()?;
+    resolve_relative_paths(&mut cfg, &path);
+    match CONFIG_OVERRIDES.get() {
+        Some(overrides) if !overrides.is_empty() => Ok((apply_overrides(cfg, overrides)?, path)),
+        _ => Ok((cfg, path)),
+    }
+}
+
+fn apply_overrides(cfg: AppConfig, overrides: &toml::Table) -> Result<AppConfig> {
+    let mut table = toml::Table::try_from(&cfg)
+        .into_diagnostic()
+        .wrap_err("Failed to apply --set values")?;
+    table.extend(overrides.clone());
+    let cfg: AppConfig = table
+        .try_into()
+        .into_diagnostic()
+        .wrap_err("Invalid --set value")?;
+    validate_config(&cfg).wrap_err("Invalid --set value")?;
+    Ok(cfg)
+}
+
+/// Loads existing configuration or creates a new one with defaults, as saved in
+/// config.toml without the `--set` values.
+/// Missing fields in the config file are filled with default values.
+/// A config file that fails to parse is backed up and replaced by the defaults for this
+/// run, unless strict config is enabled with [`set_strict_config`].
+/// Paths stay as written, so the result can be saved back without making them absolute.
+pub fn load_saved_config() -> Result<(AppConfig, Utf8PathBuf)> {
     let path = default_config_path().ok_or(miette::miette!("Could not determine config path"))?;
 
     if Path::new(path.as_str()).exists() {
         let content = fs::read_to_string(path.as_str())
             .into_diagnostic()
             .wrap_err("Failed to read config file")?;
-        let mut cfg: AppConfig = toml::from_str(&content)
-            .into_diagnostic()
-            .wrap_err("Failed to parse config file")?;
 
-        // Fill in defaults for missing optional fields
-        let defaults = AppConfig::default();
-        if cfg.hashtable_dir.is_none() {
-            cfg.hashtable_dir = defaults.hashtable_dir;
-        }
+        let cfg = match parse_config(&content, &path) {
+            Ok(cfg) => cfg,
+            Err(e) if !STRICT_CONFIG.load(Ordering::Relaxed) => {
+                warn_broken_config(&path, &e);
+                AppConfig::default()
+            }
+            Err(e) => return Err(e),
+        };
 
         Ok((cfg, path))
     } else {
@@ -94,6 +245,234 @@ pub fn load_or_create_config() -> Result<(AppConfig, Utf8PathBuf)> {
     }
 }
 
+/// Reads and parses the config file at `path`, failing where [`load_saved_config`] would
+/// fall back to the defaults
+pub fn read_config_file(path: &Utf8PathBuf) -> Result<AppConfig> {
+    let content = fs::read_to_string(path.as_str())
+        .into_diagnostic()
+        .wrap_err("Failed to read config file")?;
+    parse_config(&content, path)
+}
+
+/// Makes relative `hashtable_dir` and `combined_hashtable` paths relative to the directory
+/// of the config file instead of the working directory, so a config checked in next to its
+/// hashtables works wherever the tool is run from.
+fn resolve_relative_paths(cfg: &mut AppConfig, config_path: &Utf8Path) {
+    for path in [&mut cfg.hashtable_dir, &mut cfg.combined_hashtable]
+        .into_iter()
+        .flatten()
+    {
+        *path = resolve_relative_path(path, config_path);
+    }
+}
+
+/// `path` as written in the config file at `config_path`, made relative to its directory
+/// when it's relative, see [`resolve_relative_paths`]
+pub fn resolve_relative_path(path: &Utf8Path, config_path: &Utf8Path) -> Utf8PathBuf {
+    match config_path.parent() {
+        Some(config_dir) if path.is_relative() => config_dir.join(path),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn parse_config(content: &str, path: &Utf8PathBuf) -> Result<AppConfig> {
+    let table: toml::Table = toml::from_str(content)
+        .into_diagnostic()
+        .wrap_err("Failed to parse config file")?;
+    warn_unknown_keys(&table, path);
+
+    let mut cfg: AppConfig = table
+        .try_into()
+        .into_diagnostic()
+        .wrap_err("Failed to parse config file")?;
+    validate_config(&cfg).wrap_err("Invalid config file")?;
+
+    // Fill in defaults for missing optional fields
+    let defaults = AppConfig::default();
+    if cfg.hashtable_dir.is_none() {
+        cfg.hashtable_dir = defaults.hashtable_dir;
+    }
+    if cfg.download_timeout_secs.is_none() {
+        cfg.download_timeout_secs = defaults.download_timeout_secs;
+    }
+    if cfg.link_style.is_none() {
+        cfg.link_style = defaults.link_style;
+    }
+
+    Ok(cfg)
+}
+
+/// Checks the values serde can't: `extra_hashtables`, `hash_resolver_cmd` and the
+/// extension lists
+fn validate_config(cfg: &AppConfig) -> Result<()> {
+    validate_extra_hashtables(cfg.extra_hashtables.as_deref().unwrap_or_default())
+        .wrap_err("Invalid extra_hashtables")?;
+    if let Some(command) = &cfg.hash_resolver_cmd {
+        resolver_command(command).wrap_err("Invalid hash_resolver_cmd")?;
+    }
+    validate_extensions(
+        cfg.binary_extensions.as_deref(),
+        cfg.text_extensions.as_deref(),
+    )
+}
+
+/// Checks that the extension lists aren't empty, hold plain extensions without the dot,
+/// and don't share one, which would leave it unclear how such a file is read.
+fn validate_extensions(binary: Option<&[String]>, text: Option<&[String]>) -> Result<()> {
+    for (key, list) in [("binary_extensions", binary), ("text_extensions", text)] {
+        let Some(list) = list else {
+            continue;
+        };
+        if list.is_empty() {
+            return Err(miette::miette!(
+                "{} is empty, remove it to use the defaults",
+                key
+            ));
+        }
+        if let Some(extension) = list
+            .iter()
+            .find(|ext| ext.is_empty() || ext.contains(['.', '/', '\\']))
+        {
+            return Err(miette::miette!(
+                "'{}' in {} is not an extension, write it without the dot, e.g. 'troybin'",
+                extension,
+                key
+            ));
+        }
+    }
+
+    let binary = binary.map_or_else(
+        || {
+            DEFAULT_BINARY_EXTENSIONS
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect()
+        },
+        <[String]>::to_vec,
+    );
+    let text = text.map_or_else(
+        || {
+            DEFAULT_TEXT_EXTENSIONS
+                .iter()
+                .map(|ext| ext.to_string())
+                .collect()
+        },
+        <[String]>::to_vec,
+    );
+    if let Some(shared) = binary
+        .iter()
+        .find(|ext| text.iter().any(|t| t.eq_ignore_ascii_case(ext)))
+    {
+        return Err(miette::miette!(
+            "'{}' is in both binary_extensions and text_extensions",
+            shared
+        ));
+    }
+    Ok(())
+}
+
+/// Splits `hash_resolver_cmd` into the program and its arguments, rejecting empty commands
+/// and unclosed quotes
+pub fn resolver_command(command: &str) -> Result<Vec<String>> {
+    let words = shlex::split(command)
+        .ok_or_else(|| miette::miette!("'{}' has an unclosed quote", command))?;
+    if words.is_empty() {
+        return Err(miette::miette!("the command must not be empty"));
+    }
+    Ok(words)
+}
+
+/// Checks that every extra hashtable has a plain, unique file name that doesn't replace one
+/// of the default hashtables, and an http(s) URL with a host.
+fn validate_extra_hashtables(extra: &[ExtraHashtable]) -> Result<()> {
+    for (index, hashtable) in extra.iter().enumerate() {
+        let file = hashtable.file.as_str();
+        if file.is_empty() || file == "." || file == ".." || file.contains(['/', '\\']) {
+            return Err(miette::miette!(
+                "'{}' is not a file name, extra hashtables are stored directly in hashtable_dir",
+                file
+            ));
+        }
+        if HASHTABLE_FILES.contains(&file) {
+            return Err(miette::miette!(
+                "'{}' would replace one of the default hashtables",
+                file
+            ));
+        }
+        if extra[..index].iter().any(|other| other.file == file) {
+            return Err(miette::miette!("'{}' is listed more than once", file));
+        }
+
+        let host = hashtable
+            .url
+            .strip_prefix("https://")
+            .or_else(|| hashtable.url.strip_prefix("http://"))
+            .and_then(|rest| rest.split(['/', '?', '#']).next());
+        if host.is_none_or(str::is_empty) {
+            return Err(miette::miette!(
+                "URL '{}' of '{}' must start with http:// or https:// followed by a host",
+                hashtable.url,
+                file
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Warns that the config file is broken and copies it to `config.toml.bak`, so commands that
+/// save the config (`config reset`, `config init`) can't lose what was in it.
+/// The config is loaded several times per run, so this only happens once.
+fn warn_broken_config(path: &Utf8PathBuf, error: &miette::Report) {
+    static WARNED: Once = Once::new();
+
+    WARNED.call_once(|| {
+        let causes = error.chain().map(ToString::to_string).collect::<Vec<_>>();
+        let backup = path.with_extension("toml.bak");
+        let backed_up = match fs::copy(path, &backup) {
+            Ok(_) => format!(", a copy was saved to {}", backup),
+            Err(e) => {
+                tracing::debug!("Failed to back up {}: {}", path, e);
+                String::new()
+            }
+        };
+        tracing::warn!(
+            "Ignoring {} and using the default configuration{}: {}. Use --strict-config to fail instead",
+            path,
+            backed_up,
+            causes.join(": ").trim_end()
+        );
+    });
+}
+
+/// Returns the keys in `table` that aren't in [`CONFIG_KEYS`].
+pub fn unknown_keys(table: &toml::Table) -> Vec<&str> {
+    table
+        .keys()
+        .map(String::as_str)
+        .filter(|key| !CONFIG_KEYS.contains(key))
+        .collect()
+}
+
+/// Warns about unrecognized keys, which are usually typos like `hashtables_dir`.
+/// The config is loaded several times per run, so this only warns once.
+fn warn_unknown_keys(table: &toml::Table, path: &Utf8PathBuf) {
+    static WARNED: Once = Once::new();
+
+    let unknown = unknown_keys(table);
+    if unknown.is_empty() {
+        return;
+    }
+
+    WARNED.call_once(|| {
+        tracing::warn!(
+            "Ignoring unknown config key(s) in {}: {} (known keys: {})",
+            path,
+            unknown.join(", "),
+            CONFIG_KEYS.join(", ")
+        );
+    });
+}
+
 /// Loads configuration as a raw TOML table for flexible editing.
 pub fn load_config_as_table() -> Result<toml::Table> {
     let path = default_config_path().ok_or(miette::miette!("Could not determine config path"))?;
@@ -0,0 +1,60 @@
+//! Process exit codes, so scripts and CI can tell kinds of failures apart.
+//!
+//! - 0: success
+//! - 1: any other error
+//! - 2: usage error, options that don't work together or don't fit the input (clap's own
+//!   argument errors use 2 as well)
+//! - 3: partial failure, some files of a batch failed while the rest were processed
+//! - 4: network error, a download failed
+
+use std::fmt;
+use std::process::ExitCode;
+
+/// Exit code of errors that aren't a [`Failure`]
+pub const GENERIC_ERROR: u8 = 1;
+
+/// An error that exits with its own code instead of [`GENERIC_ERROR`]. Commands return it
+/// like any other error, e.g. `Err(Failure::Usage(message).into())`, and context added on
+/// the way up doesn't hide it.
+#[derive(Debug)]
+pub enum Failure {
+    /// Options that don't work together or don't fit the input
+    Usage(String),
+    /// Some files of a batch failed, the others were processed
+    Partial(String),
+    /// A download failed
+    Network(String),
+}
+
+impl Failure {
+    pub fn code(&self) -> u8 {
+        match self {
+            Failure::Usage(_) => 2,
+            Failure::Partial(_) => 3,
+            Failure::Network(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Failure::Usage(message) | Failure::Partial(message) | Failure::Network(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+impl miette::Diagnostic for Failure {}
+
+/// The exit code for a command's error: that of the outermost [`Failure`] in it, or
+/// [`GENERIC_ERROR`]
+pub fn exit_code(report: &miette::Report) -> ExitCode {
+    let failure = report
+        .downcast_ref::<Failure>()
+        .or_else(|| report.chain().find_map(|e| e.downcast_ref::<Failure>()));
+    ExitCode::from(failure.map_or(GENERIC_ERROR, Failure::code))
+}
@@ -0,0 +1,124 @@
+//! Names from an external command, for teams whose hash names live in a service instead
+//! of a hashtable file.
+//!
+//! The `hash_resolver_cmd` command gets the hashes the hashtables can't name on stdin, one
+//! 8-digit hex hash per line, and answers with `hash name` lines like a hashtable file.
+//! Every answer is cached for the rest of the run, including the hashes it had no name for,
+//! so the command is only asked about each hash once.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, OnceLock, PoisonError};
+use std::thread;
+
+use miette::{IntoDiagnostic, Result, WrapErr};
+
+/// Program and arguments of the resolver command
+static RESOLVER: OnceLock<Vec<String>> = OnceLock::new();
+
+/// Set once the command failed, so a broken resolver is only run and warned about once
+static FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Every hash the command was asked about, with the name it gave if any
+static CACHE: Mutex<BTreeMap<u32, Option<String>>> = Mutex::new(BTreeMap::new());
+
+/// Name hashes the hashtables don't know with `command`, a program followed by its arguments
+pub fn set_hash_resolver(command: Vec<String>) {
+    let _ = RESOLVER.set(command);
+}
+
+/// Whether a resolver command is configured and hasn't failed
+pub fn is_enabled() -> bool {
+    RESOLVER.get().is_some() && !FAILED.load(Ordering::Relaxed)
+}
+
+/// Names for `hashes` from the resolver command, which is only asked about hashes it
+/// wasn't asked about before. If the command can't be run or fails, a warning is logged and
+/// it isn't asked again, leaving the hashes without a name.
+pub fn resolve(hashes: &BTreeSet<u32>) -> HashMap<u32, String> {
+    let Some(command) = RESOLVER.get() else {
+        return HashMap::new();
+    };
+
+    let mut cache = CACHE.lock().unwrap_or_else(PoisonError::into_inner);
+    let unknown: Vec<u32> = hashes
+        .iter()
+        .copied()
+        .filter(|hash| !cache.contains_key(hash))
+        .collect();
+    if !unknown.is_empty() && !FAILED.load(Ordering::Relaxed) {
+        match run_resolver(command, &unknown) {
+            Ok(mut names) => {
+                for hash in unknown {
+                    cache.insert(hash, names.remove(&hash));
+                }
+            }
+            Err(e) => {
+                FAILED.store(true, Ordering::Relaxed);
+                tracing::warn!(
+                    "Not resolving hashes with hash_resolver_cmd for the rest of the run: {}",
+                    e.chain()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(": ")
+                );
+            }
+        }
+    }
+
+    hashes
+        .iter()
+        .filter_map(|hash| Some((*hash, cache.get(hash)?.clone()?)))
+        .collect()
+}
+
+/// Run the command once for all of `hashes` and parse its answer
+fn run_resolver(command: &[String], hashes: &[u32]) -> Result<HashMap<u32, String>> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| miette::miette!("hash_resolver_cmd is empty"))?;
+    tracing::debug!("Asking {} to name {} hash(es)", program, hashes.len());
+
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to run hash_resolver_cmd '{}'", program))?;
+
+    // Written from another thread, a resolver that answers while still reading would
+    // otherwise fill the stdout pipe and deadlock with us
+    let input: String = hashes
+        .iter()
+        .map(|hash| format!("{:08x}\n", hash))
+        .collect();
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+
+    let output = child
+        .wait_with_output()
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to run hash_resolver_cmd '{}'", program))?;
+    // A resolver may exit without reading everything, the broken pipe only matters if it failed
+    let _ = writer.join();
+    if !output.status.success() {
+        return Err(miette::miette!(
+            "hash_resolver_cmd '{}' failed: {}",
+            program,
+            output.status
+        ));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (hash, name) = line.trim().split_once(' ')?;
+            let hash = u32::from_str_radix(hash.trim_start_matches("0x"), 16).ok()?;
+            let name = name.trim();
+            (!name.is_empty()).then(|| (hash, name.to_string()))
+        })
+        .collect())
+}
@@ -0,0 +1,441 @@
+//! Hashtable loading and binary cache utilities.
+
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Once, OnceLock};
+use std::time::{Duration, SystemTime};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use ltk_ritobin::HashMapProvider;
+use miette::{IntoDiagnostic, Result, WrapErr};
+use time::OffsetDateTime;
+
+use crate::utils::{DEFAULT_IO_BUFFER_SIZE, io_buffer_size};
+
+/// Text hashtable files loaded by [`load_text_hashtables`], in the order of the categories
+/// they hold: entries, fields, hashes and types
+pub const HASHTABLE_FILES: &[&str] = &[
+    "hashes.binentries.txt",
+    "hashes.binfields.txt",
+    "hashes.binhashes.txt",
+    "hashes.bintypes.txt",
+];
+
+/// File name of the binary hashtable cache inside the hashtable directory
+pub const HASH_CACHE_FILE: &str = "hashes.bincache";
+
+const HASH_CACHE_MAGIC: &[u8; 4] = b"RBHC";
+const HASH_CACHE_VERSION: u32 = 1;
+
+/// What to do about hashtables older than `max_age`, see [`set_update_policy`]
+#[derive(Debug, Clone, Copy)]
+pub struct UpdatePolicy {
+    pub max_age: Duration,
+    /// Downloads fresh hashtables instead of only warning, `None` only warns
+    pub update: Option<fn() -> Result<()>>,
+}
+
+/// `hashtable_max_age_days` as a duration, saturating instead of overflowing for huge values
+pub fn max_age(days: u64) -> Duration {
+    Duration::from_secs(days.saturating_mul(24 * 60 * 60))
+}
+
+static UPDATE_POLICY: OnceLock<UpdatePolicy> = OnceLock::new();
+
+/// Check the age of the hashtables whenever they are loaded for the rest of the run.
+/// Only the first call has an effect.
+pub fn set_update_policy(policy: UpdatePolicy) {
+    let _ = UPDATE_POLICY.set(policy);
+}
+
+static EXTRA_HASHTABLES: OnceLock<Vec<String>> = OnceLock::new();
+
+static STRICT_HASHTABLES: AtomicBool = AtomicBool::new(false);
+
+/// Make a hashtable file that can't be loaded an error for the rest of the run, instead of
+/// skipping it with a warning and keeping the names of the others.
+pub fn set_strict_hashtables(strict: bool) {
+    STRICT_HASHTABLES.store(strict, Ordering::Relaxed);
+}
+
+/// Load these files from the hashtable directory along with the default hashtables for the
+/// rest of the run, see `extra_hashtables` in the config. Only the first call has an effect.
+pub fn set_extra_hashtables(files: Vec<String>) {
+    let _ = EXTRA_HASHTABLES.set(files);
+}
+
+/// Loads a hash provider from the given hashtable directory, then merges in the
+/// extra hashtables and the `combined` hashtable if any are configured.
+/// Only fails for a broken hashtable with [`set_strict_hashtables`].
+pub fn load_hash_provider(dir: &Utf8Path, combined: Option<&Utf8Path>) -> Result<HashMapProvider> {
+    update_if_stale(dir);
+    let mut provider = load_hashtable_dir(dir)?;
+
+    for file in EXTRA_HASHTABLES.get().into_iter().flatten() {
+        let path = dir.join(file);
+        match merge_combined_hashtable(&mut provider, &path) {
+            Ok(count) => tracing::debug!("Loaded {} hashes from {}", count, path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => tracing::warn!(
+                "Extra hashtable {} is missing. Run `ritobin-tools download-hashes` to fetch it",
+                path
+            ),
+            Err(e) => tracing::warn!("Ignoring extra hashtable {}: {}", path, e),
+        }
+    }
+
+    if let Some(combined) = combined {
+        match merge_combined_hashtable(&mut provider, combined) {
+            Ok(count) => tracing::debug!("Loaded {} hashes from {}", count, combined),
+            Err(e) => tracing::warn!("Ignoring combined hashtable {}: {}", combined, e),
+        }
+    }
+
+    warn_if_empty(&provider, dir);
+    Ok(provider)
+}
+
+/// Loads the four text hashtables of a directory.
+/// Prefers the binary cache when it is present and newer than every text hashtable.
+fn load_hashtable_dir(dir: &Utf8Path) -> Result<HashMapProvider> {
+    let cache_path = dir.join(HASH_CACHE_FILE);
+
+    if is_cache_fresh(dir, &cache_path) {
+        match read_hash_cache(&cache_path) {
+            Ok(provider) => {
+                tracing::debug!(
+                    "Loaded {} hashes from cache {}",
+                    provider.total_count(),
+                    cache_path
+                );
+                return Ok(provider);
+            }
+            Err(e) => tracing::warn!("Ignoring unreadable hashtable cache {}: {}", cache_path, e),
+        }
+    }
+
+    load_text_hashtables(dir)
+}
+
+/// Loads the four text hashtables of a directory, skipping missing files.
+/// A file that can't be read, or has content but no `hash name` lines (e.g. an HTML error
+/// page saved by a proxy), is skipped with a warning so the other categories keep their
+/// names, or fails the load with [`set_strict_hashtables`].
+pub fn load_text_hashtables(dir: &Utf8Path) -> Result<HashMapProvider> {
+    let mut provider = HashMapProvider::new();
+
+    for (index, file) in HASHTABLE_FILES.iter().enumerate() {
+        let path = dir.join(file);
+        let loaded = match index {
+            0 => provider.load_entries(&path).map(|p| p.entries.len()),
+            1 => provider.load_fields(&path).map(|p| p.fields.len()),
+            2 => provider.load_hashes(&path).map(|p| p.hashes.len()),
+            _ => provider.load_types(&path).map(|p| p.types.len()),
+        };
+        let error = match loaded {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => e.to_string(),
+            Ok(0) if fs::metadata(&path).is_ok_and(|m| m.len() > 0) => {
+                "no `hash name` lines found".to_string()
+            }
+            Ok(_) => continue,
+        };
+
+        if STRICT_HASHTABLES.load(Ordering::Relaxed) {
+            return Err(miette::miette!(
+                "Failed to load hashtable {}: {}. Run `ritobin-tools download-hashes` to replace it",
+                path,
+                error
+            ));
+        }
+        tracing::warn!(
+            "Ignoring hashtable {}: {}. Names from it are written as hex, run `ritobin-tools download-hashes` to replace it or pass --strict-hashtable to fail instead",
+            path,
+            error
+        );
+    }
+
+    Ok(provider)
+}
+
+/// Merges a single hashtable file holding every category into `provider`.
+///
+/// Each line is `hash name` with the hash in hex, like the regular hashtables. A combined
+/// file doesn't say which category a name belongs to, so every name is added to all four.
+/// Names already known from the category files win. Lines whose hash doesn't fit 32 bits,
+/// like WAD path hashes, are skipped. Returns the number of names read.
+pub fn merge_combined_hashtable(
+    provider: &mut HashMapProvider,
+    path: &Utf8Path,
+) -> io::Result<usize> {
+    let reader =
+        BufReader::with_capacity(io_buffer_size(DEFAULT_IO_BUFFER_SIZE), File::open(path)?);
+
+    let mut count = 0;
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((hash, name)) = line.split_once(' ') else {
+            continue;
+        };
+        let hash = hash.trim_start_matches("0x");
+        let Ok(hash) = u32::from_str_radix(hash, 16) else {
+            continue;
+        };
+
+        for table in [
+            &mut provider.entries,
+            &mut provider.fields,
+            &mut provider.hashes,
+            &mut provider.types,
+        ] {
+            table.entry(hash).or_insert_with(|| name.to_string());
+        }
+        count += 1;
+    }
+
+    Ok(count)
+}
+
+/// Warns about hashtables older than the [`UpdatePolicy`] allows, or downloads fresh ones
+/// when it auto-updates. A failed download keeps the old hashtables.
+fn update_if_stale(dir: &Utf8Path) {
+    static CHECKED: Once = Once::new();
+
+    let Some(policy) = UPDATE_POLICY.get() else {
+        return;
+    };
+    CHECKED.call_once(|| {
+        // The oldest file decides, missing files are reported by `warn_if_empty`
+        let Some(age) = HASHTABLE_FILES
+            .iter()
+            .filter_map(|file| modified_time(&dir.join(file)))
+            .filter_map(|mtime| mtime.elapsed().ok())
+            .max()
+        else {
+            return;
+        };
+        if age <= policy.max_age {
+            return;
+        }
+
+        let days = age.as_secs() / (24 * 60 * 60);
+        let Some(update) = policy.update else {
+            tracing::warn!(
+                "Hashtables in {} are {} days old. Run `ritobin-tools download-hashes` or pass --auto-update to refresh them",
+                dir,
+                days
+            );
+            return;
+        };
+
+        tracing::info!("Hashtables in {} are {} days old, updating them", dir, days);
+        if let Err(e) = update() {
+            tracing::warn!("Failed to update hashtables, using the old ones: {}", e);
+        }
+    });
+}
+
+/// Warns when no hashes were loaded, since every name would silently be written as hex
+pub fn warn_if_empty(provider: &HashMapProvider, dir: &Utf8Path) {
+    if provider.total_count() > 0 {
+        return;
+    }
+
+    if dir.is_dir() {
+        tracing::warn!(
+            "No hashtables found in {}, names will be written as hex. Run `ritobin-tools download-hashes` to fetch them",
+            dir
+        );
+    } else {
+        tracing::warn!(
+            "Hashtable directory {} does not exist, names will be written as hex. Run `ritobin-tools download-hashes` to fetch the hashtables",
+            dir
+        );
+    }
+}
+
+/// A hash provider shared across a run, loaded from the hashtable directory on first use.
+/// Runs that never need names (e.g. text -> bin) never pay for loading the tables.
+pub struct LazyHashProvider {
+    hashtable_dir: Option<Utf8PathBuf>,
+    combined_hashtable: Option<Utf8PathBuf>,
+    /// The error is kept as text, since a report can't be cloned for every caller
+    provider: OnceCell<Result<Option<HashMapProvider>, String>>,
+}
+
+impl LazyHashProvider {
+    pub fn new(
+        hashtable_dir: Option<Utf8PathBuf>,
+        combined_hashtable: Option<Utf8PathBuf>,
+    ) -> Self {
+        Self {
+            hashtable_dir,
+            combined_hashtable,
+            provider: OnceCell::new(),
+        }
+    }
+
+    /// The loaded hashtables for provenance comments: how many names they hold and the
+    /// date the newest file was modified, e.g. `123456 names, updated 2024-05-01`
+    pub fn describe(&self) -> Result<String> {
+        let Some(provider) = self.get()? else {
+            return Ok("none".to_string());
+        };
+        let updated = self.hashtable_dir.as_deref().and_then(|dir| {
+            HASHTABLE_FILES
+                .iter()
+                .map(|file| dir.join(file))
+                .chain(self.combined_hashtable.clone())
+                .filter_map(|path| modified_time(&path))
+                .max()
+        });
+        Ok(match updated {
+            Some(updated) => format!(
+                "{} names, updated {}",
+                provider.total_count(),
+                OffsetDateTime::from(updated).date()
+            ),
+            None => format!("{} names", provider.total_count()),
+        })
+    }
+
+    /// Returns the loaded provider, or `None` if no hashtable directory is configured.
+    /// Fails every time if loading failed, see [`load_hash_provider`].
+    pub fn get(&self) -> Result<Option<&HashMapProvider>> {
+        self.provider
+            .get_or_init(|| {
+                let Some(dir) = self.hashtable_dir.as_deref() else {
+                    return Ok(None);
+                };
+                load_hash_provider(dir, self.combined_hashtable.as_deref())
+                    .map(Some)
+                    .map_err(|e| e.to_string())
+            })
+            .as_ref()
+            .map(Option::as_ref)
+            .map_err(|e| miette::miette!("{}", e))
+    }
+}
+
+/// Reads the text hashtables in `dir` and writes them to the binary cache.
+/// Returns the cache path and the number of cached hashes.
+pub fn build_hash_cache(dir: &Utf8Path) -> Result<(Utf8PathBuf, usize)> {
+    let provider = load_text_hashtables(dir)?;
+
+    let cache_path = dir.join(HASH_CACHE_FILE);
+    write_hash_cache(&cache_path, &provider)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write hashtable cache: {}", cache_path))?;
+
+    Ok((cache_path, provider.total_count()))
+}
+
+/// The cache is fresh when it exists and no text hashtable was modified after it
+fn is_cache_fresh(dir: &Utf8Path, cache_path: &Utf8Path) -> bool {
+    let Some(cache_mtime) = modified_time(cache_path) else {
+        return false;
+    };
+
+    HASHTABLE_FILES
+        .iter()
+        .filter_map(|file| modified_time(&dir.join(file)))
+        .all(|mtime| mtime <= cache_mtime)
+}
+
+fn modified_time(path: &Utf8Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Cache layout (little endian):
+/// magic, version, then for entries/fields/hashes/types: count followed by
+/// `(hash: u32, len: u32, name: [u8; len])` records sorted by hash.
+fn write_hash_cache(path: &Utf8Path, provider: &HashMapProvider) -> io::Result<()> {
+    let mut writer =
+        BufWriter::with_capacity(io_buffer_size(DEFAULT_IO_BUFFER_SIZE), File::create(path)?);
+
+    writer.write_all(HASH_CACHE_MAGIC)?;
+    writer.write_all(&HASH_CACHE_VERSION.to_le_bytes())?;
+
+    for table in [
+        &provider.entries,
+        &provider.fields,
+        &provider.hashes,
+        &provider.types,
+    ] {
+        let mut sorted: Vec<_> = table.iter().collect();
+        sorted.sort_unstable_by_key(|(hash, _)| **hash);
+
+        writer.write_all(&(sorted.len() as u32).to_le_bytes())?;
+        for (hash, name) in sorted {
+            writer.write_all(&hash.to_le_bytes())?;
+            writer.write_all(&(name.len() as u32).to_le_bytes())?;
+            writer.write_all(name.as_bytes())?;
+        }
+    }
+
+    writer.flush()
+}
+
+fn read_hash_cache(path: &Utf8Path) -> io::Result<HashMapProvider> {
+    let mut reader =
+        BufReader::with_capacity(io_buffer_size(DEFAULT_IO_BUFFER_SIZE), File::open(path)?);
+
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != HASH_CACHE_MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid cache signature",
+        ));
+    }
+
+    let version = read_u32(&mut reader)?;
+    if version != HASH_CACHE_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported cache version {}", version),
+        ));
+    }
+
+    Ok(HashMapProvider {
+        entries: read_table(&mut reader)?,
+        fields: read_table(&mut reader)?,
+        hashes: read_table(&mut reader)?,
+        types: read_table(&mut reader)?,
+    })
+}
+
+/// Counts and lengths come from the file, so nothing is preallocated from them: a corrupt
+/// cache fails with an error instead of asking for gigabytes of memory
+fn read_table(reader: &mut impl Read) -> io::Result<HashMap<u32, String>> {
+    let count = read_u32(reader)?;
+    let mut table = HashMap::new();
+
+    for _ in 0..count {
+        let hash = read_u32(reader)?;
+        let len = read_u32(reader)?;
+        let mut name = Vec::new();
+        reader.by_ref().take(len as u64).read_to_end(&mut name)?;
+        if name.len() != len as usize {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let name =
+            String::from_utf8(name).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        table.insert(hash, name);
+    }
+
+    Ok(table)
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
@@ -0,0 +1,119 @@
+//! Following the bins a .bin links to, for `convert --resolve-links`.
+//!
+//! Links are the game paths in a bin's `linked` list, e.g. `DATA/Characters/Ahri/Ahri.bin`.
+//! They are looked up under a root directory holding an extracted game or mod, matching
+//! each path component case-insensitively since the paths in bins and on disk rarely agree
+//! on case.
+
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::fs;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use ltk_meta::BinTree;
+use ltk_ritobin::HashProvider;
+
+use crate::utils::{bin_io, format_hash};
+
+/// A summary of every bin `tree` links to, directly or through other linked bins, as
+/// ritobin comment lines: where each link was found under `root` and the entries it
+/// defines, named with `names`. Links that can't be found or read are listed as such and
+/// warned about, `source` is the file they are reported for.
+pub fn linked_bins_summary(
+    tree: &BinTree,
+    root: &Utf8Path,
+    names: &impl HashProvider,
+    source: &str,
+) -> String {
+    let mut summary = format!("\n# Linked bins, resolved under {}\n", root);
+    if tree.dependencies.is_empty() {
+        summary.push_str("# (none)\n");
+        return summary;
+    }
+
+    let mut visited = HashSet::new();
+    // Depth first so every link is listed under the bin that links it
+    let mut stack: Vec<(String, usize)> = tree
+        .dependencies
+        .iter()
+        .rev()
+        .map(|link| (link.clone(), 1))
+        .collect();
+    while let Some((link, depth)) = stack.pop() {
+        let indent = "  ".repeat(depth - 1);
+        if !visited.insert(link.to_lowercase()) {
+            let _ = writeln!(summary, "# {}{} (listed above)", indent, link);
+            continue;
+        }
+
+        let Some(path) = find_linked_bin(root, &link) else {
+            tracing::warn!("{}: linked bin {} not found under {}", source, link, root);
+            let _ = writeln!(summary, "# {}{} (not found)", indent, link);
+            continue;
+        };
+        let linked = match bin_io::read_bin_file(&path) {
+            Ok(linked) => linked,
+            Err(e) => {
+                tracing::warn!("{}: linked bin {} can't be read: {:?}", source, path, e);
+                let _ = writeln!(summary, "# {}{} -> {} (can't be read)", indent, link, path);
+                continue;
+            }
+        };
+
+        let _ = writeln!(
+            summary,
+            "# {}{} -> {} ({} entries)",
+            indent,
+            link,
+            path,
+            linked.objects.len()
+        );
+        for object in linked.objects.values() {
+            let name = names
+                .lookup_entry(object.path_hash)
+                .map(str::to_string)
+                .unwrap_or_else(|| format_hash(object.path_hash));
+            let class = names
+                .lookup_type(object.class_hash)
+                .map(str::to_string)
+                .unwrap_or_else(|| format_hash(object.class_hash));
+            let _ = writeln!(summary, "# {}  {}: {}", indent, name, class);
+        }
+        stack.extend(
+            linked
+                .dependencies
+                .iter()
+                .rev()
+                .map(|link| (link.clone(), depth + 1)),
+        );
+    }
+
+    summary
+}
+
+/// The file `link` names under `root`, matching every component case-insensitively.
+/// Links stepping out of `root` with `..` are never found.
+fn find_linked_bin(root: &Utf8Path, link: &str) -> Option<Utf8PathBuf> {
+    let mut path = root.to_path_buf();
+    for component in link
+        .split(['/', '\\'])
+        .filter(|c| !c.is_empty() && *c != ".")
+    {
+        if component == ".." {
+            return None;
+        }
+        let exact = path.join(component);
+        if exact.exists() {
+            path = exact;
+            continue;
+        }
+        let entry = fs::read_dir(&path).ok()?.flatten().find(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| name.eq_ignore_ascii_case(component))
+        })?;
+        path = path.join(entry.file_name().to_str()?);
+    }
+    path.is_file().then_some(path)
+}
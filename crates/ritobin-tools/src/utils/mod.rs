@@ -1,69 +1,302 @@
+pub mod bin_io;
+pub mod bin_json;
 pub mod config;
+pub mod exit_code;
+pub mod hash_resolver;
+pub mod hashtable;
+pub mod linked_bins;
+pub mod permission;
+pub mod ritobin_text;
+pub mod schema;
+pub mod tree_diff;
 
-use camino::Utf8Path;
-use fancy_regex::Regex;
-use miette::Result;
+use std::fmt;
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-/// Format a path as a clickable hyperlink using OSC 8 escape sequences.
-/// Supported by modern terminals like Windows Terminal, iTerm2, VS Code terminal, etc.
-pub fn hyperlink_path(path: impl AsRef<Utf8Path>) -> String {
-    let path = path.as_ref();
-    let url = format!("file://{}", path.as_str().replace('\\', "/"));
-    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, path)
+use camino::{Utf8Path, Utf8PathBuf};
+use clap::ValueEnum;
+use dialoguer::Confirm;
+use dialoguer::theme::{ColorfulTheme, SimpleTheme, Theme};
+use miette::{IntoDiagnostic, Result, WrapErr};
+use serde::{Deserialize, Serialize};
+
+/// Whether styled output is enabled, as decided by the global `--color` option.
+/// Covers escape sequences that aren't colors, like hyperlinks.
+pub fn colors_enabled() -> bool {
+    colored::control::SHOULD_COLORIZE.should_colorize()
 }
 
-/// Creates a filter pattern from an optional regex string.
-/// Defaults to case-insensitive matching unless the user explicitly sets (?i) or (?-i).
-pub fn create_filter_pattern(pattern: Option<String>) -> Result<Option<Regex>> {
-    match pattern {
-        Some(mut p) => {
-            // Default to case-insensitive unless the user explicitly sets (?i) or (?-i)
-            let has_inline_flag = p.contains("(?i)") || p.contains("(?-i)");
-            if !has_inline_flag {
-                p = format!("(?i){p}");
-            }
-            Ok(Some(Regex::new(&p).map_err(|e| {
-                miette::miette!("Failed to create filter pattern: {}", e)
-            })?))
-        }
-        None => Ok(None),
-    }
+/// How paths are rendered in output
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStyle {
+    /// Hyperlinks when stdout is a terminal and colors are enabled
+    #[default]
+    Auto,
+    /// Always emit OSC 8 hyperlinks
+    Osc8,
+    /// Never emit hyperlinks
+    Plain,
 }
 
-pub fn format_chunk_path_hash(path_hash: u64) -> String {
-    format!("{:016x}", path_hash)
+impl fmt::Display for LinkStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LinkStyle::Auto => "auto",
+            LinkStyle::Osc8 => "osc8",
+            LinkStyle::Plain => "plain",
+        })
+    }
 }
 
-pub fn is_hex_chunk_path(path: &Utf8Path) -> bool {
-    let file_name = path.file_name().unwrap_or("");
-    file_name.len() == 16 && file_name.chars().all(|c| c.is_ascii_hexdigit())
+/// Extension of ritobin text outputs, the content is the same for both
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum TextExtension {
+    /// `.py`, for editors to highlight it as Python
+    #[default]
+    Py,
+    /// `.ritobin`
+    Ritobin,
 }
 
-/// Truncates a string in the middle
-pub fn truncate_middle(input: &str, max_len: usize) -> String {
-    if input.len() <= max_len {
-        return input.to_string();
+impl TextExtension {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TextExtension::Py => "py",
+            TextExtension::Ritobin => "ritobin",
+        }
     }
-    if max_len <= 3 {
-        return "...".to_string();
+}
+
+impl fmt::Display for TextExtension {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
-    let keep = max_len - 3;
-    let left = keep / 2;
-    let right = keep - left;
-    let mut left_iter = input.chars();
-    let mut left_str = String::with_capacity(left);
-    for _ in 0..left {
-        if let Some(c) = left_iter.next() {
-            left_str.push(c);
+}
+
+static LINK_STYLE: OnceLock<LinkStyle> = OnceLock::new();
+
+/// Capacity of std's `BufReader`/`BufWriter`, used where no buffer size is configured
+pub const DEFAULT_IO_BUFFER_SIZE: usize = 8 * 1024;
+
+static IO_BUFFER_SIZE: OnceLock<usize> = OnceLock::new();
+
+/// Set the buffer size used for file and network I/O for the rest of the run.
+/// Only the first call has an effect.
+pub fn set_io_buffer_size(size: usize) {
+    let _ = IO_BUFFER_SIZE.set(size);
+}
+
+/// Buffer size for file and network I/O: the configured size, or `default` if none is set
+pub fn io_buffer_size(default: usize) -> usize {
+    IO_BUFFER_SIZE.get().copied().unwrap_or(default)
+}
+
+/// Parse a byte size like `65536`, `64K`, `64KiB` or `1M`. Suffixes are powers of 1024.
+pub fn parse_byte_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kb" | "kib" => 1024,
+        "m" | "mb" | "mib" => 1024 * 1024,
+        _ => {
+            return Err(format!(
+                "unknown size unit '{}', expected K or M",
+                unit.trim()
+            ));
         }
+    };
+    let size = digits
+        .parse::<usize>()
+        .map_err(|_| format!("invalid size '{}'", value))?
+        .checked_mul(multiplier)
+        .ok_or_else(|| format!("size '{}' is too large", value))?;
+
+    if size == 0 {
+        return Err("size must be greater than zero".to_string());
     }
-    let mut right_iter = input.chars().rev();
-    let mut right_str = String::with_capacity(right);
-    for _ in 0..right {
-        if let Some(c) = right_iter.next() {
-            right_str.push(c);
+    Ok(size)
+}
+
+static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+/// Catch Ctrl-C so batch runs can stop between files instead of mid-write.
+/// The first Ctrl-C only requests a stop, see [`stop_requested`]; a second one exits immediately.
+pub fn install_interrupt_handler() {
+    let result = ctrlc::set_handler(|| {
+        if STOP_REQUESTED.swap(true, Ordering::SeqCst) {
+            std::process::exit(130);
         }
+        eprintln!("Stopping after the current file, press Ctrl-C again to abort");
+    });
+    if let Err(e) = result {
+        tracing::debug!("Failed to install Ctrl-C handler: {}", e);
+    }
+}
+
+/// Whether Ctrl-C was pressed since [`install_interrupt_handler`]
+pub fn stop_requested() -> bool {
+    STOP_REQUESTED.load(Ordering::SeqCst)
+}
+
+/// `--files-from` value that reads the file list from stdin
+pub const STDIN_FILE_LIST: &str = "-";
+
+/// The paths listed one per line in `source`, or on stdin for [`STDIN_FILE_LIST`], e.g.
+/// the output of `git diff --name-only`. Blank lines are skipped, and so are listed paths
+/// that don't exist (like files the diff deleted), with a warning.
+pub fn read_file_list(source: &str) -> Result<Vec<Utf8PathBuf>> {
+    let content = if source == STDIN_FILE_LIST {
+        io::read_to_string(io::stdin())
+            .into_diagnostic()
+            .wrap_err("Failed to read the file list from stdin")?
+    } else {
+        fs::read_to_string(source)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to read file list: {}", source))?
+    };
+
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(Utf8PathBuf::from)
+        .filter(|path| {
+            let exists = path.exists();
+            if !exists {
+                tracing::warn!("Skipping listed file {}: it doesn't exist", path);
+            }
+            exists
+        })
+        .collect())
+}
+
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Log the one-line-per-file messages of batch commands at DEBUG instead of INFO,
+/// keeping summaries, warnings and errors
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+/// Log a per-file progress message, at INFO unless [`set_quiet`] lowered it to DEBUG
+pub fn log_file_progress(message: fmt::Arguments) {
+    if QUIET.load(Ordering::Relaxed) {
+        tracing::debug!("{}", message);
+    } else {
+        tracing::info!("{}", message);
+    }
+}
+
+static ASSUME_YES: AtomicBool = AtomicBool::new(false);
+
+/// Answer yes to every [`confirm`] question without asking, for scripts
+pub fn set_assume_yes(assume_yes: bool) {
+    ASSUME_YES.store(assume_yes, Ordering::Relaxed);
+}
+
+/// Theme for interactive prompts, without colors when they are disabled
+pub fn prompt_theme() -> Box<dyn Theme> {
+    if colors_enabled() {
+        Box::new(ColorfulTheme::default())
+    } else {
+        Box::new(SimpleTheme)
+    }
+}
+
+/// Ask before a destructive operation, defaulting to no. With `--yes`, or without a
+/// terminal on stdin, nothing is asked and the answer is yes, so scripts keep working.
+pub fn confirm(prompt: &str) -> Result<bool> {
+    if ASSUME_YES.load(Ordering::Relaxed) || !io::stdin().is_terminal() {
+        return Ok(true);
+    }
+    Confirm::with_theme(prompt_theme().as_ref())
+        .with_prompt(prompt)
+        .default(false)
+        .interact()
+        .into_diagnostic()
+}
+
+/// Set the link style used by [`hyperlink_path`] for the rest of the run.
+/// Only the first call has an effect.
+pub fn set_link_style(style: LinkStyle) {
+    let _ = LINK_STYLE.set(style);
+}
+
+/// Whether [`hyperlink_path`] emits hyperlinks, as decided by the link style
+pub fn links_enabled() -> bool {
+    match LINK_STYLE.get().copied().unwrap_or_default() {
+        LinkStyle::Auto => colors_enabled() && io::stdout().is_terminal(),
+        LinkStyle::Osc8 => true,
+        LinkStyle::Plain => false,
+    }
+}
+
+/// Format a path as a clickable hyperlink using OSC 8 escape sequences.
+/// Supported by modern terminals like Windows Terminal, iTerm2, VS Code terminal, etc.
+/// Returns the plain path when links are disabled, see [`LinkStyle`].
+pub fn hyperlink_path(path: impl AsRef<Utf8Path>) -> String {
+    let path = path.as_ref();
+    if !links_enabled() {
+        return path.to_string();
+    }
+    // Link targets must be absolute, the displayed text stays as given
+    let target = std::path::absolute(path).unwrap_or_else(|_| path.into());
+    let normalized = target.to_string_lossy().replace('\\', "/");
+    // Unix paths already start with the slash of `file:///`, Windows drive paths don't
+    let url = if normalized.starts_with('/') {
+        format!("file://{}", normalized)
+    } else {
+        format!("file:///{}", normalized)
+    };
+    format!("\x1b]8;;{}\x1b\\{}\x1b]8;;\x1b\\", url, path)
+}
+
+/// Returns `path` relative to `base` when it lies inside it, otherwise `path` as given.
+/// Both are made absolute against the working directory first, so mixing relative
+/// and absolute inputs works.
+pub fn relative_path(base: &Utf8Path, path: &Utf8Path) -> Utf8PathBuf {
+    let absolute = |p: &Utf8Path| {
+        std::path::absolute(p)
+            .ok()
+            .and_then(|p| Utf8PathBuf::from_path_buf(p).ok())
+    };
+
+    match (absolute(base), absolute(path)) {
+        (Some(base), Some(absolute_path)) => match absolute_path.strip_prefix(&base) {
+            Ok(relative) if relative.as_str().is_empty() => Utf8PathBuf::from("."),
+            Ok(relative) => relative.to_path_buf(),
+            Err(_) => path.to_path_buf(),
+        },
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Render a hash without a known name: `0x`, then lowercase hex zero-padded to the
+/// hash's full width (8 digits for `u32`, 16 for `u64`), so equal hashes always look
+/// the same in logs, diffs and output
+pub fn format_hash<T: fmt::LowerHex>(hash: T) -> String {
+    format!("{:#0width$x}", hash, width = 2 + 2 * size_of::<T>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_hash_pads_to_full_width() {
+        assert_eq!(format_hash(0x2a5deb8fu32), "0x2a5deb8f");
+        assert_eq!(format_hash(0xabu32), "0x000000ab");
+        assert_eq!(format_hash(0u32), "0x00000000");
+        assert_eq!(format_hash(0x1234_5678_9abc_def0u64), "0x123456789abcdef0");
+        assert_eq!(format_hash(0xabu64), "0x00000000000000ab");
     }
-    right_str = right_str.chars().rev().collect();
-    format!("{}...{}", left_str, right_str)
 }
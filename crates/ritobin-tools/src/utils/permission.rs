@@ -0,0 +1,74 @@
+//! Files that can't be read or written for lack of permissions.
+//!
+//! These fail for reasons outside the files themselves, so they get an error of their own
+//! that says how to fix access, and that batches can count apart from broken files.
+
+use std::fmt;
+use std::io;
+
+use miette::{IntoDiagnostic, Result};
+
+/// Hint for outputs of `convert`
+pub const OUTPUT_DIR_HINT: &str = "the output directory is read-only; try --output-dir elsewhere or run with appropriate permissions";
+
+/// Hint for a single output of `convert` given with `-o`
+pub const OUTPUT_FILE_HINT: &str = "the output location is read-only; choose another --output path or run with appropriate permissions";
+
+/// Hint for downloaded hashtables
+pub const HASHTABLE_DIR_HINT: &str = "the hashtable directory is read-only; point it elsewhere with `config set hashtable_dir <dir>` or run with appropriate permissions";
+
+/// Hint for inputs
+const READ_HINT: &str = "check the file's permissions or run with appropriate permissions";
+
+/// Reading or writing a file was denied. The path is left to the context around it, so
+/// batches group every denied file under the same cause.
+#[derive(Debug)]
+pub struct PermissionDenied {
+    /// What to do about it, e.g. [`OUTPUT_DIR_HINT`]
+    pub hint: &'static str,
+}
+
+impl fmt::Display for PermissionDenied {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "permission denied, {}", self.hint)
+    }
+}
+
+impl std::error::Error for PermissionDenied {}
+
+impl miette::Diagnostic for PermissionDenied {}
+
+/// Whether `report` failed on a [`PermissionDenied`], anywhere in its chain
+pub fn is_permission_denied(report: &miette::Report) -> bool {
+    report.downcast_ref::<PermissionDenied>().is_some()
+        || report
+            .chain()
+            .any(|e| e.downcast_ref::<PermissionDenied>().is_some())
+}
+
+/// `into_diagnostic` for file I/O, turning denied permissions into [`PermissionDenied`]
+pub trait IntoAccessDiagnostic<T> {
+    /// For reading an input
+    fn read_access(self) -> Result<T>;
+    /// For writing, with `hint` saying where else the file could go
+    fn write_access(self, hint: &'static str) -> Result<T>;
+}
+
+impl<T> IntoAccessDiagnostic<T> for io::Result<T> {
+    fn read_access(self) -> Result<T> {
+        denied(self, READ_HINT)
+    }
+
+    fn write_access(self, hint: &'static str) -> Result<T> {
+        denied(self, hint)
+    }
+}
+
+fn denied<T>(result: io::Result<T>, hint: &'static str) -> Result<T> {
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            Err(PermissionDenied { hint }.into())
+        }
+        result => result.into_diagnostic(),
+    }
+}
@@ -0,0 +1,320 @@
+//! Lightweight scanning of ritobin text for checks the parser doesn't surface.
+//!
+//! `ltk_ritobin` parses straight into maps, so information like duplicate keys is lost
+//! by the time a `BinTree` exists. The tokenizer here works on the raw text instead.
+
+use std::collections::HashMap;
+use std::ops::Range;
+
+use ltk_hash::fnv1a::hash_lower;
+use ltk_ritobin::HashMapProvider;
+
+/// A token of ritobin text
+#[derive(Debug, Clone, PartialEq)]
+pub enum TokenKind {
+    /// Bare word: identifiers, numbers, hex hashes, type names
+    Word(String),
+    /// Quoted string with escapes resolved
+    Str(String),
+    /// One of `{ } [ ] : = ,`
+    Punct(char),
+}
+
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// 1-based line the token starts on
+    pub line: usize,
+    /// Byte offset of the token's first character
+    pub offset: usize,
+}
+
+/// Splits ritobin text into tokens, skipping whitespace and `#` comments.
+pub fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    let mut line = 1;
+
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '#' => while chars.next_if(|&(_, c)| c != '\n').is_some() {},
+            '"' => {
+                let start_line = line;
+                let mut value = String::new();
+                while let Some((_, c)) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next().map(|(_, c)| c) {
+                            Some('n') => value.push('\n'),
+                            Some('r') => value.push('\r'),
+                            Some('t') => value.push('\t'),
+                            Some(other) => value.push(other),
+                            None => break,
+                        },
+                        '\n' => {
+                            line += 1;
+                            value.push(c);
+                        }
+                        _ => value.push(c),
+                    }
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Str(value),
+                    line: start_line,
+                    offset,
+                });
+            }
+            '{' | '}' | '[' | ']' | ':' | '=' | ',' => tokens.push(Token {
+                kind: TokenKind::Punct(c),
+                line,
+                offset,
+            }),
+            _ => {
+                let mut word = String::from(c);
+                while let Some((_, c)) = chars.next_if(|&(_, c)| !is_word_boundary(c)) {
+                    word.push(c);
+                }
+                tokens.push(Token {
+                    kind: TokenKind::Word(word),
+                    line,
+                    offset,
+                });
+            }
+        }
+    }
+
+    tokens
+}
+
+fn is_word_boundary(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '{' | '}' | '[' | ']' | ':' | '=' | ',' | '"' | '#')
+}
+
+/// A key defined more than once within the same block
+#[derive(Debug, Clone)]
+pub struct DuplicateKey {
+    /// The key as written at the duplicate definition
+    pub key: String,
+    /// Hash both definitions resolve to
+    pub hash: u32,
+    pub line: usize,
+    pub first_line: usize,
+}
+
+/// Finds keys that are defined more than once at the same nesting level.
+///
+/// Field names (`name: type = ...`) and map keys (`key = ...`) are compared by the hash the
+/// parser would assign them, so `mName`, `mname` and its hex hash all count as the same key.
+pub fn find_duplicate_keys(text: &str) -> Vec<DuplicateKey> {
+    let tokens = tokenize(text);
+    let mut scopes: Vec<HashMap<u32, usize>> = vec![HashMap::new()];
+    let mut duplicates = Vec::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        match &token.kind {
+            TokenKind::Punct('{') => scopes.push(HashMap::new()),
+            // Keep the root scope even on unbalanced input, the parser reports that
+            TokenKind::Punct('}') if scopes.len() > 1 => {
+                scopes.pop();
+            }
+            TokenKind::Word(key) | TokenKind::Str(key) => {
+                let next = tokens.get(i + 1).map(|t| &t.kind);
+                let prev = i.checked_sub(1).map(|p| &tokens[p].kind);
+
+                // `name:` is a field, `key =` is a map entry unless it's the type in `name: type =`
+                let is_key = match next {
+                    Some(TokenKind::Punct(':')) => true,
+                    Some(TokenKind::Punct('=')) => prev != Some(&TokenKind::Punct(':')),
+                    _ => false,
+                };
+                if !is_key {
+                    continue;
+                }
+
+                let hash = key_hash(key);
+                let scope = scopes.last_mut().expect("root scope is never popped");
+                if let Some(&first_line) = scope.get(&hash) {
+                    duplicates.push(DuplicateKey {
+                        key: key.clone(),
+                        hash,
+                        line: token.line,
+                        first_line,
+                    });
+                } else {
+                    scope.insert(hash, token.line);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    duplicates
+}
+
+/// Hash a key the way the parser does: hex literals as-is, names via lowercase FNV-1a
+pub fn key_hash(key: &str) -> u32 {
+    key.strip_prefix("0x")
+        .or_else(|| key.strip_prefix("0X"))
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .unwrap_or_else(|| hash_lower(key))
+}
+
+/// Returns the 1-based lines holding `#` comments, excluding the `#PROP_text` header.
+/// The parser discards comments, so anything reported here is lost on conversion.
+pub fn comment_lines(text: &str) -> Vec<usize> {
+    let mut lines = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, line) in text.lines().enumerate() {
+        for c in line.chars() {
+            if in_string {
+                match c {
+                    _ if escaped => escaped = false,
+                    '\\' => escaped = true,
+                    '"' => in_string = false,
+                    _ => {}
+                }
+            } else if c == '"' {
+                in_string = true;
+            } else if c == '#' {
+                let is_header = index == 0 && line.trim_start().starts_with("#PROP_text");
+                if !is_header {
+                    lines.push(index + 1);
+                }
+                break;
+            }
+        }
+    }
+
+    lines
+}
+
+/// A word or string of ritobin text that isn't a hex literal, so the parser hashes it
+/// wherever it stands for a hash
+#[derive(Debug, Clone)]
+pub struct WrittenName {
+    pub name: String,
+    /// Hash the parser would give the name
+    pub hash: u32,
+    pub line: usize,
+}
+
+/// Every word and string in the text that could be a name, in order
+pub fn written_names(text: &str) -> Vec<WrittenName> {
+    tokenize(text)
+        .into_iter()
+        .filter_map(|token| {
+            let (TokenKind::Word(name) | TokenKind::Str(name)) = token.kind else {
+                return None;
+            };
+            if name.starts_with("0x") || name.starts_with("0X") {
+                return None;
+            }
+            Some(WrittenName {
+                hash: hash_lower(&name),
+                name,
+                line: token.line,
+            })
+        })
+        .collect()
+}
+
+/// Build a provider from every word and string in the text, so names written by the
+/// author are written back the same way instead of as hex after parsing
+pub fn names_from_text(text: &str) -> HashMapProvider {
+    let mut provider = HashMapProvider::new();
+
+    for WrittenName { name, hash, .. } in written_names(text) {
+        for table in [
+            &mut provider.entries,
+            &mut provider.fields,
+            &mut provider.hashes,
+            &mut provider.types,
+        ] {
+            table.entry(hash).or_insert_with(|| name.clone());
+        }
+    }
+
+    provider
+}
+
+/// Ritobin text cut into the top-level `entries` map and everything around it
+#[derive(Debug, Clone)]
+pub struct EntrySections {
+    /// Everything up to and including the `{` opening the entries map
+    pub head: Range<usize>,
+    /// Byte range of each `key = Type { ... }` entry
+    pub entries: Vec<Range<usize>>,
+    /// Everything after the `}` closing the entries map, empty if it is never closed
+    pub tail: Range<usize>,
+}
+
+/// Finds the entries of the top-level `entries` map by their braces, without parsing them.
+/// An entry with unbalanced braces swallows the entries after it up to where they balance
+/// out again. Returns `None` if the text has no `entries` map.
+pub fn split_entries(text: &str) -> Option<EntrySections> {
+    let tokens = tokenize(text);
+    let mut depth = 0usize;
+    let mut key = None;
+    let mut open = None;
+    for (i, token) in tokens.iter().enumerate() {
+        match &token.kind {
+            TokenKind::Word(word)
+                if depth == 0
+                    && tokens.get(i + 1).map(|t| &t.kind) == Some(&TokenKind::Punct(':')) =>
+            {
+                key = Some(word.as_str());
+            }
+            TokenKind::Punct('{') if depth == 0 && key == Some("entries") => {
+                open = Some(i);
+                break;
+            }
+            TokenKind::Punct('{') => depth += 1,
+            TokenKind::Punct('}') => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+    let open = open?;
+
+    let head = 0..tokens[open].offset + 1;
+    let mut entries = Vec::new();
+    let mut start = None;
+    let mut depth = 1;
+    for token in &tokens[open + 1..] {
+        match token.kind {
+            TokenKind::Punct('}') if depth == 1 => {
+                if let Some(start) = start {
+                    entries.push(start..token.offset);
+                }
+                return Some(EntrySections {
+                    head,
+                    entries,
+                    tail: token.offset + 1..text.len(),
+                });
+            }
+            TokenKind::Punct('{') => depth += 1,
+            TokenKind::Punct('}') => {
+                depth -= 1;
+                if depth == 1
+                    && let Some(entry_start) = start.take()
+                {
+                    entries.push(entry_start..token.offset + 1);
+                }
+            }
+            _ if depth == 1 && start.is_none() => start = Some(token.offset),
+            _ => {}
+        }
+    }
+
+    if let Some(start) = start {
+        entries.push(start..text.len());
+    }
+    Some(EntrySections {
+        head,
+        entries,
+        tail: text.len()..text.len(),
+    })
+}
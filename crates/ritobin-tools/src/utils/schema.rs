@@ -0,0 +1,331 @@
+//! Checking bin trees against a schema given as a ritobin template.
+//!
+//! Every struct in the template (entries, pointers and embeds, at any depth) declares a
+//! class and the fields it may have, with the field values fixing their types. Classes
+//! that appear more than once contribute the union of their fields. A checked tree may
+//! only use classes from the template, and only their declared fields with those types.
+
+use std::collections::HashMap;
+
+use camino::Utf8Path;
+use indexmap::IndexMap;
+use ltk_meta::value::{EmbeddedValue, StructValue, UnorderedContainerValue};
+use ltk_meta::{BinProperty, BinTree, PropertyValueEnum};
+use ltk_ritobin::{HashMapProvider, HashProvider, kind_to_type_name};
+use miette::{Result, WrapErr};
+
+use crate::utils::ritobin_text::names_from_text;
+use crate::utils::tree_diff::ValueFormatter;
+use crate::utils::{bin_io, format_hash};
+
+/// Allowed classes and the type of each of their fields
+#[derive(Debug, Clone)]
+pub struct Schema {
+    classes: HashMap<u32, HashMap<u32, String>>,
+    /// Names written in the template, so messages can name schema classes and fields
+    pub names: HashMapProvider,
+}
+
+/// One place where a tree doesn't match the schema
+#[derive(Debug, Clone)]
+pub struct SchemaViolation {
+    /// Property path in `set` syntax, e.g. `Characters/Ahri.skinScale`
+    pub path: String,
+    pub message: String,
+}
+
+impl Schema {
+    /// Load a schema from a ritobin template file
+    pub fn load(path: &Utf8Path) -> Result<Self> {
+        let text = bin_io::read_text_file(path)?;
+        let schema =
+            Self::parse(&text).wrap_err_with(|| format!("Failed to load schema {}", path))?;
+        if schema.classes.is_empty() {
+            return Err(miette::miette!("Schema {} declares no types", path));
+        }
+        Ok(schema)
+    }
+
+    /// Read a schema from ritobin template text
+    fn parse(text: &str) -> Result<Self> {
+        let tree = bin_io::parse_text(text)?;
+
+        let mut schema = Schema {
+            classes: HashMap::new(),
+            names: names_from_text(text),
+        };
+        for object in tree.objects.values() {
+            schema.add_class(object.class_hash, &object.properties);
+        }
+        Ok(schema)
+    }
+
+    fn add_class(&mut self, class_hash: u32, properties: &IndexMap<u32, BinProperty>) {
+        for property in properties.values() {
+            self.classes
+                .entry(class_hash)
+                .or_default()
+                .insert(property.name_hash, type_name(&property.value));
+            self.add_nested(&property.value);
+        }
+        self.classes.entry(class_hash).or_default();
+    }
+
+    fn add_nested(&mut self, value: &PropertyValueEnum) {
+        match value {
+            PropertyValueEnum::Struct(value)
+            | PropertyValueEnum::Embedded(EmbeddedValue(value))
+                if value.class_hash != 0 =>
+            {
+                self.add_class(value.class_hash, &value.properties)
+            }
+            PropertyValueEnum::Container(container)
+            | PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)) => {
+                container
+                    .items
+                    .iter()
+                    .for_each(|item| self.add_nested(item))
+            }
+            PropertyValueEnum::Map(map) => map.entries.values().for_each(|v| self.add_nested(v)),
+            PropertyValueEnum::Optional(optional) => {
+                if let Some(value) = optional.value.as_deref() {
+                    self.add_nested(value)
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Check every entry of `tree`, naming paths and types with `names`
+    pub fn check(&self, tree: &BinTree, names: &impl HashProvider) -> Vec<SchemaViolation> {
+        let mut checker = SchemaChecker {
+            schema: self,
+            names,
+            violations: Vec::new(),
+        };
+        for object in tree.objects.values() {
+            let path = ValueFormatter::new(names).entry_path(object.path_hash);
+            checker.check_struct(&path, object.class_hash, &object.properties);
+        }
+        checker.violations
+    }
+}
+
+struct SchemaChecker<'a, H> {
+    schema: &'a Schema,
+    names: &'a H,
+    violations: Vec<SchemaViolation>,
+}
+
+impl<H: HashProvider> SchemaChecker<'_, H> {
+    fn violation(&mut self, path: &str, message: String) {
+        self.violations.push(SchemaViolation {
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    fn type_name(&self, hash: u32) -> String {
+        self.names
+            .lookup_type(hash)
+            .or_else(|| self.schema.names.lookup_type(hash))
+            .map(str::to_string)
+            .unwrap_or_else(|| format_hash(hash))
+    }
+
+    fn field_name(&self, hash: u32) -> String {
+        self.names
+            .lookup_field(hash)
+            .or_else(|| self.schema.names.lookup_field(hash))
+            .map(str::to_string)
+            .unwrap_or_else(|| format_hash(hash))
+    }
+
+    fn check_struct(
+        &mut self,
+        path: &str,
+        class_hash: u32,
+        properties: &IndexMap<u32, BinProperty>,
+    ) {
+        let Some(fields) = self.schema.classes.get(&class_hash) else {
+            let message = format!("type {} is not in the schema", self.type_name(class_hash));
+            self.violation(path, message);
+            return;
+        };
+
+        for property in properties.values() {
+            let field_path = format!("{}.{}", path, self.field_name(property.name_hash));
+            let actual = type_name(&property.value);
+            match fields.get(&property.name_hash) {
+                None => {
+                    let message = format!("unknown field of {}", self.type_name(class_hash));
+                    self.violation(&field_path, message);
+                }
+                Some(expected) if *expected != actual => {
+                    let message = format!("expected {}, found {}", expected, actual);
+                    self.violation(&field_path, message);
+                }
+                Some(_) => self.check_value(&field_path, &property.value),
+            }
+        }
+    }
+
+    fn check_value(&mut self, path: &str, value: &PropertyValueEnum) {
+        match value {
+            PropertyValueEnum::Struct(value)
+            | PropertyValueEnum::Embedded(EmbeddedValue(value)) => {
+                self.check_struct_value(path, value)
+            }
+            PropertyValueEnum::Container(container)
+            | PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)) => {
+                for (index, item) in container.items.iter().enumerate() {
+                    self.check_value(&format!("{}[{}]", path, index), item);
+                }
+            }
+            PropertyValueEnum::Map(map) => {
+                for (key, value) in &map.entries {
+                    let value_path = ValueFormatter::new(self.names).map_value_path(path, &key.0);
+                    self.check_value(&value_path, value);
+                }
+            }
+            PropertyValueEnum::Optional(optional) => {
+                if let Some(value) = optional.value.as_deref() {
+                    self.check_value(path, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn check_struct_value(&mut self, path: &str, value: &StructValue) {
+        // Null pointers have no class to check
+        if value.class_hash == 0 && value.properties.is_empty() {
+            return;
+        }
+        self.check_struct(path, value.class_hash, &value.properties);
+    }
+}
+
+/// The ritobin type of a value, including the item types of containers, maps and optionals
+pub(crate) fn type_name(value: &PropertyValueEnum) -> String {
+    let kind = kind_to_type_name(value.kind());
+    match value {
+        PropertyValueEnum::Container(container)
+        | PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)) => {
+            format!("{}[{}]", kind, kind_to_type_name(container.item_kind))
+        }
+        PropertyValueEnum::Map(map) => format!(
+            "{}[{},{}]",
+            kind,
+            kind_to_type_name(map.key_kind),
+            kind_to_type_name(map.value_kind)
+        ),
+        PropertyValueEnum::Optional(optional) => {
+            format!("{}[{}]", kind, kind_to_type_name(optional.kind))
+        }
+        _ => kind.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = r#"#PROP_text
+entries: map[hash,embed] = {
+    "Template" = SkinCharacterDataProperties {
+        skinScale: f32 = 1
+        mTags: map[hash,embed] = {
+            "tag" = TagData {
+                weight: u32 = 0
+            }
+        }
+    }
+}
+"#;
+
+    /// Violations of `text`, as (path, message) pairs
+    fn check(text: &str) -> Vec<(String, String)> {
+        let schema = Schema::parse(TEMPLATE).unwrap();
+        let tree = bin_io::parse_text(text).unwrap();
+        schema
+            .check(&tree, &names_from_text(text))
+            .into_iter()
+            .map(|violation| (violation.path, violation.message))
+            .collect()
+    }
+
+    fn entry(fields: &str) -> String {
+        format!(
+            "entries: map[hash,embed] = {{\n    \"Skin0\" = SkinCharacterDataProperties {{\n{}\n    }}\n}}\n",
+            fields
+        )
+    }
+
+    #[test]
+    fn matching_tree_has_no_violations() {
+        let text = entry(
+            r#"        skinScale: f32 = 1.2
+        mTags: map[hash,embed] = {
+            "hit" = TagData {
+                weight: u32 = 3
+            }
+        }"#,
+        );
+        assert_eq!(check(&text), []);
+    }
+
+    #[test]
+    fn field_missing_from_schema() {
+        let text = entry("        skinColor: u32 = 1");
+        assert_eq!(
+            check(&text),
+            [(
+                "Skin0.skinColor".to_string(),
+                "unknown field of SkinCharacterDataProperties".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn wrong_type() {
+        let text = entry(r#"        skinScale: string = "big""#);
+        assert_eq!(
+            check(&text),
+            [(
+                "Skin0.skinScale".to_string(),
+                "expected f32, found string".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn wrong_map_key_type() {
+        let text = entry("        mTags: map[string,embed] = {}");
+        assert_eq!(
+            check(&text),
+            [(
+                "Skin0.mTags".to_string(),
+                "expected map[hash,embed], found map[string,embed]".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn map_values_are_reported_by_key() {
+        let text = entry(
+            r#"        mTags: map[hash,embed] = {
+            "hit" = TagData {
+                weight: f32 = 3
+            }
+        }"#,
+        );
+        assert_eq!(
+            check(&text),
+            [(
+                "Skin0.mTags[\"hit\"].weight".to_string(),
+                "expected u32, found f32".to_string()
+            )]
+        );
+    }
+}
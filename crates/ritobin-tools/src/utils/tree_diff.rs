@@ -0,0 +1,300 @@
+//! Structural comparison of bin trees.
+//!
+//! Unlike the text diff, this compares parsed values, so two files that only differ in
+//! formatting (float spelling, hex vs. names, key order) compare equal.
+
+use indexmap::IndexMap;
+use ltk_meta::value::{
+    ContainerValue, EmbeddedValue, MapValue, StructValue, UnorderedContainerValue,
+};
+use ltk_meta::{BinProperty, BinTree, BinTreeObject, PropertyValueEnum};
+use ltk_ritobin::{HashProvider, kind_to_type_name};
+
+use crate::utils::format_hash;
+
+/// One difference between two trees. `old` is `None` for additions, `new` for removals.
+#[derive(Debug, Clone)]
+pub struct TreeChange {
+    /// Property path in `set` syntax, e.g. `Characters/Ahri/Skins/Skin0.skinScale`
+    pub path: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// Compare two trees entry by entry and field by field.
+/// Names in paths and values are resolved with `names`, falling back to hex.
+pub fn diff_trees(old: &BinTree, new: &BinTree, names: &impl HashProvider) -> Vec<TreeChange> {
+    let mut differ = TreeDiffer {
+        format: ValueFormatter::new(names),
+        changes: Vec::new(),
+    };
+
+    for (hash, old_object) in &old.objects {
+        let path = differ.format.entry_path(*hash);
+        match new.objects.get(hash) {
+            Some(new_object) => differ.compare_objects(&path, old_object, new_object),
+            None => differ.push(path, Some(differ.format.describe_object(old_object)), None),
+        }
+    }
+    for (hash, new_object) in &new.objects {
+        if !old.objects.contains_key(hash) {
+            let path = differ.format.entry_path(*hash);
+            differ.push(path, None, Some(differ.format.describe_object(new_object)));
+        }
+    }
+
+    differ.changes
+}
+
+struct TreeDiffer<'a, H> {
+    format: ValueFormatter<'a, H>,
+    changes: Vec<TreeChange>,
+}
+
+impl<H: HashProvider> TreeDiffer<'_, H> {
+    fn push(&mut self, path: String, old: Option<String>, new: Option<String>) {
+        self.changes.push(TreeChange { path, old, new });
+    }
+
+    fn compare_objects(&mut self, path: &str, old: &BinTreeObject, new: &BinTreeObject) {
+        if old.class_hash != new.class_hash {
+            self.push(
+                path.to_string(),
+                Some(self.format.describe_object(old)),
+                Some(self.format.describe_object(new)),
+            );
+            return;
+        }
+        self.compare_properties(path, &old.properties, &new.properties);
+    }
+
+    fn compare_properties(
+        &mut self,
+        path: &str,
+        old: &IndexMap<u32, BinProperty>,
+        new: &IndexMap<u32, BinProperty>,
+    ) {
+        for (hash, old_property) in old {
+            let field_path = format!("{}.{}", path, self.format.field_name(*hash));
+            match new.get(hash) {
+                Some(new_property) => {
+                    self.compare_values(&field_path, &old_property.value, &new_property.value)
+                }
+                None => self.push(
+                    field_path,
+                    Some(self.format.format_value(&old_property.value)),
+                    None,
+                ),
+            }
+        }
+        for (hash, new_property) in new {
+            if !old.contains_key(hash) {
+                let field_path = format!("{}.{}", path, self.format.field_name(*hash));
+                self.push(
+                    field_path,
+                    None,
+                    Some(self.format.format_value(&new_property.value)),
+                );
+            }
+        }
+    }
+
+    fn compare_values(&mut self, path: &str, old: &PropertyValueEnum, new: &PropertyValueEnum) {
+        if old == new {
+            return;
+        }
+
+        match (old, new) {
+            (PropertyValueEnum::Struct(old), PropertyValueEnum::Struct(new))
+            | (
+                PropertyValueEnum::Embedded(EmbeddedValue(old)),
+                PropertyValueEnum::Embedded(EmbeddedValue(new)),
+            ) if old.class_hash == new.class_hash => {
+                self.compare_properties(path, &old.properties, &new.properties)
+            }
+            (PropertyValueEnum::Container(old), PropertyValueEnum::Container(new))
+            | (
+                PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(old)),
+                PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(new)),
+            ) if old.item_kind == new.item_kind => self.compare_items(path, old, new),
+            (PropertyValueEnum::Map(old), PropertyValueEnum::Map(new))
+                if old.key_kind == new.key_kind && old.value_kind == new.value_kind =>
+            {
+                self.compare_entries(path, old, new)
+            }
+            (PropertyValueEnum::Optional(old), PropertyValueEnum::Optional(new))
+                if old.kind == new.kind =>
+            {
+                match (old.value.as_deref(), new.value.as_deref()) {
+                    (Some(old), Some(new)) => self.compare_values(path, old, new),
+                    // Emptying an optional changes its value, the field itself stays
+                    _ => self.push(
+                        path.to_string(),
+                        Some(self.format.format_optional(old.value.as_deref())),
+                        Some(self.format.format_optional(new.value.as_deref())),
+                    ),
+                }
+            }
+            _ => self.push(
+                path.to_string(),
+                Some(self.format.format_value(old)),
+                Some(self.format.format_value(new)),
+            ),
+        }
+    }
+
+    /// Lists are compared by index, so an insertion shows up as changes to every later item
+    fn compare_items(&mut self, path: &str, old: &ContainerValue, new: &ContainerValue) {
+        for index in 0..old.items.len().max(new.items.len()) {
+            let item_path = format!("{}[{}]", path, index);
+            match (old.items.get(index), new.items.get(index)) {
+                (Some(old), Some(new)) => self.compare_values(&item_path, old, new),
+                (old, new) => self.push(
+                    item_path,
+                    old.map(|value| self.format.format_value(value)),
+                    new.map(|value| self.format.format_value(value)),
+                ),
+            }
+        }
+    }
+
+    fn compare_entries(&mut self, path: &str, old: &MapValue, new: &MapValue) {
+        for (key, old_value) in &old.entries {
+            let entry_path = self.format.map_value_path(path, &key.0);
+            match new.entries.get(key) {
+                Some(new_value) => self.compare_values(&entry_path, old_value, new_value),
+                None => self.push(entry_path, Some(self.format.format_value(old_value)), None),
+            }
+        }
+        for (key, new_value) in &new.entries {
+            if !old.entries.contains_key(key) {
+                let entry_path = self.format.map_value_path(path, &key.0);
+                self.push(entry_path, None, Some(self.format.format_value(new_value)));
+            }
+        }
+    }
+}
+
+/// Renders names, values and property paths in `set` syntax, resolving hashes with
+/// `names` and falling back to hex. Shared by everything that reports property paths.
+pub(crate) struct ValueFormatter<'a, H> {
+    names: &'a H,
+}
+
+impl<'a, H: HashProvider> ValueFormatter<'a, H> {
+    pub(crate) fn new(names: &'a H) -> Self {
+        Self { names }
+    }
+
+    /// Entry names are quoted when they contain path separators, like `set` expects
+    pub(crate) fn entry_path(&self, hash: u32) -> String {
+        match self.names.lookup_entry(hash) {
+            Some(name) if name.contains(['.', '[']) => format!("{:?}", name),
+            Some(name) => name.to_string(),
+            None => format_hash(hash),
+        }
+    }
+
+    fn field_name(&self, hash: u32) -> String {
+        or_hex(self.names.lookup_field(hash), hash)
+    }
+
+    fn type_name(&self, hash: u32) -> String {
+        or_hex(self.names.lookup_type(hash), hash)
+    }
+
+    fn describe_object(&self, object: &BinTreeObject) -> String {
+        format!(
+            "{} ({} field(s))",
+            self.type_name(object.class_hash),
+            object.properties.len()
+        )
+    }
+
+    fn describe_struct(&self, kind_name: &str, value: &StructValue) -> String {
+        format!(
+            "{} {} ({} field(s))",
+            kind_name,
+            self.type_name(value.class_hash),
+            value.properties.len()
+        )
+    }
+
+    fn format_optional(&self, value: Option<&PropertyValueEnum>) -> String {
+        match value {
+            Some(value) => self.format_value(value),
+            None => "none".to_string(),
+        }
+    }
+
+    /// A one-line rendering of `value`: ritobin syntax for scalars, a summary for the rest
+    fn format_value(&self, value: &PropertyValueEnum) -> String {
+        match value {
+            PropertyValueEnum::None(_) => "null".to_string(),
+            PropertyValueEnum::Bool(v) => v.0.to_string(),
+            PropertyValueEnum::BitBool(v) => v.0.to_string(),
+            PropertyValueEnum::I8(v) => v.0.to_string(),
+            PropertyValueEnum::U8(v) => v.0.to_string(),
+            PropertyValueEnum::I16(v) => v.0.to_string(),
+            PropertyValueEnum::U16(v) => v.0.to_string(),
+            PropertyValueEnum::I32(v) => v.0.to_string(),
+            PropertyValueEnum::U32(v) => v.0.to_string(),
+            PropertyValueEnum::I64(v) => v.0.to_string(),
+            PropertyValueEnum::U64(v) => v.0.to_string(),
+            PropertyValueEnum::F32(v) => v.0.to_string(),
+            PropertyValueEnum::Vector2(v) => format!("{{ {}, {} }}", v.0.x, v.0.y),
+            PropertyValueEnum::Vector3(v) => format!("{{ {}, {}, {} }}", v.0.x, v.0.y, v.0.z),
+            PropertyValueEnum::Vector4(v) => {
+                format!("{{ {}, {}, {}, {} }}", v.0.x, v.0.y, v.0.z, v.0.w)
+            }
+            PropertyValueEnum::Matrix44(v) => {
+                let values: Vec<String> = v.0.to_cols_array().iter().map(f32::to_string).collect();
+                format!("{{ {} }}", values.join(", "))
+            }
+            PropertyValueEnum::Color(v) => {
+                format!("{{ {}, {}, {}, {} }}", v.0.r, v.0.g, v.0.b, v.0.a)
+            }
+            PropertyValueEnum::String(v) => format!("{:?}", v.0),
+            PropertyValueEnum::Hash(v) => quoted_or_hex(self.names.lookup_hash(v.0), v.0),
+            PropertyValueEnum::ObjectLink(v) => quoted_or_hex(self.names.lookup_entry(v.0), v.0),
+            PropertyValueEnum::WadChunkLink(v) => format_hash(v.0),
+            PropertyValueEnum::Optional(optional) => {
+                self.format_optional(optional.value.as_deref())
+            }
+            PropertyValueEnum::Struct(value) => self.describe_struct("pointer", value),
+            PropertyValueEnum::Embedded(EmbeddedValue(value)) => {
+                self.describe_struct("embed", value)
+            }
+            PropertyValueEnum::Container(container)
+            | PropertyValueEnum::UnorderedContainer(UnorderedContainerValue(container)) => {
+                format!(
+                    "{}[{}] ({} item(s))",
+                    kind_to_type_name(value.kind()),
+                    kind_to_type_name(container.item_kind),
+                    container.items.len()
+                )
+            }
+            PropertyValueEnum::Map(map) => format!(
+                "map[{},{}] ({} entries)",
+                kind_to_type_name(map.key_kind),
+                kind_to_type_name(map.value_kind),
+                map.entries.len()
+            ),
+        }
+    }
+
+    /// The path of a map value, its key in ritobin syntax in brackets, e.g. `path["key"]`
+    pub(crate) fn map_value_path(&self, path: &str, key: &PropertyValueEnum) -> String {
+        format!("{}[{}]", path, self.format_value(key))
+    }
+}
+
+fn or_hex(name: Option<&str>, hash: u32) -> String {
+    name.map(str::to_string)
+        .unwrap_or_else(|| format_hash(hash))
+}
+
+fn quoted_or_hex(name: Option<&str>, hash: u32) -> String {
+    name.map(|name| format!("{:?}", name))
+        .unwrap_or_else(|| format_hash(hash))
+}